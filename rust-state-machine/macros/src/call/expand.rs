@@ -1,4 +1,8 @@
 use super::parse::CallDef;
+use crate::metadata::{
+	expand::{field_metadata, variant_metadata},
+	parse::type_name,
+};
 use quote::quote;
 
 /// See the `fn call` docs at the `lib.rs` of this crate for a high level definition.
@@ -9,7 +13,7 @@ pub fn expand_call(def: CallDef) -> proc_macro2::TokenStream {
 	let fn_name = methods.iter().map(|method| &method.name).collect::<Vec<_>>();
 
 	// This is a nested vector of all the arguments for each of the functions in `fn_name`. It does
-	// not include the `self` or `caller: T::AccountId` parameter, which we always assume are the
+	// not include the `self` or `origin: T::RuntimeOrigin` parameter, which we always assume are the
 	// first two parameters to these calls.
 	let args_name = methods
 		.iter()
@@ -23,42 +27,116 @@ pub fn expand_call(def: CallDef) -> proc_macro2::TokenStream {
 		.map(|method| method.args.iter().map(|(_, type_)| type_.clone()).collect::<Vec<_>>())
 		.collect::<Vec<_>>();
 
+	// This is a vector of the weight expression of each function in `fn_name`.
+	let weight = methods.iter().map(|method| &method.weight).collect::<Vec<_>>();
+
+	// This is a vector of the index of each function in `fn_name`, given by its `#[call_index(n)]`
+	// attribute. It is used to encode the `Call`, so it does not depend on the order of functions.
+	let call_index = methods.iter().map(|method| method.call_index).collect::<Vec<_>>();
+
+	// This is a vector of the metadata of each function in `fn_name`, describing its call.
+	let call_metadata = methods.iter().map(|method| {
+		let fields = method
+			.args
+			.iter()
+			.map(|(name, type_)| field_metadata(&Some(name.to_string()), &type_name(type_)));
+		variant_metadata(&method.name.to_string(), method.call_index, fields, &method.docs)
+	});
+
 	// This quote block creates an `enum Call` which contains all the calls exposed by our pallet,
-	// and the `Dispatch` trait logic to route a `caller` to access those functions.
+	// and the `Dispatch` trait logic to route an `origin` to access those functions.
 	let dispatch_impl = quote! {
 		// The callable functions exposed by this pallet.
 		//
 		// The parsed function names will be `snake_case`, and that will show up in the enum.
 		#[allow(non_camel_case_types)]
+		#[derive(Debug, Clone, PartialEq)]
 		pub enum Call<T: Config> {
 			#(
 				#fn_name { #( #args_name: #args_type),* },
 			)*
 		}
 
+		// Each call is encoded as its index in the `Call` enum, followed by its arguments.
+		impl<T: Config> crate::codec::Encode for Call<T> {
+			fn encode_to(&self, dest: &mut Vec<u8>) {
+				match self {
+					#(
+						Call::#fn_name { #( #args_name ),* } => {
+							dest.push(#call_index);
+							#( crate::codec::Encode::encode_to(#args_name, dest); )*
+						},
+					)*
+				}
+			}
+		}
+
+		// A call is decoded by reading the index of its variant, followed by its arguments.
+		impl<T: Config> crate::codec::Decode for Call<T> {
+			fn decode(input: &mut &[u8]) -> Result<Self, crate::codec::Error> {
+				match <u8 as crate::codec::Decode>::decode(input)? {
+					#(
+						#call_index => Ok(Call::#fn_name {
+							#( #args_name: crate::codec::Decode::decode(input)? ),*
+						}),
+					)*
+					_ => Err(crate::codec::Error("invalid call index")),
+				}
+			}
+		}
+
+		// Each call is described by its name, its index, its arguments and its doc comments.
+		impl<T: Config> crate::metadata::EnumMetadata for Call<T> {
+			fn variants() -> Vec<crate::metadata::VariantMetadata> {
+				vec![ #( #call_metadata ),* ]
+			}
+		}
+
+		impl<T: Config> Call<T> {
+			// The weight of this call, given by the `#[weight(expr)]` attribute of its function.
+			//
+			// The arguments of the call are available by reference in the weight expression.
+			#[allow(unused_variables)]
+			pub fn weight(&self) -> crate::support::Weight {
+				match self {
+					#(
+						Call::#fn_name { #( #args_name ),* } => #weight,
+					)*
+				}
+			}
+		}
+
 		// Dispatch logic at the pallet level, mapping each of the items in the `Call` enum to the
-		// appropriate function call with all arguments, including the `caller`.
+		// appropriate function call with all arguments, including the `origin`.
+		//
+		// Note that we assume the pallet declares an `Error` enum, which every call returns. Calls
+		// returning `DispatchResultWithPostInfo` report the weight they actually consumed.
 		impl<T: Config> crate::support::Dispatch for #pallet_struct<T> {
-			type Caller = T::AccountId;
+			type Origin = T::RuntimeOrigin;
 			type Call = Call<T>;
+			type Error = Error;
 
-			fn dispatch(&mut self, caller: Self::Caller, call: Self::Call) -> crate::support::DispatchResult {
+			fn dispatch(
+				&mut self,
+				origin: Self::Origin,
+				call: Self::Call,
+			) -> crate::support::DispatchResultWithPostInfo<Self::Error> {
 				match call {
 					#(
 						Call::#fn_name { #( #args_name ),* } => {
-							self.#fn_name(
-								// Note that we assume the first argument of every call is the `caller`.
-								caller,
+							let info = self.#fn_name(
+								// Note that we assume the first argument of every call is the `origin`.
+								origin,
 								#( #args_name ),*
 							)?;
+							Ok(info.into())
 						},
 					)*
 				}
-				Ok(())
 			}
 		}
 	};
 
 	// Return the generated code.
-	dispatch_impl.into()
+	dispatch_impl
 }
@@ -4,7 +4,7 @@ use syn::spanned::Spanned;
 // Custom keywords we match to when parsing the calls in a pallet.
 mod keyword {
 	syn::custom_keyword!(T);
-	syn::custom_keyword!(AccountId);
+	syn::custom_keyword!(RuntimeOrigin);
 }
 
 /// This object will collect all the information we need to keep while parsing the callable
@@ -25,10 +25,21 @@ pub struct CallVariantDef {
 	pub name: syn::Ident,
 	/// Information on args of the function: `(name, type)`.
 	pub args: Vec<(syn::Ident, Box<syn::Type>)>,
+	/// The expression given in the `#[weight(expr)]` attribute of the function.
+	pub weight: syn::Expr,
+	/// The index given in the `#[call_index(n)]` attribute of the function, which identifies the
+	/// call in its encoding.
+	pub call_index: u8,
+	/// The lines of the doc comments on the function, kept for the metadata of the call.
+	pub docs: Vec<String>,
 }
 
 impl CallDef {
-	pub fn try_from(item: syn::Item) -> syn::Result<Self> {
+	/// Parse the callable functions in `item`.
+	///
+	/// The `#[weight(expr)]` and `#[call_index(n)]` attributes are removed from `item`, since they
+	/// are only understood by this macro.
+	pub fn try_from(item: &mut syn::Item) -> syn::Result<Self> {
 		// First we check that we are parsing an `impl`.
 		let item_impl = if let syn::Item::Impl(item) = item {
 			item
@@ -44,8 +55,8 @@ impl CallDef {
 		};
 
 		// Here is where we will store all the callable functions.
-		let mut methods = vec![];
-		for item in item_impl.items {
+		let mut methods: Vec<CallVariantDef> = vec![];
+		for item in item_impl.items.iter_mut() {
 			if let syn::ImplItem::Fn(method) = item {
 				// Here is where we will store all the args for each callable functions.
 				let mut args = vec![];
@@ -59,22 +70,38 @@ impl CallDef {
 					},
 				}
 
-				// The second argument should be the `caller: T::AccountId` argument.
-				match method.sig.inputs.iter().skip(1).next() {
+				// The second argument should be the `origin: T::RuntimeOrigin` argument.
+				match method.sig.inputs.iter().nth(1) {
 					Some(syn::FnArg::Typed(arg)) => {
 						// Here we specifically check that this argument is as we expect for
-						// `caller: T::AccountId`.
-						check_caller_arg(arg)?;
+						// `origin: T::RuntimeOrigin`.
+						check_origin_arg(arg)?;
 					},
 					_ => {
-						let msg = "Invalid call, second argument should be `caller: T::AccountId`";
+						let msg = "Invalid call, second argument should be `origin: T::RuntimeOrigin`";
 						return Err(syn::Error::new(method.sig.span(), msg))
 					},
 				}
 
 				let fn_name = method.sig.ident.clone();
+				let docs = crate::metadata::parse::docs(&method.attrs);
+
+				// Every call must declare its weight.
+				let weight = take_attr::<syn::Expr>(method, "weight", "expr")?;
+
+				// Every call must declare its index, which must be unique within the pallet.
+				let index_lit = take_attr::<syn::LitInt>(method, "call_index", "n")?;
+				let call_index = index_lit.base10_parse::<u8>()?;
+				let duplicate = methods.iter().find(|m| m.call_index == call_index);
+				if let Some(other) = duplicate {
+					let msg = format!(
+						"Invalid call, duplicate `#[call_index({})]`, already used by `{}`",
+						call_index, other.name
+					);
+					return Err(syn::Error::new(index_lit.span(), msg))
+				}
 
-				// Parsing the rest of the args. Skipping 2 for `self` and `caller`.
+				// Parsing the rest of the args. Skipping 2 for `self` and `origin`.
 				for arg in method.sig.inputs.iter().skip(2) {
 					// All arguments should be typed.
 					let arg = if let syn::FnArg::Typed(arg) = arg {
@@ -96,7 +123,7 @@ impl CallDef {
 				}
 
 				// Store all the function name and the arg data for the function.
-				methods.push(CallVariantDef { name: fn_name, args });
+				methods.push(CallVariantDef { name: fn_name, args, weight, call_index, docs });
 			}
 		}
 
@@ -105,33 +132,65 @@ impl CallDef {
 	}
 }
 
-/// Check caller arg is exactly: `caller: T::AccountId`.
+/// Remove the `#[name(arg)]` attribute from `method`, and parse its argument.
+///
+/// Every call must have exactly one such attribute. `arg` is only used in error messages.
+fn take_attr<T: syn::parse::Parse>(
+	method: &mut syn::ImplItemFn,
+	name: &str,
+	arg: &str,
+) -> syn::Result<T> {
+	let mut found = vec![];
+	let mut attrs = vec![];
+	for attr in method.attrs.drain(..) {
+		if attr.path().is_ident(name) {
+			found.push(attr);
+		} else {
+			attrs.push(attr);
+		}
+	}
+	method.attrs = attrs;
+
+	match found.as_slice() {
+		[attr] => attr.parse_args::<T>(),
+		[] => {
+			let msg = format!("Invalid call, missing `#[{}({})]` attribute", name, arg);
+			Err(syn::Error::new(method.sig.span(), msg))
+		},
+		[_, duplicate, ..] => {
+			let msg = format!("Invalid call, duplicate `#[{}({})]` attribute", name, arg);
+			Err(syn::Error::new(duplicate.span(), msg))
+		},
+	}
+}
+
+/// Check origin arg is exactly: `origin: T::RuntimeOrigin`.
 ///
 /// This is kept strict to keep the code simple.
-pub fn check_caller_arg(arg: &syn::PatType) -> syn::Result<()> {
+pub fn check_origin_arg(arg: &syn::PatType) -> syn::Result<()> {
 	pub struct CheckDispatchableFirstArg;
 	impl syn::parse::Parse for CheckDispatchableFirstArg {
 		fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
 			input.parse::<keyword::T>()?;
 			input.parse::<syn::Token![::]>()?;
-			input.parse::<keyword::AccountId>()?;
+			input.parse::<keyword::RuntimeOrigin>()?;
 			Ok(Self)
 		}
 	}
 
-	// This checks the arg name is `caller` or `_caller`.
+	// This checks the arg name is `origin` or `_origin`.
 	if let syn::Pat::Ident(ident) = &*arg.pat {
-		// We also support the name as `_caller` for when the variable is unused.
-		if &ident.ident != "caller" && &ident.ident != "_caller" {
-			let msg = "Invalid name for second parameter: expected `caller: T::AccountId`";
+		// We also support the name as `_origin` for when the variable is unused.
+		if &ident.ident != "origin" && &ident.ident != "_origin" {
+			let msg = "Invalid name for second parameter: expected `origin: T::RuntimeOrigin`";
 			return Err(syn::Error::new(ident.span(), msg))
 		}
 	}
 
-	// This checks the type is `T::AccountId` with `CheckDispatchableFirstArg`
+	// This checks the type is `T::RuntimeOrigin` with `CheckDispatchableFirstArg`
 	let ty = &arg.ty;
 	syn::parse2::<CheckDispatchableFirstArg>(ty.to_token_stream()).map_err(|e| {
-		let msg = "Invalid type for second parameter: expected `caller: T::AccountId`";
+		let msg = "Invalid type for second parameter: expected `origin: T::RuntimeOrigin`";
 		let mut err = syn::Error::new(ty.span(), msg);
 		err.combine(e);
 		err
@@ -139,3 +198,47 @@ pub fn check_caller_arg(arg: &syn::PatType) -> syn::Result<()> {
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod test {
+	use super::CallDef;
+	use proc_macro2::LineColumn;
+
+	/// Parse the calls in `source`, which must be rejected, and return the message of the error and
+	/// the line and column it points at.
+	fn parse_error(source: &str) -> (String, LineColumn) {
+		let mut item = syn::parse_str::<syn::Item>(source).unwrap();
+		let error = CallDef::try_from(&mut item).unwrap_err();
+		(error.to_string(), error.span().start())
+	}
+
+	#[test]
+	fn duplicate_call_indices_are_rejected() {
+		let source = "impl<T: Config> Pallet<T> {
+			#[call_index(0)]
+			#[weight(1)]
+			fn create(&mut self, origin: T::RuntimeOrigin) -> DispatchResult<Error> { Ok(()) }
+			#[call_index(1)]
+			#[weight(1)]
+			fn update(&mut self, origin: T::RuntimeOrigin) -> DispatchResult<Error> { Ok(()) }
+			#[call_index(0)]
+			#[weight(1)]
+			fn delete(&mut self, origin: T::RuntimeOrigin) -> DispatchResult<Error> { Ok(()) }
+		}";
+		let (message, start) = parse_error(source);
+		assert_eq!(message, "Invalid call, duplicate `#[call_index(0)]`, already used by `create`");
+		// The error points at the index of the last call.
+		assert_eq!(start, LineColumn { line: 8, column: 16 });
+
+		// A call cannot have two indices either.
+		let source = "impl<T: Config> Pallet<T> {
+			#[weight(1)]
+			#[call_index(0)]
+			#[call_index(1)]
+			fn create(&mut self, origin: T::RuntimeOrigin) -> DispatchResult<Error> { Ok(()) }
+		}";
+		let (message, start) = parse_error(source);
+		assert_eq!(message, "Invalid call, duplicate `#[call_index(n)]` attribute");
+		assert_eq!(start, LineColumn { line: 4, column: 3 });
+	}
+}
@@ -0,0 +1,129 @@
+use super::parse::{CodecDef, DataDef, FieldsDef};
+use quote::{format_ident, quote};
+
+/// See the `fn encode` docs at the `lib.rs` of this crate for a high level definition.
+pub fn expand_encode(def: CodecDef) -> proc_macro2::TokenStream {
+	let CodecDef { name, generics, data } = def;
+	let generics = with_bound(generics, quote!(crate::codec::Encode));
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+	// The fields are encoded one after the other, in the order they are declared. Enums are
+	// prefixed by the index of their variant.
+	let body = match data {
+		DataDef::Struct(fields) => {
+			let pattern = fields_pattern(&fields);
+			let names = fields_names(&fields);
+			quote! {
+				let Self #pattern = self;
+				#( crate::codec::Encode::encode_to(#names, dest); )*
+			}
+		},
+		DataDef::Enum(variants) => {
+			let arms = variants.iter().enumerate().map(|(index, (variant, fields))| {
+				let index = index as u8;
+				let pattern = fields_pattern(fields);
+				let names = fields_names(fields);
+				quote! {
+					Self::#variant #pattern => {
+						dest.push(#index);
+						#( crate::codec::Encode::encode_to(#names, dest); )*
+					}
+				}
+			});
+			quote! {
+				match self {
+					#( #arms ),*
+				}
+			}
+		},
+	};
+
+	quote! {
+		impl #impl_generics crate::codec::Encode for #name #ty_generics #where_clause {
+			// Types without any field do not use `dest`.
+			#[allow(unused_variables)]
+			fn encode_to(&self, dest: &mut Vec<u8>) {
+				#body
+			}
+		}
+	}
+}
+
+/// See the `fn decode` docs at the `lib.rs` of this crate for a high level definition.
+pub fn expand_decode(def: CodecDef) -> proc_macro2::TokenStream {
+	let CodecDef { name, generics, data } = def;
+	let generics = with_bound(generics, quote!(crate::codec::Decode));
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+	// The fields are decoded in the same order they were encoded.
+	let body = match data {
+		DataDef::Struct(fields) => {
+			let constructor = fields_constructor(&fields);
+			quote! { Ok(Self #constructor) }
+		},
+		DataDef::Enum(variants) => {
+			let arms = variants.iter().enumerate().map(|(index, (variant, fields))| {
+				let index = index as u8;
+				let constructor = fields_constructor(fields);
+				quote! { #index => Ok(Self::#variant #constructor) }
+			});
+			quote! {
+				match <u8 as crate::codec::Decode>::decode(input)? {
+					#( #arms, )*
+					_ => Err(crate::codec::Error("invalid enum variant index")),
+				}
+			}
+		},
+	};
+
+	quote! {
+		impl #impl_generics crate::codec::Decode for #name #ty_generics #where_clause {
+			// Types without any field do not use `input`.
+			#[allow(unused_variables)]
+			fn decode(input: &mut &[u8]) -> Result<Self, crate::codec::Error> {
+				#body
+			}
+		}
+	}
+}
+
+/// Add `bound` to every type parameter of `generics`.
+fn with_bound(mut generics: syn::Generics, bound: proc_macro2::TokenStream) -> syn::Generics {
+	for param in generics.type_params_mut() {
+		param.bounds.push(syn::parse_quote!(#bound));
+	}
+	generics
+}
+
+/// The names given to the fields when matching on them. Unnamed fields are called `field_{i}`.
+fn fields_names(fields: &FieldsDef) -> Vec<syn::Ident> {
+	match fields {
+		FieldsDef::Named(names) => names.clone(),
+		FieldsDef::Unnamed(count) => (0..*count).map(|i| format_ident!("field_{}", i)).collect(),
+		FieldsDef::Unit => vec![],
+	}
+}
+
+/// The pattern binding every field to its name from `fields_names`.
+fn fields_pattern(fields: &FieldsDef) -> proc_macro2::TokenStream {
+	let names = fields_names(fields);
+	match fields {
+		FieldsDef::Named(_) => quote! { { #( #names ),* } },
+		FieldsDef::Unnamed(_) => quote! { ( #( #names ),* ) },
+		FieldsDef::Unit => quote! {},
+	}
+}
+
+/// The expression building the fields, by decoding each of them from `input`.
+fn fields_constructor(fields: &FieldsDef) -> proc_macro2::TokenStream {
+	match fields {
+		FieldsDef::Named(names) => quote! {
+			{ #( #names: crate::codec::Decode::decode(input)? ),* }
+		},
+		FieldsDef::Unnamed(count) => {
+			let values = (0..*count).map(|_| quote! { crate::codec::Decode::decode(input)? });
+			quote! { ( #( #values ),* ) }
+		},
+		FieldsDef::Unit => quote! {},
+	}
+}
@@ -0,0 +1,26 @@
+pub mod expand;
+pub mod parse;
+
+/// See the `fn encode` docs at the `lib.rs` of this crate for a high level definition.
+pub fn encode(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	let input = syn::parse_macro_input!(item as syn::DeriveInput);
+
+	// First we parse the type deriving the trait...
+	match parse::CodecDef::try_from(input) {
+		// ..then we generate its implementation.
+		Ok(def) => expand::expand_encode(def).into(),
+		Err(e) => e.to_compile_error().into(),
+	}
+}
+
+/// See the `fn decode` docs at the `lib.rs` of this crate for a high level definition.
+pub fn decode(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	let input = syn::parse_macro_input!(item as syn::DeriveInput);
+
+	// First we parse the type deriving the trait...
+	match parse::CodecDef::try_from(input) {
+		// ..then we generate its implementation.
+		Ok(def) => expand::expand_decode(def).into(),
+		Err(e) => e.to_compile_error().into(),
+	}
+}
@@ -0,0 +1,73 @@
+use syn::spanned::Spanned;
+
+/// This object will collect all the information we need to keep while parsing a type deriving
+/// `Encode` or `Decode`.
+#[derive(Debug)]
+pub struct CodecDef {
+	/// The name of the type.
+	pub name: syn::Ident,
+	/// The generics of the type. Every type parameter must implement the derived trait.
+	pub generics: syn::Generics,
+	/// The shape of the data of the type. See `DataDef`.
+	pub data: DataDef,
+}
+
+/// The shape of the data of a type.
+#[derive(Debug)]
+pub enum DataDef {
+	/// A struct, with its fields.
+	Struct(FieldsDef),
+	/// An enum, with the name and the fields of each of its variants. Each variant is identified
+	/// by its position in the enum.
+	Enum(Vec<(syn::Ident, FieldsDef)>),
+}
+
+/// The fields of a struct or of an enum variant.
+#[derive(Debug)]
+pub enum FieldsDef {
+	/// Fields with a name, like `struct Foo { a: u32, b: u32 }`.
+	Named(Vec<syn::Ident>),
+	/// Fields without a name, like `struct Foo(u32, u32)`. We only keep the number of fields.
+	Unnamed(usize),
+	/// No fields at all, like `struct Foo;`.
+	Unit,
+}
+
+impl CodecDef {
+	pub fn try_from(input: syn::DeriveInput) -> syn::Result<Self> {
+		let data = match input.data {
+			syn::Data::Struct(data) => DataDef::Struct(FieldsDef::from(data.fields)),
+			syn::Data::Enum(data) => {
+				// The index of each variant is encoded as a `u8`.
+				if data.variants.len() > 256 {
+					let msg = "Invalid codec, enums can have at most 256 variants";
+					return Err(syn::Error::new(input.ident.span(), msg))
+				}
+				let variants = data
+					.variants
+					.into_iter()
+					.map(|variant| (variant.ident, FieldsDef::from(variant.fields)))
+					.collect();
+				DataDef::Enum(variants)
+			},
+			syn::Data::Union(data) => {
+				let msg = "Invalid codec, unions are not supported";
+				return Err(syn::Error::new(data.union_token.span(), msg))
+			},
+		};
+
+		Ok(Self { name: input.ident, generics: input.generics, data })
+	}
+}
+
+impl From<syn::Fields> for FieldsDef {
+	fn from(fields: syn::Fields) -> Self {
+		match fields {
+			syn::Fields::Named(fields) => FieldsDef::Named(
+				fields.named.into_iter().filter_map(|field| field.ident).collect(),
+			),
+			syn::Fields::Unnamed(fields) => FieldsDef::Unnamed(fields.unnamed.len()),
+			syn::Fields::Unit => FieldsDef::Unit,
+		}
+	}
+}
@@ -1,6 +1,34 @@
 mod call;
+mod codec;
+mod metadata;
 mod runtime;
+mod storage;
 
+/// Expand the callable functions of a pallet.
+///
+/// This is placed on an `impl Pallet<T>` block, where every function is a call which can be made
+/// by a user. The first argument of each function must be `&mut self`, the second must be
+/// `origin: T::RuntimeOrigin`, and the function must return `DispatchResult<Error>`, where `Error`
+/// is the error enum declared by the pallet. A function can instead return
+/// `DispatchResultWithPostInfo<Error>`, to report that it used less than its declared weight.
+///
+/// Every function must also declare its weight with a `#[weight(expr)]` attribute. The expression
+/// must evaluate to a `support::Weight`, and can use the arguments of the call by reference.
+///
+/// Every function must also declare the index of its call with a `#[call_index(n)]` attribute,
+/// where `n` is a `u8` unique within the pallet. The index is used in the encoding of the call, so
+/// functions can be reordered or added without changing the encoding of existing calls.
+///
+/// This generates:
+/// - `enum Call` - an enum with a variant for every callable function, containing its arguments.
+/// - `fn weight()` on `Call` - which returns the weight declared for the call.
+/// - implements the traits `codec::Encode` and `codec::Decode` for `Call`, encoding the call index
+///   of the variant followed by its arguments.
+/// - implements the trait `metadata::EnumMetadata` for `Call`, describing each call with its name,
+///   its call index, the names and types of its arguments, and the doc comments of its function.
+/// - implements the trait `support::Dispatch` to route each `Call` variant to its function, along
+///   with the origin of the call. Functions check the origin themselves, with guards like
+///   `system::ensure_signed` and `system::ensure_root`.
 #[proc_macro_attribute]
 pub fn call(
 	attr: proc_macro::TokenStream,
@@ -11,20 +39,86 @@ pub fn call(
 
 /// Expand the `Runtime` definition.
 ///
+/// The first field of the `Runtime` struct must be the `system` pallet. Every other pallet must
+/// declare its index in the runtime with a `#[pallet_index(n)]` attribute, where `n` is a `u8`
+/// unique within the runtime. The index 0 is reserved for the system pallet.
+///
 /// This generates function implementations on `Runtime`:
 /// - `fn new()` - which generates a new instance of the runtime, by instantiating all the pallets
 ///   included in the runtime.
-/// - `fn execute_block()` - which handles basic logic for executing a block of extrinsics. It does
-///   basic actions like incrementing the block number and checking the block to be executed has a
-///   valid block number.
+/// - `fn initialize_block()` - which increments the block number and checks that a header builds on
+///   top of the last executed block, then calls the `on_initialize` hook of every pallet.
+/// - `fn finalize_block()` - which calls the `on_finalize` hook of every pallet, after the
+///   extrinsics of the block were applied.
+/// - `fn apply_extrinsic()` - which applies a single extrinsic of the current block. The signature
+///   of the extrinsic is checked against the `support::SigningContext` of the chain, which holds
+///   its genesis hash and runtime version, and its nonce must be the next nonce of the signer. The
+///   signer is converted into the `system::Config::AccountId` of the runtime. Then its call
+///   is dispatched on behalf of the account of the signer in its own transaction, which is reverted
+///   if the dispatch fails. The fee paid for weight the call did not use is then refunded. It
+///   returns a receipt with the result of the call, its events and the weight it consumed.
+/// - `fn apply_trusted_extrinsic()` - only available in tests, which dispatches a call on behalf of
+///   any origin without checking a signature or charging a fee.
+/// - `fn storage_pairs()` - which collects the storage of every pallet as `(key, value)` pairs,
+///   where each key is prefixed by the name of the pallet and of the storage item. Pallets must
+///   implement `storage::PalletStorage`, usually with `#[macros::storage]`.
+/// - `fn state_root()` - which computes the root of a Merkle tree over `storage_pairs()`.
+/// - `fn prove_storage()` - which creates a proof of the value stored at some key, or of its
+///   absence, which can be checked against the state root with `merkle::verify_storage_proof`.
+/// - `fn committed_state()` - which returns the hash of the last executed block and the
+///   `storage_pairs()`, to commit them to a `backend::Backend` after executing a block.
+/// - `fn restore()` - which rebuilds the runtime from a state committed to a backend, so it can
+///   execute the next block. If the state was used by an older `system::Config::VERSION` of the
+///   runtime, the `support::OnRuntimeUpgrade` migrations of every pallet are run on its pairs
+///   first. It fails if the state belongs to another runtime or to a newer version, if a value does
+///   not decode, or if a key belongs to no pallet.
+/// - `fn metadata()` - which describes every pallet except system, with its name, its index, and
+///   the metadata of its calls, events and errors. The `Event` and `Error` enums of each pallet
+///   must implement `metadata::EnumMetadata`, usually with `#[derive(macros::EnumMetadata)]`.
+/// - `fn execute_block()` - which handles basic logic for executing a block of extrinsics, using the
+///   functions above. It checks the block number, parent hash, extrinsics root and state root of
+///   the block header, the signature and nonce of every extrinsic, and that the total weight of the
+///   calls does not exceed `system::Config::MAX_BLOCK_WEIGHT`. The signer of every extrinsic must
+///   be able to pay its fee, which is withdrawn before dispatching its call through the
+///   `support::ChargeTransaction` trait, which the runtime must implement. It rejects the block
+///   without changing any state if one check fails. Otherwise, it returns a `support::BlockReceipt`
+///   with the receipt of every extrinsic, so failed calls are reported instead of printed.
+/// - implements the trait `storage::Transactional`, opening, committing and reverting a
+///   transaction over the storage of every pallet, including system. Pallets must implement
+///   `storage::Transactional`, usually with `#[macros::storage]`.
+/// - implements the trait `transaction_pool::TransactionValidator`, which checks the signature,
+///   nonce, weight and fee of an extrinsic against the current state before it enters the
+///   transaction pool. The nonce may be ahead of the next nonce of the signer, and the priority of
+///   the extrinsic is its fee. The runtime must implement `support::ChargeTransaction`.
+///
+/// This generates `enum RuntimeError`, an "outer"-enum wrapping the `Error` enum of every pallet,
+/// and `enum RuntimeEvent`, an "outer"-enum wrapping the `Event` enum of every pallet. After each
+/// extrinsic and after the hooks, the events taken from every pallet are deposited in the system
+/// pallet along with the `support::Phase` of the block which emitted them. The system pallet keeps
+/// them until the next block starts.
+///
+/// Every pallet, including system, must implement `support::Hooks`. The hooks are called in the
+/// order the pallets are declared, and their weight is always counted against the block. Every
+/// pallet except system must also implement `support::OnRuntimeUpgrade`, which migrates its
+/// storage when the runtime is upgraded.
+///
+/// This generates `struct RuntimeGenesisConfig`, with a field for the `GenesisConfig` of every
+/// pallet, including system. It can be serialized and deserialized, for example from a chain spec
+/// file, and its `fn build()` creates a new runtime starting from that initial state, whose state
+/// root becomes the genesis hash of the chain. Every pallet must declare a `GenesisConfig<T>` with
+/// `fn build(&self, pallet: &mut Pallet<T>)`.
 ///
 /// This also generates code needed for dispatching calls to the pallets:
 /// - Note: For simplicity, we assume that the system pallet is not callable.
 /// - `enum RuntimeCall` - an "outer"-enum representing the accumulation of all possible calls to
-///   all pallets. The system pallet is not included.
-/// - implements the trait `support::Dispatch` to dispatch calls to the appropriate pallet. Basic
-///   logic like incrementing the nonce of the user is included in the generated code. The system
-///   pallet is not included.
+///   all pallets. The system pallet is not included. It implements `codec::Encode` and
+///   `codec::Decode`, encoding the pallet index followed by the pallet level call, and has a
+///   `fn weight()` returning the weight of the pallet level call.
+/// - implements the trait `support::Dispatch` to dispatch calls from a
+///   `system::Config::RuntimeOrigin` to the appropriate pallet. Extrinsics are dispatched from the
+///   signed origin of their signer, and basic logic like incrementing the nonce of the signer is
+///   included in the generated code. The system pallet is not included. Pallet errors are wrapped
+///   into a `support::DispatchError` along with the index of the pallet in the runtime.
 #[proc_macro_attribute]
 pub fn runtime(
 	attr: proc_macro::TokenStream,
@@ -32,3 +126,67 @@ pub fn runtime(
 ) -> proc_macro::TokenStream {
 	runtime::runtime(attr, item)
 }
+
+/// Declare the storage of a pallet.
+///
+/// This is placed on the `Pallet` struct. Every field whose type is a `storage::StorageValue<V>`,
+/// a `storage::StorageMap<K, V>` or a `storage::StorageDoubleMap<K1, K2, V>` is a storage item of
+/// the pallet, named after the field. Other fields are kept as is.
+///
+/// Storage items hold the `Default` of their value type when nothing was stored. A storage item can
+/// declare another default value with a `#[default(expr)]` attribute, and a getter on the pallet
+/// with a `#[getter(name)]` attribute, which returns the stored value or the default value.
+///
+/// This generates:
+/// - `fn name()` on the pallet for every `#[getter(name)]` attribute, taking the keys of the
+///   storage item by reference.
+/// - a type implementing `storage::Get` for every `#[default(expr)]` attribute, which is added as
+///   the last generic argument of the type of the storage item.
+/// - implements the trait `storage::Transactional` for the pallet, opening, committing and
+///   reverting a transaction over every storage item, and over the other fields marked with
+///   `#[transactional]`, like the queue of events of the pallet.
+/// - implements the trait `storage::PalletStorage` for the pallet, where the keys of each storage
+///   item are prefixed by the name of the pallet and the name of the item. The storage items can
+///   be restored from these `(key, value)` pairs, so their keys and values must implement
+///   `codec::Decode` as well as `codec::Encode`.
+///
+/// The layout of the storage can be versioned with `#[macros::storage(version = n)]`, where `n` is a
+/// `storage::StorageVersion`. Pallets without a version are at version 0. The version is stored
+/// along with the storage items, unless it is 0, and the storage items are only restored from
+/// pairs at the same version. Older pairs must first be migrated with `support::OnRuntimeUpgrade`.
+#[proc_macro_attribute]
+pub fn storage(
+	attr: proc_macro::TokenStream,
+	item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+	storage::storage(attr, item)
+}
+
+/// Derive the `codec::Encode` trait for a struct or an enum.
+///
+/// The fields are encoded one after the other, in the order they are declared. An enum is encoded
+/// as the index of its variant as a `u8`, followed by the fields of the variant. Every type
+/// parameter of the type must implement `codec::Encode`.
+#[proc_macro_derive(Encode)]
+pub fn encode(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	codec::encode(item)
+}
+
+/// Derive the `codec::Decode` trait for a struct or an enum.
+///
+/// This decodes the encoding produced by `#[derive(Encode)]`, and returns a `codec::Error` for an
+/// unknown variant index. Every type parameter of the type must implement `codec::Decode`.
+#[proc_macro_derive(Decode)]
+pub fn decode(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	codec::decode(item)
+}
+
+/// Derive the `metadata::EnumMetadata` trait for an enum.
+///
+/// Each variant is described with its name, its position in the enum as its index, the names and
+/// types of its fields, and its doc comments. This is used to describe the `Event` and `Error`
+/// enums of a pallet in the metadata of the runtime.
+#[proc_macro_derive(EnumMetadata)]
+pub fn enum_metadata(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	metadata::enum_metadata(item)
+}
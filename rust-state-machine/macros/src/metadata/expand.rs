@@ -0,0 +1,51 @@
+use super::parse::EnumMetadataDef;
+use quote::quote;
+
+/// See the `fn enum_metadata` docs at the `lib.rs` of this crate for a high level definition.
+pub fn expand_enum_metadata(def: EnumMetadataDef) -> proc_macro2::TokenStream {
+	let EnumMetadataDef { name, generics, variants } = def;
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+	// Each variant is described along with its position in the enum, which is its index.
+	let variants = variants.iter().enumerate().map(|(index, variant)| {
+		let index = index as u8;
+		let fields = variant.fields.iter().map(|(name, type_name)| field_metadata(name, type_name));
+		variant_metadata(&variant.name.to_string(), index, fields, &variant.docs)
+	});
+
+	quote! {
+		impl #impl_generics crate::metadata::EnumMetadata for #name #ty_generics #where_clause {
+			fn variants() -> Vec<crate::metadata::VariantMetadata> {
+				vec![ #( #variants ),* ]
+			}
+		}
+	}
+}
+
+/// Generate the expression building the `metadata::VariantMetadata` of a variant.
+pub fn variant_metadata(
+	name: &str,
+	index: u8,
+	fields: impl Iterator<Item = proc_macro2::TokenStream>,
+	docs: &[String],
+) -> proc_macro2::TokenStream {
+	quote! {
+		crate::metadata::VariantMetadata {
+			name: #name,
+			index: #index,
+			fields: vec![ #( #fields ),* ],
+			docs: vec![ #( #docs ),* ],
+		}
+	}
+}
+
+/// Generate the expression building the `metadata::FieldMetadata` of a field.
+pub fn field_metadata(name: &Option<String>, type_name: &str) -> proc_macro2::TokenStream {
+	let name = match name {
+		Some(name) => quote!(Some(#name)),
+		None => quote!(None),
+	};
+	quote! {
+		crate::metadata::FieldMetadata { name: #name, type_name: #type_name }
+	}
+}
@@ -0,0 +1,14 @@
+pub mod expand;
+pub mod parse;
+
+/// See the `fn enum_metadata` docs at the `lib.rs` of this crate for a high level definition.
+pub fn enum_metadata(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	let input = syn::parse_macro_input!(item as syn::DeriveInput);
+
+	// First we parse the enum deriving the trait...
+	match parse::EnumMetadataDef::try_from(input) {
+		// ..then we generate its implementation.
+		Ok(def) => expand::expand_enum_metadata(def).into(),
+		Err(e) => e.to_compile_error().into(),
+	}
+}
@@ -0,0 +1,102 @@
+use quote::ToTokens;
+
+/// This object will collect all the information we need to keep while parsing an enum deriving
+/// `EnumMetadata`.
+#[derive(Debug)]
+pub struct EnumMetadataDef {
+	/// The name of the enum.
+	pub name: syn::Ident,
+	/// The generics of the enum. The type parameters do not need any bound, since only their names
+	/// are described.
+	pub generics: syn::Generics,
+	/// The variants of the enum, in the order they are declared. See `VariantDef`.
+	pub variants: Vec<VariantDef>,
+}
+
+/// This is the metadata we keep about each variant of the enum.
+#[derive(Debug)]
+pub struct VariantDef {
+	/// The name of the variant.
+	pub name: syn::Ident,
+	/// Information on the fields of the variant: `(name, type name)`. The name is `None` for the
+	/// fields of a tuple variant.
+	pub fields: Vec<(Option<String>, String)>,
+	/// The lines of the doc comments on the variant.
+	pub docs: Vec<String>,
+}
+
+impl EnumMetadataDef {
+	pub fn try_from(input: syn::DeriveInput) -> syn::Result<Self> {
+		let data = match input.data {
+			syn::Data::Enum(data) => data,
+			_ => {
+				let msg = "Invalid enum metadata, expected an enum";
+				return Err(syn::Error::new(input.ident.span(), msg))
+			},
+		};
+
+		// The index of each variant is its position, which must fit in a `u8`.
+		if data.variants.len() > 256 {
+			let msg = "Invalid enum metadata, enums can have at most 256 variants";
+			return Err(syn::Error::new(input.ident.span(), msg))
+		}
+
+		let variants = data
+			.variants
+			.into_iter()
+			.map(|variant| VariantDef {
+				docs: docs(&variant.attrs),
+				fields: variant
+					.fields
+					.iter()
+					.map(|field| (field.ident.as_ref().map(ToString::to_string), type_name(&field.ty)))
+					.collect(),
+				name: variant.ident,
+			})
+			.collect();
+
+		Ok(Self { name: input.ident, generics: input.generics, variants })
+	}
+}
+
+/// Collect the lines of the doc comments in `attrs`.
+///
+/// Doc comments are turned into `#[doc = "..."]` attributes by the compiler. We remove the space
+/// which usually follows `///`.
+pub fn docs(attrs: &[syn::Attribute]) -> Vec<String> {
+	attrs
+		.iter()
+		.filter(|attr| attr.path().is_ident("doc"))
+		.filter_map(|attr| match &attr.meta {
+			syn::Meta::NameValue(syn::MetaNameValue {
+				value: syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(doc), .. }),
+				..
+			}) => Some(doc.value()),
+			_ => None,
+		})
+		.map(|doc| doc.strip_prefix(' ').map(str::to_string).unwrap_or(doc).trim_end().to_string())
+		.collect()
+}
+
+/// The name of `ty`, as it would be written in the source code.
+///
+/// The tokens of the type are printed with spaces between all of them, like `Vec < T >`, so we
+/// only keep the spaces after a comma, or between two words like in `&'a mut T`.
+pub fn type_name(ty: &syn::Type) -> String {
+	let tokens = ty.to_token_stream().to_string();
+	let is_word = |c: char| c.is_alphanumeric() || c == '_';
+	let chars = tokens.chars().collect::<Vec<_>>();
+	let mut name = String::new();
+	for (i, &c) in chars.iter().enumerate() {
+		if c == ' ' {
+			let prev = name.chars().last().unwrap_or(' ');
+			let next = chars.get(i + 1).copied().unwrap_or(' ');
+			if !(prev == ',' || is_word(prev) && is_word(next)) {
+				continue
+			}
+		}
+		name.push(c);
+	}
+	name
+}
+
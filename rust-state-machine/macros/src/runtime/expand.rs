@@ -6,9 +6,13 @@ pub fn expand_runtime(def: RuntimeDef) -> proc_macro2::TokenStream {
 	let RuntimeDef { runtime_struct, pallets } = def;
 
 	// This is a vector of all the pallet names, not including system.
-	let pallet_names = pallets.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+	let pallet_names = pallets.iter().map(|pallet| pallet.name.clone()).collect::<Vec<_>>();
 	// This is a vector of all the pallet types, not including system.
-	let pallet_types = pallets.iter().map(|(_, type_)| type_.clone()).collect::<Vec<_>>();
+	let pallet_types = pallets.iter().map(|pallet| pallet.ty.clone()).collect::<Vec<_>>();
+	// This is a vector of the index of each pallet in the runtime, given by its
+	// `#[pallet_index(n)]` attribute. It is used to encode the `RuntimeCall`, and to identify
+	// which pallet returned an error. The system pallet always has the index 0.
+	let pallet_indices = pallets.iter().map(|pallet| pallet.index).collect::<Vec<_>>();
 
 	// This quote block implements functions on the `Runtime` struct.
 	let runtime_impl = quote! {
@@ -24,23 +28,390 @@ pub fn expand_runtime(def: RuntimeDef) -> proc_macro2::TokenStream {
 				}
 			}
 
-			// Execute a block of extrinsics. Increments the block number.
-			fn execute_block(&mut self, block: types::Block) -> crate::support::DispatchResult {
+			// Start executing a new block. Increments the block number, and checks that `header`
+			// builds on top of the last executed block.
+			//
+			// Then the `on_initialize` hook of every pallet is called, in the order they are
+			// declared, and their weight is counted against the block. The hook of the system
+			// pallet clears the events and the weight of the previous block.
+			fn initialize_block(&mut self, header: &types::Header) -> Result<(), crate::support::BlockError> {
+				use crate::support::Hooks;
 				self.system.inc_block_number();
-				if block.header.block_number != self.system.block_number() {
-					return Err(&"block number does not match what is expected")
-				}
-				for (i, support::Extrinsic { caller, call }) in block.extrinsics.into_iter().enumerate() {
-					self.system.inc_nonce(&caller);
-					let _res = self.dispatch(caller, call).map_err(|e| {
-						eprintln!(
-							"Extrinsic Error\n\tBlock Number: {}\n\tExtrinsic Number: {}\n\tError: {}",
-							block.header.block_number, i, e
-						)
-					});
+				if header.block_number != self.system.block_number() {
+					return Err(crate::support::BlockError::WrongBlockNumber)
 				}
+				if header.parent_hash != self.system.parent_hash() {
+					return Err(crate::support::BlockError::UnknownParent)
+				}
+				let block_number = self.system.block_number();
+				let weight = self.system.on_initialize(block_number);
+				self.system.register_extra_weight(weight);
+				#(
+					let weight = self.#pallet_names.on_initialize(block_number);
+					self.system.register_extra_weight(weight);
+				)*
+				self.collect_events(crate::support::Phase::Initialization);
 				Ok(())
 			}
+
+			// Finish executing the current block, after all its extrinsics were applied.
+			//
+			// The `on_finalize` hook of every pallet is called, in the order they are declared,
+			// and their weight is counted against the block.
+			fn finalize_block(&mut self) {
+				use crate::support::Hooks;
+				let block_number = self.system.block_number();
+				let weight = self.system.on_finalize(block_number);
+				self.system.register_extra_weight(weight);
+				#(
+					let weight = self.#pallet_names.on_finalize(block_number);
+					self.system.register_extra_weight(weight);
+				)*
+				self.collect_events(crate::support::Phase::Finalization);
+			}
+
+			// Apply a single extrinsic, which is the `extrinsic_index`-th of the current block.
+			//
+			// The signature and the nonce of the extrinsic are checked before dispatching its call
+			// on behalf of the account of the signer. The nonce must be the number of extrinsics
+			// already applied for that account, so each extrinsic can only be included once.
+			//
+			// The weight of the call is consumed even if the dispatch fails, and the block is
+			// rejected if it exceeds the maximum block weight. The fee of the extrinsic is withdrawn
+			// from the signer before the dispatch, and the part of it paid for weight the call did
+			// not use is refunded afterwards.
+			//
+			// An extrinsic which is rejected leaves the state unchanged, so a block builder can skip
+			// it and try the next one. Otherwise, the returned receipt tells whether its call
+			// succeeded, along with the events it emitted and the weight it consumed.
+			fn apply_extrinsic(
+				&mut self,
+				extrinsic_index: u32,
+				extrinsic: types::Extrinsic,
+			) -> Result<types::ExtrinsicReceipt, crate::support::BlockError> {
+				use crate::support::ChargeTransaction;
+				if !extrinsic.verify_signature(&self.system.signing_context()) {
+					return Err(crate::support::BlockError::BadSignature)
+				}
+				let caller: <Self as system::Config>::AccountId = extrinsic.signer.into();
+				match extrinsic.nonce.cmp(&self.system.nonce(&caller)) {
+					core::cmp::Ordering::Less => return Err(crate::support::BlockError::StaleNonce),
+					core::cmp::Ordering::Greater => return Err(crate::support::BlockError::FutureNonce),
+					core::cmp::Ordering::Equal => {},
+				}
+				let weight = extrinsic.call.weight();
+				let len = crate::codec::Encode::encode(&extrinsic).len();
+				let fee = Self::compute_fee(len, weight);
+				if !self.can_pay_fee(&caller, fee) {
+					return Err(crate::support::BlockError::CannotPayFees)
+				}
+				if !self.system.consume_weight(weight) {
+					return Err(crate::support::BlockError::ExhaustsResources)
+				}
+				let withdrawn = self.withdraw_fee(&caller, fee);
+				debug_assert!(withdrawn, "the signer can pay the fee");
+				let origin = system::RawOrigin::Signed(caller.clone()).into();
+				let result = self.apply_call(extrinsic_index, origin, extrinsic.call);
+				let actual_weight = result
+					.as_ref()
+					.ok()
+					.and_then(|info| info.actual_weight)
+					.map_or(weight, |actual| actual.min(weight));
+				self.settle_fee(&caller, fee, Self::compute_fee(len, actual_weight));
+				let phase = crate::support::Phase::ApplyExtrinsic(extrinsic_index);
+				Ok(crate::support::ExtrinsicReceipt {
+					result: result.map(|_| ()),
+					events: self
+						.system
+						.events()
+						.iter()
+						.filter(|record| record.phase == phase)
+						.map(|record| record.event.clone())
+						.collect(),
+					weight: actual_weight,
+				})
+			}
+
+			// Apply a call from `origin`, as the `extrinsic_index`-th extrinsic of the current
+			// block, without checking any signature or charging any fee.
+			//
+			// This is only available in tests, where a call can be trusted to come from `origin`,
+			// including the root or no origin at all.
+			#[cfg(test)]
+			fn apply_trusted_extrinsic(
+				&mut self,
+				extrinsic_index: u32,
+				origin: <#runtime_struct as system::Config>::RuntimeOrigin,
+				call: RuntimeCall,
+			) -> crate::support::DispatchResult<crate::support::DispatchError<RuntimeError>> {
+				self.apply_call(extrinsic_index, origin, call).map(|_| ())
+			}
+
+			// Dispatch `call` from `origin`, whose signature has already been checked. The nonce of
+			// a signed origin is incremented.
+			//
+			// Returns the result of the dispatch, with the information reported by the call.
+			fn apply_call(
+				&mut self,
+				extrinsic_index: u32,
+				origin: <#runtime_struct as system::Config>::RuntimeOrigin,
+				call: RuntimeCall,
+			) -> crate::support::DispatchResultWithPostInfo<
+				crate::support::DispatchError<RuntimeError>,
+			> {
+				let origin: system::RawOrigin<<Self as system::Config>::AccountId> = origin.into();
+				if let system::RawOrigin::Signed(who) = &origin {
+					self.system.inc_nonce(who);
+				}
+				// Each extrinsic is dispatched in its own transaction, so a failed extrinsic
+				// does not leave any partial changes behind in the storage of any pallet.
+				let result = crate::storage::Transactional::with_transaction(self, |runtime| {
+					runtime.dispatch(origin.into(), call)
+				});
+				self.collect_events(crate::support::Phase::ApplyExtrinsic(extrinsic_index));
+				result
+			}
+
+			// Collect the `(key, value)` pairs in the storage of every pallet, ordered by key.
+			fn storage_pairs(&self) -> crate::storage::StoragePairs {
+				use crate::storage::PalletStorage;
+				let mut pairs = Vec::new();
+				self.system.storage_pairs("system", &mut pairs);
+				#( self.#pallet_names.storage_pairs(stringify!(#pallet_names), &mut pairs); )*
+				pairs.sort();
+				pairs
+			}
+
+			// Compute the state root: the root of a Merkle tree over the storage of every pallet.
+			fn state_root(&self) -> crate::support::Hash {
+				crate::merkle::storage_root(&self.storage_pairs())
+			}
+
+			// Create a proof of the value stored at `key` in the current state, which can be
+			// checked against the state root of the last executed block.
+			fn prove_storage(&self, key: &[u8]) -> crate::merkle::StorageProof {
+				crate::merkle::prove_storage(&self.storage_pairs(), key)
+			}
+
+			// The state to commit to a storage backend, after executing a block.
+			fn committed_state(&self) -> crate::backend::CommittedState {
+				crate::backend::CommittedState {
+					block_hash: self.system.parent_hash(),
+					pairs: self.storage_pairs(),
+				}
+			}
+
+			// Restore the runtime from a state committed to a storage backend, so it can execute
+			// the block following the committed one.
+			//
+			// If the state was used by an older version of the runtime, the migrations of every
+			// pallet are run on it first, and the state then records the current version.
+			//
+			// Fails if the state belongs to another runtime or to a newer version of this one, if a
+			// value does not decode, or if a key belongs to no storage item.
+			fn restore(
+				state: &crate::backend::CommittedState,
+			) -> Result<Self, crate::codec::Error> {
+				use crate::storage::PalletStorage;
+				let version = <Self as system::Config>::VERSION;
+				let mut runtime = Self::new();
+				runtime.system.load_storage_pairs("system", &state.pairs)?;
+				let (spec_name, spec_version) = runtime.system.last_runtime_upgrade();
+				if !spec_name.is_empty() && spec_name != version.spec_name {
+					return Err(crate::codec::Error("the state belongs to another runtime"));
+				}
+				if spec_version > version.spec_version {
+					return Err(crate::codec::Error("the state belongs to a newer runtime"));
+				}
+
+				let mut pairs = state.pairs.clone();
+				let upgraded = spec_version < version.spec_version;
+				if upgraded {
+					#(
+						<#pallet_types as crate::support::OnRuntimeUpgrade>::on_runtime_upgrade(
+							stringify!(#pallet_names),
+							&mut pairs,
+						)?;
+					)*
+					pairs.sort();
+				}
+				#(
+					runtime.#pallet_names.load_storage_pairs(stringify!(#pallet_names), &pairs)?;
+				)*
+				// Every pair must belong to a storage item. The storage values missing from the
+				// state, like the ones added by a newer runtime, keep their default value.
+				let restored = runtime.storage_pairs();
+				if pairs.iter().any(|pair| restored.binary_search(pair).is_err()) {
+					return Err(crate::codec::Error("unknown keys in the state"));
+				}
+				if upgraded {
+					runtime.system.set_last_runtime_upgrade(version);
+				}
+				runtime.system.set_parent_hash(state.block_hash);
+				Ok(runtime)
+			}
+
+			// Execute a block of extrinsics. Increments the block number.
+			//
+			// The block is rejected if its header does not build on the last executed block, or if
+			// its roots do not match its extrinsics and the state after executing them. In that
+			// case, none of the changes made by the block are kept. Otherwise, the returned receipt
+			// tells the outcome of every extrinsic of the block.
+			fn execute_block(
+				&mut self,
+				block: types::Block,
+			) -> Result<types::BlockReceipt, crate::support::BlockError> {
+				let block_hash = block.header.hash();
+				let result = crate::storage::Transactional::with_transaction(self, |runtime| {
+					runtime.initialize_block(&block.header)?;
+					if block.header.extrinsics_root != crate::support::extrinsics_root(&block.extrinsics) {
+						return Err(crate::support::BlockError::ExtrinsicsRootMismatch)
+					}
+					let mut extrinsics = Vec::new();
+					for (i, extrinsic) in block.extrinsics.into_iter().enumerate() {
+						extrinsics.push(runtime.apply_extrinsic(i as u32, extrinsic)?);
+					}
+					runtime.finalize_block();
+					if block.header.state_root != runtime.state_root() {
+						return Err(crate::support::BlockError::StateRootMismatch)
+					}
+					Ok(crate::support::BlockReceipt { block_hash, extrinsics })
+				});
+				if result.is_ok() {
+					self.system.set_parent_hash(block_hash);
+				}
+				result
+			}
+
+			// Describe the pallets of the runtime, with their calls, events and errors.
+			pub fn metadata() -> crate::metadata::RuntimeMetadata {
+				use crate::metadata::EnumMetadata;
+				crate::metadata::RuntimeMetadata {
+					pallets: vec![
+						#(
+							crate::metadata::PalletMetadata {
+								name: stringify!(#pallet_names),
+								index: #pallet_indices,
+								calls: #pallet_names::Call::<#runtime_struct>::variants(),
+								events: #pallet_names::Event::<#runtime_struct>::variants(),
+								errors: #pallet_names::Error::variants(),
+							}
+						),*
+					],
+				}
+			}
+
+			// Move the events emitted by every pallet into the system pallet, recording that they
+			// were emitted during `phase` of the current block.
+			fn collect_events(&mut self, phase: crate::support::Phase) {
+				#(
+					for event in self.#pallet_names.take_events() {
+						self.system.deposit_event(phase, RuntimeEvent::#pallet_names(event));
+					}
+				)*
+			}
+		}
+	};
+
+	// This quote block implements the `Transactional` trait on the `Runtime` struct, so that a
+	// transaction spans the storage of every pallet.
+	let transactional_impl = quote! {
+		impl crate::storage::Transactional for #runtime_struct {
+			fn start_transaction(&mut self) {
+				self.system.start_transaction();
+				#( self.#pallet_names.start_transaction(); )*
+			}
+
+			fn commit_transaction(&mut self) {
+				self.system.commit_transaction();
+				#( self.#pallet_names.commit_transaction(); )*
+			}
+
+			fn rollback_transaction(&mut self) {
+				self.system.rollback_transaction();
+				#( self.#pallet_names.rollback_transaction(); )*
+			}
+		}
+	};
+
+	// This quote block implements the `TransactionValidator` trait on the `Runtime` struct, so that
+	// extrinsics can be checked before being added to the transaction pool.
+	let validator_impl = quote! {
+		impl crate::transaction_pool::TransactionValidator for #runtime_struct {
+			type Extrinsic = types::Extrinsic;
+			type AccountId = <Self as system::Config>::AccountId;
+			type Nonce = types::Nonce;
+			type Priority = <Self as crate::support::ChargeTransaction>::Balance;
+
+			// Run the checks of `apply_extrinsic` which do not depend on the other extrinsics of
+			// the block, except that the nonce may be ahead of the next nonce of the signer. The
+			// priority of the extrinsic is its fee.
+			fn validate_transaction(
+				&self,
+				extrinsic: &types::Extrinsic,
+			) -> Result<
+				crate::transaction_pool::ValidTransactionOf<Self>,
+				crate::support::BlockError,
+			> {
+				use crate::support::ChargeTransaction;
+				if !extrinsic.verify_signature(&self.system.signing_context()) {
+					return Err(crate::support::BlockError::BadSignature)
+				}
+				let signer: <Self as system::Config>::AccountId = extrinsic.signer.into();
+				let account_nonce = self.system.nonce(&signer);
+				if extrinsic.nonce < account_nonce {
+					return Err(crate::support::BlockError::StaleNonce)
+				}
+				let weight = extrinsic.call.weight();
+				if weight > <Self as system::Config>::MAX_BLOCK_WEIGHT {
+					return Err(crate::support::BlockError::ExhaustsResources)
+				}
+				let len = crate::codec::Encode::encode(extrinsic).len();
+				let fee = Self::compute_fee(len, weight);
+				if !self.can_pay_fee(&signer, fee) {
+					return Err(crate::support::BlockError::CannotPayFees)
+				}
+				Ok(crate::transaction_pool::ValidTransaction {
+					signer,
+					nonce: extrinsic.nonce,
+					account_nonce,
+					priority: fee,
+				})
+			}
+		}
+	};
+
+	// This quote block implements the `RuntimeError` enum, which wraps the `Error` of each pallet.
+	let error_impl = quote! {
+		// These are all the errors which can be returned when dispatching a `RuntimeCall`.
+		// Note that it is just an accumulation of the errors of each pallet.
+		#[allow(non_camel_case_types)]
+		#[derive(Debug, Clone, PartialEq, Eq)]
+		pub enum RuntimeError {
+			#( #pallet_names(#pallet_names::Error) ),*
+		}
+
+		impl core::fmt::Display for RuntimeError {
+			fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+				match self {
+					#(
+						RuntimeError::#pallet_names(error) => {
+							write!(f, "{}: {}", stringify!(#pallet_names), error)
+						}
+					),*
+				}
+			}
+		}
+	};
+
+	// This quote block implements the `RuntimeEvent` enum, which wraps the `Event` of each pallet.
+	let event_impl = quote! {
+		// These are all the events which can be emitted by the runtime.
+		// Note that it is just an accumulation of the events of each pallet.
+		#[allow(non_camel_case_types)]
+		#[derive(Debug, Clone, PartialEq)]
+		pub enum RuntimeEvent {
+			#( #pallet_names(#pallet_names::Event<#runtime_struct>) ),*
 		}
 	};
 
@@ -51,41 +422,120 @@ pub fn expand_runtime(def: RuntimeDef) -> proc_macro2::TokenStream {
 		//
 		// The parsed function names will be `snake_case`, and that will show up in the enum.
 		#[allow(non_camel_case_types)]
+		#[derive(Debug, Clone, PartialEq)]
 		pub enum RuntimeCall {
 			#( #pallet_names(#pallet_names::Call<#runtime_struct>) ),*
 		}
 
+		impl RuntimeCall {
+			// The weight of this call, as declared by the pallet it belongs to.
+			pub fn weight(&self) -> crate::support::Weight {
+				match self {
+					#( RuntimeCall::#pallet_names(call) => call.weight() ),*
+				}
+			}
+		}
+
+		// Each call is encoded as the index of its pallet, followed by the pallet level call.
+		impl crate::codec::Encode for RuntimeCall {
+			fn encode_to(&self, dest: &mut Vec<u8>) {
+				match self {
+					#(
+						RuntimeCall::#pallet_names(call) => {
+							dest.push(#pallet_indices);
+							crate::codec::Encode::encode_to(call, dest);
+						}
+					),*
+				}
+			}
+		}
+
+		// A call is decoded by reading the index of its pallet, followed by the pallet level call.
+		impl crate::codec::Decode for RuntimeCall {
+			fn decode(input: &mut &[u8]) -> Result<Self, crate::codec::Error> {
+				match <u8 as crate::codec::Decode>::decode(input)? {
+					#(
+						#pallet_indices => {
+							Ok(RuntimeCall::#pallet_names(crate::codec::Decode::decode(input)?))
+						}
+					)*
+					_ => Err(crate::codec::Error("invalid pallet index")),
+				}
+			}
+		}
+
 		impl crate::support::Dispatch for #runtime_struct {
-			type Caller = <Runtime as system::Config>::AccountId;
+			type Origin = <#runtime_struct as system::Config>::RuntimeOrigin;
 			type Call = RuntimeCall;
-			// Dispatch a call on behalf of a caller. Increments the caller's nonce.
+			type Error = crate::support::DispatchError<RuntimeError>;
+			// Dispatch a call from an origin.
 			//
 			// Dispatch allows us to identify which underlying pallet call we want to execute.
-			// Note that we extract the `caller` from the extrinsic, and use that information
-			// to determine who we are executing the call on behalf of.
+			// Note that the `origin` of a signed extrinsic is its signer, and is used by the
+			// pallets to determine who we are executing the call on behalf of.
 			fn dispatch(
 				&mut self,
-				caller: Self::Caller,
+				origin: Self::Origin,
 				runtime_call: Self::Call,
-			) -> crate::support::DispatchResult {
+			) -> crate::support::DispatchResultWithPostInfo<Self::Error> {
 				// This match statement will allow us to correctly route `RuntimeCall`s
 				// to the appropriate pallet level call.
+				//
+				// Any error returned by a pallet is wrapped into a `DispatchError`, so we know
+				// which pallet it came from.
 				match runtime_call {
 					#(
 						RuntimeCall::#pallet_names(call) => {
-							self.#pallet_names.dispatch(caller, call)?;
+							self.#pallet_names.dispatch(origin, call).map_err(|error| {
+								crate::support::DispatchError {
+									pallet_index: #pallet_indices,
+									error_index: crate::support::PalletError::error_index(&error),
+									error: RuntimeError::#pallet_names(error),
+								}
+							})
 						}
 					),*
 				}
-				Ok(())
+			}
+		}
+	};
+
+	// This quote block implements the `RuntimeGenesisConfig` struct, which aggregates the
+	// `GenesisConfig` of every pallet.
+	let genesis_impl = quote! {
+		// The initial state of the runtime, made of the initial state of every pallet.
+		//
+		// It can be read from a chain spec, where the section of each pallet is named after the
+		// pallet. Missing sections use the default initial state of their pallet.
+		#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+		#[serde(default, deny_unknown_fields)]
+		pub struct RuntimeGenesisConfig {
+			pub system: system::GenesisConfig<#runtime_struct>,
+			#( pub #pallet_names: #pallet_names::GenesisConfig<#runtime_struct>, )*
+		}
+
+		impl RuntimeGenesisConfig {
+			// Create a new instance of the runtime, starting from this initial state.
+			pub fn build(&self) -> #runtime_struct {
+				let mut runtime = #runtime_struct::new();
+				self.system.build(&mut runtime.system);
+				#( self.#pallet_names.build(&mut runtime.#pallet_names); )*
+				// The chain is identified by the root of its initial state.
+				let genesis_hash = runtime.state_root();
+				runtime.system.set_genesis_hash(genesis_hash);
+				runtime
 			}
 		}
 	};
 
 	// We combine and return all the generated code.
 	quote! {
+		#genesis_impl
+		#error_impl
+		#event_impl
 		#dispatch_impl
 		#runtime_impl
+		#transactional_impl
+		#validator_impl
 	}
-	.into()
 }
@@ -6,12 +6,28 @@ pub struct RuntimeDef {
 	/// This is the name of the struct used by the user. We mostly assume it is `Runtime`.
 	pub runtime_struct: syn::Ident,
 	/// This is the list of pallets included in the `Runtime` struct. We omit `system` from this
-	/// list, but during parsing we check that system exists.
-	pub pallets: Vec<(syn::Ident, syn::Type)>,
+	/// list, but during parsing we check that system exists. See `PalletDef`.
+	pub pallets: Vec<PalletDef>,
+}
+
+/// This is the metadata we keep about each pallet in the runtime.
+#[derive(Debug)]
+pub struct PalletDef {
+	/// The name of the field of the pallet in the `Runtime` struct.
+	pub name: syn::Ident,
+	/// The type of the pallet.
+	pub ty: syn::Type,
+	/// The index given in the `#[pallet_index(n)]` attribute of the field, which identifies the
+	/// pallet in the encoding of calls and in errors.
+	pub index: u8,
 }
 
 impl RuntimeDef {
-	pub fn try_from(item: syn::Item) -> syn::Result<Self> {
+	/// Parse the `Runtime` struct in `item`.
+	///
+	/// The `#[pallet_index(n)]` attributes are removed from `item`, since they are only understood
+	/// by this macro.
+	pub fn try_from(item: &mut syn::Item) -> syn::Result<Self> {
 		// First we check that we are parsing a `struct`.
 		let item_struct = if let syn::Item::Struct(item) = item {
 			item
@@ -20,23 +36,64 @@ impl RuntimeDef {
 		};
 
 		// We check that the `Runtime` includes the `system` pallet as the first item.
-		check_system(&item_struct)?;
+		check_system(item_struct)?;
 
-		let runtime_struct = item_struct.ident;
+		let runtime_struct = item_struct.ident.clone();
 
 		// Here is where we will store a list of all the pallets.
-		let mut pallets = vec![];
+		let mut pallets: Vec<PalletDef> = vec![];
 		// We skip `system`, which we ensure is the first field in `check_system`.
-		for field in item_struct.fields.into_iter().skip(1) {
-			if let Some(ident) = field.ident {
-				pallets.push((ident, field.ty))
+		for field in item_struct.fields.iter_mut().skip(1) {
+			let Some(name) = field.ident.clone() else { continue };
+
+			// Every pallet must declare its index, which must be unique within the runtime.
+			let index_lit = take_pallet_index_attr(field)?;
+			let index = index_lit.base10_parse::<u8>()?;
+			if index == 0 {
+				let msg = "Invalid runtime, `#[pallet_index(0)]` is reserved for the system pallet";
+				return Err(syn::Error::new(index_lit.span(), msg))
+			}
+			if let Some(other) = pallets.iter().find(|pallet| pallet.index == index) {
+				let msg = format!(
+					"Invalid runtime, duplicate `#[pallet_index({})]`, already used by `{}`",
+					index, other.name
+				);
+				return Err(syn::Error::new(index_lit.span(), msg))
 			}
+
+			pallets.push(PalletDef { name, ty: field.ty.clone(), index })
 		}
 
 		Ok(Self { runtime_struct, pallets })
 	}
 }
 
+/// Remove the `#[pallet_index(n)]` attribute from `field`, and return its index.
+fn take_pallet_index_attr(field: &mut syn::Field) -> syn::Result<syn::LitInt> {
+	let mut found = vec![];
+	let mut attrs = vec![];
+	for attr in field.attrs.drain(..) {
+		if attr.path().is_ident("pallet_index") {
+			found.push(attr);
+		} else {
+			attrs.push(attr);
+		}
+	}
+	field.attrs = attrs;
+
+	match found.as_slice() {
+		[attr] => attr.parse_args::<syn::LitInt>(),
+		[] => {
+			let msg = "Invalid runtime, missing `#[pallet_index(n)]` attribute";
+			Err(syn::Error::new(field.span(), msg))
+		},
+		[_, duplicate, ..] => {
+			let msg = "Invalid runtime, duplicate `#[pallet_index(n)]` attribute";
+			Err(syn::Error::new(duplicate.span(), msg))
+		},
+	}
+}
+
 /// This function checks that the `system` pallet is the first pallet included in the `Runtime`
 /// struct. We make many assumptions about the `system` pallet in order to keep these macros simple.
 /// For example, we assume that the system pallet has no callable functions, and that it contains
@@ -65,3 +122,59 @@ fn check_system(item_struct: &syn::ItemStruct) -> syn::Result<()> {
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod test {
+	use super::RuntimeDef;
+	use proc_macro2::LineColumn;
+
+	/// Parse the runtime in `source`, which must be rejected, and return the message of the error
+	/// and the line and column it points at.
+	fn parse_error(source: &str) -> (String, LineColumn) {
+		let mut item = syn::parse_str::<syn::Item>(source).unwrap();
+		let error = RuntimeDef::try_from(&mut item).unwrap_err();
+		(error.to_string(), error.span().start())
+	}
+
+	#[test]
+	fn duplicate_pallet_indices_are_rejected() {
+		let source = "pub struct Runtime {
+			system: system::Pallet<Self>,
+			#[pallet_index(1)]
+			balances: balances::Pallet<Self>,
+			#[pallet_index(1)]
+			proof_of_existence: proof_of_existence::Pallet<Self>,
+		}";
+		let (message, start) = parse_error(source);
+		assert_eq!(
+			message,
+			"Invalid runtime, duplicate `#[pallet_index(1)]`, already used by `balances`"
+		);
+		// The error points at the index of the last pallet.
+		assert_eq!(start, LineColumn { line: 5, column: 18 });
+
+		// The index of the system pallet cannot be used by another pallet.
+		let source = "pub struct Runtime {
+			system: system::Pallet<Self>,
+			#[pallet_index(0)]
+			balances: balances::Pallet<Self>,
+		}";
+		let (message, start) = parse_error(source);
+		assert_eq!(
+			message,
+			"Invalid runtime, `#[pallet_index(0)]` is reserved for the system pallet"
+		);
+		assert_eq!(start, LineColumn { line: 3, column: 18 });
+
+		// A pallet cannot have two indices either.
+		let source = "pub struct Runtime {
+			system: system::Pallet<Self>,
+			#[pallet_index(1)]
+			#[pallet_index(2)]
+			balances: balances::Pallet<Self>,
+		}";
+		let (message, start) = parse_error(source);
+		assert_eq!(message, "Invalid runtime, duplicate `#[pallet_index(n)]` attribute");
+		assert_eq!(start, LineColumn { line: 4, column: 3 });
+	}
+}
@@ -0,0 +1,123 @@
+use super::parse::{StorageDef, StorageKind};
+use quote::{format_ident, quote};
+
+/// See the `fn storage` docs at the `lib.rs` of this crate for a high level definition.
+pub fn expand_storage(def: StorageDef) -> proc_macro2::TokenStream {
+	let StorageDef { pallet_struct, generics, items, transactional, version } = def;
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+	// This is a vector of the names of all the storage items.
+	let item_names = items.iter().map(|item| &item.name).collect::<Vec<_>>();
+
+	// These are all the fields which follow the transactions of the pallet: the storage items,
+	// and the fields marked with `#[transactional]`.
+	let transactional_names =
+		item_names.iter().copied().chain(transactional.iter()).collect::<Vec<_>>();
+
+	// Pallets which do not declare their storage version are at version 0.
+	let version = version.map_or(quote!(0), |version| quote!(#version));
+
+	// The type parameters of the pallet, which the types providing default values must use.
+	let type_params = generics.type_params().map(|param| &param.ident).collect::<Vec<_>>();
+
+	// This generates a type for every storage item with a `#[default(expr)]` attribute, which
+	// provides its default value.
+	let defaults = items.iter().filter_map(|item| {
+		let (default_struct, expr) = item.default.as_ref()?;
+		let value = &item.value;
+		Some(quote! {
+			#[allow(non_camel_case_types)]
+			pub struct #default_struct #impl_generics (
+				core::marker::PhantomData<( #( #type_params, )* )>
+			) #where_clause;
+
+			impl #impl_generics crate::storage::Get<#value> for #default_struct #ty_generics
+			#where_clause
+			{
+				fn get() -> #value {
+					#expr
+				}
+			}
+		})
+	});
+
+	// This generates a getter for every storage item with a `#[getter(name)]` attribute, which
+	// returns the value stored in the item, or its default value.
+	let getters = items.iter().filter_map(|item| {
+		let getter = item.getter.as_ref()?;
+		let name = &item.name;
+		let value = &item.value;
+		let keys = &item.keys;
+		let key_names = (1..=keys.len()).map(|i| format_ident!("key{}", i)).collect::<Vec<_>>();
+		Some(match item.kind {
+			StorageKind::Value => quote! {
+				pub fn #getter(&self) -> #value {
+					self.#name.get().clone()
+				}
+			},
+			StorageKind::Map | StorageKind::DoubleMap => quote! {
+				pub fn #getter(&self, #( #key_names: &#keys ),*) -> #value {
+					self.#name.get_or_default(#( #key_names ),*)
+				}
+			},
+		})
+	});
+
+	quote! {
+		#( #defaults )*
+
+		impl #impl_generics #pallet_struct #ty_generics #where_clause {
+			#( #getters )*
+		}
+
+		// A transaction of the pallet spans all of its storage items.
+		impl #impl_generics crate::storage::Transactional for #pallet_struct #ty_generics
+		#where_clause
+		{
+			fn start_transaction(&mut self) {
+				#( crate::storage::Transactional::start_transaction(&mut self.#transactional_names); )*
+			}
+
+			fn commit_transaction(&mut self) {
+				#( crate::storage::Transactional::commit_transaction(&mut self.#transactional_names); )*
+			}
+
+			fn rollback_transaction(&mut self) {
+				#(
+					crate::storage::Transactional::rollback_transaction(&mut self.#transactional_names);
+				)*
+			}
+		}
+
+		// The storage of the pallet is made of all of its storage items, each prefixed by the name
+		// of the pallet and the name of the item, along with its storage version.
+		impl #impl_generics crate::storage::PalletStorage for #pallet_struct #ty_generics
+		#where_clause
+		{
+			const STORAGE_VERSION: crate::storage::StorageVersion = #version;
+
+			fn storage_pairs(&self, pallet: &str, pairs: &mut crate::storage::StoragePairs) {
+				#(
+					let prefix = crate::storage::storage_prefix(pallet, stringify!(#item_names));
+					self.#item_names.storage_pairs(&prefix, pairs);
+				)*
+				crate::storage::set_storage_version(pallet, pairs, Self::STORAGE_VERSION);
+			}
+
+			fn load_storage_pairs(
+				&mut self,
+				pallet: &str,
+				pairs: &[(Vec<u8>, Vec<u8>)],
+			) -> Result<(), crate::codec::Error> {
+				if crate::storage::storage_version(pallet, pairs)? != Self::STORAGE_VERSION {
+					return Err(crate::codec::Error("storage of a pallet is not at its current version"));
+				}
+				#(
+					let prefix = crate::storage::storage_prefix(pallet, stringify!(#item_names));
+					self.#item_names.load_storage_pairs(&prefix, pairs)?;
+				)*
+				Ok(())
+			}
+		}
+	}
+}
@@ -0,0 +1,29 @@
+pub mod expand;
+pub mod parse;
+
+/// See the `fn storage` docs at the `lib.rs` of this crate for a high level definition.
+pub fn storage(
+	attr: proc_macro::TokenStream,
+	item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+	let mut item_struct = syn::parse_macro_input!(item as syn::Item);
+
+	// First we parse the storage items of the pallet...
+	let def = parse::StorageDef::try_from(attr.into(), &mut item_struct);
+	let generated: proc_macro::TokenStream = match def {
+		// ..then we generate our new code.
+		Ok(def) => expand::expand_storage(def).into(),
+		Err(e) => e.to_compile_error().into(),
+	};
+
+	// The final expanded code will be placed here.
+	// Since our macro only adds new code, our final product will contain all of our old code too,
+	// without the attributes which were only meant for this macro, and with the default value type
+	// of the storage items which declare one.
+	let mut finished: proc_macro::TokenStream =
+		quote::ToTokens::into_token_stream(item_struct).into();
+
+	// Add our generated code to the end, and return the final result.
+	finished.extend(generated);
+	finished
+}
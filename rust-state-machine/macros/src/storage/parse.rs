@@ -0,0 +1,227 @@
+use syn::spanned::Spanned;
+
+/// This object will collect all the information we need to keep while parsing the storage of a
+/// pallet.
+#[derive(Debug)]
+pub struct StorageDef {
+	/// This is the name of the pallet struct holding the storage. We mostly assume it is `Pallet`.
+	pub pallet_struct: syn::Ident,
+	/// The generics of the pallet struct, usually `<T: Config>`.
+	pub generics: syn::Generics,
+	/// This is a list of the storage items of the pallet. See `StorageItemDef`.
+	pub items: Vec<StorageItemDef>,
+	/// The fields marked with `#[transactional]`, which are not storage items but must follow the
+	/// transactions of the pallet, like its queue of events.
+	pub transactional: Vec<syn::Ident>,
+	/// The storage version given in the `#[macros::storage(version = n)]` attribute, if any.
+	pub version: Option<syn::LitInt>,
+}
+
+/// The kinds of storage items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
+	/// A `StorageValue<V>`.
+	Value,
+	/// A `StorageMap<K, V>`.
+	Map,
+	/// A `StorageDoubleMap<K1, K2, V>`.
+	DoubleMap,
+}
+
+impl StorageKind {
+	/// The number of keys of this kind of storage item.
+	pub fn keys(&self) -> usize {
+		match self {
+			StorageKind::Value => 0,
+			StorageKind::Map => 1,
+			StorageKind::DoubleMap => 2,
+		}
+	}
+}
+
+/// This is the metadata we keep about each storage item of the pallet.
+#[derive(Debug)]
+pub struct StorageItemDef {
+	/// The name of the field of the storage item, which is also its name in the storage prefix.
+	pub name: syn::Ident,
+	/// The kind of the storage item.
+	pub kind: StorageKind,
+	/// The types of the keys of the storage item.
+	pub keys: Vec<syn::Type>,
+	/// The type of the values of the storage item.
+	pub value: syn::Type,
+	/// The name given in the `#[getter(name)]` attribute of the field, if any.
+	pub getter: Option<syn::Ident>,
+	/// The expression given in the `#[default(expr)]` attribute of the field, if any, along with
+	/// the name of the type we generate to provide it.
+	pub default: Option<(syn::Ident, syn::Expr)>,
+}
+
+impl StorageDef {
+	/// Parse the storage items of the pallet struct in `item`, and the arguments of the attribute in
+	/// `attr`.
+	///
+	/// The `#[getter(name)]`, `#[default(expr)]` and `#[transactional]` attributes are removed from
+	/// `item`, since they are only understood by this macro. Storage items with a
+	/// `#[default(expr)]` attribute get the type providing their default value as their last
+	/// generic argument.
+	pub fn try_from(attr: proc_macro2::TokenStream, item: &mut syn::Item) -> syn::Result<Self> {
+		let version = parse_version(attr)?;
+
+		// First we check that we are parsing a `struct`.
+		let item_struct = if let syn::Item::Struct(item) = item {
+			item
+		} else {
+			return Err(syn::Error::new(item.span(), "Invalid pallet::storage, expected struct"))
+		};
+
+		let pallet_struct = item_struct.ident.clone();
+		let generics = item_struct.generics.clone();
+		let (_, ty_generics, _) = generics.split_for_impl();
+
+		// Here is where we will store all the storage items, and the other transactional fields.
+		let mut items = vec![];
+		let mut transactional = vec![];
+		for field in item_struct.fields.iter_mut() {
+			let Some(name) = field.ident.clone() else {
+				let msg = "Invalid pallet::storage, expected named fields";
+				return Err(syn::Error::new(field.span(), msg))
+			};
+
+			if take_attr::<NoArgs>(field, "transactional")?.is_some() {
+				transactional.push(name);
+				continue
+			}
+			let getter = take_attr::<syn::Ident>(field, "getter")?;
+			let default_expr = take_attr::<syn::Expr>(field, "default")?;
+
+			// Any field which is not a storage item is kept as is.
+			let Some((kind, args)) = storage_type(&mut field.ty) else {
+				if getter.is_some() || default_expr.is_some() {
+					let msg = "Invalid pallet::storage, only storage items can have a getter or \
+						a default value";
+					return Err(syn::Error::new(field.ty.span(), msg))
+				}
+				continue
+			};
+
+			// The storage item has its keys, its value, and optionally the type of its default
+			// value.
+			let types = args
+				.iter()
+				.filter_map(|arg| match arg {
+					syn::GenericArgument::Type(ty) => Some(ty.clone()),
+					_ => None,
+				})
+				.collect::<Vec<_>>();
+			let has_default_type = match types.len() - kind.keys() {
+				1 => false,
+				2 => true,
+				_ => {
+					let msg = "Invalid pallet::storage, unexpected number of generic arguments";
+					return Err(syn::Error::new(args.span(), msg))
+				},
+			};
+			let keys = types[..kind.keys()].to_vec();
+			let value = types[kind.keys()].clone();
+
+			// The default value is provided by a type we generate, which we add to the type of the
+			// storage item.
+			let default = match default_expr {
+				Some(_) if has_default_type => {
+					let msg = "Invalid pallet::storage, the storage item already has a default \
+						value type";
+					return Err(syn::Error::new(args.span(), msg))
+				},
+				Some(expr) => {
+					let default_struct = quote::format_ident!("{}Default_{}", pallet_struct, name);
+					args.push(syn::parse_quote!(#default_struct #ty_generics));
+					Some((default_struct, expr))
+				},
+				None => None,
+			};
+
+			items.push(StorageItemDef { name, kind, keys, value, getter, default });
+		}
+
+		Ok(Self { pallet_struct, generics, items, transactional, version })
+	}
+}
+
+/// Parse the arguments of the `#[macros::storage]` attribute, which are either empty or
+/// `version = n`.
+fn parse_version(attr: proc_macro2::TokenStream) -> syn::Result<Option<syn::LitInt>> {
+	if attr.is_empty() {
+		return Ok(None)
+	}
+	let arg = syn::parse2::<syn::MetaNameValue>(attr)?;
+	if !arg.path.is_ident("version") {
+		let msg = "Invalid pallet::storage, expected `version = n`";
+		return Err(syn::Error::new(arg.path.span(), msg))
+	}
+	match &arg.value {
+		syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(version), .. }) => {
+			version.base10_parse::<u16>()?;
+			Ok(Some(version.clone()))
+		},
+		value => {
+			let msg = "Invalid pallet::storage, the version must be an integer";
+			Err(syn::Error::new(value.span(), msg))
+		},
+	}
+}
+
+/// If `ty` is one of the storage types, return its kind and its generic arguments.
+fn storage_type(
+	ty: &mut syn::Type,
+) -> Option<(StorageKind, &mut syn::punctuated::Punctuated<syn::GenericArgument, syn::Token![,]>)> {
+	let syn::Type::Path(type_path) = ty else { return None };
+	let segment = type_path.path.segments.last_mut()?;
+	let kind = match segment.ident.to_string().as_str() {
+		"StorageValue" => StorageKind::Value,
+		"StorageMap" => StorageKind::Map,
+		"StorageDoubleMap" => StorageKind::DoubleMap,
+		_ => return None,
+	};
+	match &mut segment.arguments {
+		syn::PathArguments::AngleBracketed(args) => Some((kind, &mut args.args)),
+		_ => None,
+	}
+}
+
+/// The argument of an attribute which takes none, like `#[transactional]`.
+struct NoArgs;
+
+impl syn::parse::Parse for NoArgs {
+	fn parse(_input: syn::parse::ParseStream) -> syn::Result<Self> {
+		Ok(Self)
+	}
+}
+
+/// Remove the `#[name(arg)]` attribute from `field`, if any, and parse its argument.
+///
+/// A field can have at most one such attribute.
+fn take_attr<T: syn::parse::Parse>(field: &mut syn::Field, name: &str) -> syn::Result<Option<T>> {
+	let mut found = vec![];
+	let mut attrs = vec![];
+	for attr in field.attrs.drain(..) {
+		if attr.path().is_ident(name) {
+			found.push(attr);
+		} else {
+			attrs.push(attr);
+		}
+	}
+	field.attrs = attrs;
+
+	match found.as_slice() {
+		[] => Ok(None),
+		[attr] => match &attr.meta {
+			syn::Meta::Path(_) => syn::parse2::<T>(Default::default()).map(Some),
+			_ => attr.parse_args::<T>().map(Some),
+		},
+		[_, duplicate, ..] => {
+			let msg = format!("Invalid pallet::storage, duplicate `#[{}]` attribute", name);
+			Err(syn::Error::new(duplicate.span(), msg))
+		},
+	}
+}
@@ -0,0 +1,440 @@
+use crate::{
+	codec::{Decode, Encode},
+	storage::StoragePairs,
+	support::{hash, Hash},
+};
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fs::{File, OpenOptions},
+	io::{Read, Seek, SeekFrom, Write},
+	path::Path,
+};
+
+// A storage backend keeps the state of the runtime after every executed block, so a node can be
+// stopped and resumed at the same block. The runtime itself only ever works on its state in memory,
+// and commits it to the backend once a block has been executed.
+
+// The state of the runtime after the last committed block.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CommittedState {
+	// The hash of the last committed block, which is the parent of the next block.
+	pub block_hash: Hash,
+	// The `(key, value)` pairs in the storage of every pallet, ordered by key.
+	pub pairs: StoragePairs,
+}
+
+// The error returned when a backend cannot read or write its state.
+#[derive(Debug)]
+pub enum BackendError {
+	// The underlying file could not be read or written.
+	Io(std::io::Error),
+}
+
+impl core::fmt::Display for BackendError {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			BackendError::Io(e) => write!(f, "cannot access the stored state: {}", e),
+		}
+	}
+}
+
+// A trait for the places the state of the runtime can be kept in.
+pub trait Backend {
+	// The last committed state, or `None` if no block was ever committed.
+	fn load(&self) -> Option<&CommittedState>;
+	// Commit the state after a new block. Once this returns `Ok`, the state is the one returned by
+	// `load`, even after a restart for backends which persist it.
+	fn commit(&mut self, state: CommittedState) -> Result<(), BackendError>;
+}
+
+// A backend keeping the state in memory, which is lost when the node stops. The node keeps its
+// state on disk, so nothing in this crate constructs one outside of the tests.
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+pub struct InMemoryBackend {
+	state: Option<CommittedState>,
+}
+
+impl Backend for InMemoryBackend {
+	fn load(&self) -> Option<&CommittedState> {
+		self.state.as_ref()
+	}
+
+	fn commit(&mut self, state: CommittedState) -> Result<(), BackendError> {
+		self.state = Some(state);
+		Ok(())
+	}
+}
+
+// The changes made to the state by a block, as written in the log of a `FileBackend`.
+#[derive(Debug, Clone, PartialEq, macros::Encode, macros::Decode)]
+struct Record {
+	block_hash: Hash,
+	// The new value of every modified key, or `None` for the keys which were removed.
+	changes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+// The length of the header of a record: the length of its payload as a `u32`, followed by the hash
+// of its payload.
+const RECORD_HEADER_LEN: usize = 4 + 32;
+
+// A backend keeping the state in a file on disk.
+//
+// The file is an append-only log with one record per committed block, holding the changes made by
+// the block. The state is rebuilt by replaying the log when the file is opened. Each record starts
+// with the length and the hash of its content, so a record which was only partially written, for
+// example because the node was killed in the middle of a commit, is detected and dropped. The state
+// is then the one of the last completely written block.
+//
+// Since the log keeps the changes made by every block, the state after an older block can be
+// rebuilt too, which a node needs to reorganize its chain.
+pub struct FileBackend {
+	file: File,
+	// The length of the log, up to the end of the last complete record.
+	len: u64,
+	state: Option<CommittedState>,
+	// The hashes of the blocks whose state was committed.
+	committed: BTreeSet<Hash>,
+}
+
+impl FileBackend {
+	// Open the log at `path`, creating it if it does not exist, and replay it.
+	pub fn open(path: &Path) -> Result<Self, BackendError> {
+		let mut file = OpenOptions::new()
+			.read(true)
+			.append(true)
+			.create(true)
+			.open(path)
+			.map_err(BackendError::Io)?;
+		let mut bytes = Vec::new();
+		file.read_to_end(&mut bytes).map_err(BackendError::Io)?;
+		let mut committed = BTreeSet::new();
+		let (state, len) = replay(&bytes, |block_hash| {
+			committed.insert(*block_hash);
+			false
+		});
+
+		// Drop what follows the last complete record, so the next record is appended right
+		// after it.
+		if len < bytes.len() {
+			file.set_len(len as u64)
+				.and_then(|_| file.sync_all())
+				.map_err(BackendError::Io)?;
+		}
+		Ok(Self { file, len: len as u64, state, committed })
+	}
+
+	// Whether the state after the block with `block_hash` was committed.
+	pub fn is_committed(&self, block_hash: &Hash) -> bool {
+		self.committed.contains(block_hash)
+	}
+
+	// The state committed after the block with `block_hash`, rebuilt by replaying the log up to
+	// this block. Returns `None` if it was never committed.
+	pub fn load_at(&self, block_hash: &Hash) -> Result<Option<CommittedState>, BackendError> {
+		if !self.is_committed(block_hash) {
+			return Ok(None);
+		}
+		let mut bytes = Vec::new();
+		(&self.file)
+			.seek(SeekFrom::Start(0))
+			.and_then(|_| (&self.file).read_to_end(&mut bytes))
+			.map_err(BackendError::Io)?;
+		Ok(replay(&bytes, |hash| hash == block_hash).0)
+	}
+}
+
+// Replay the complete records at the start of the log `bytes`, until `is_last` holds for the hash
+// of the block of a record. Returns the state after the last replayed record, and the length of the
+// replayed records.
+fn replay(bytes: &[u8], mut is_last: impl FnMut(&Hash) -> bool) -> (Option<CommittedState>, usize) {
+	let mut pairs = BTreeMap::new();
+	let mut block_hash = None;
+	let mut rest = bytes;
+	while let Some((record, len)) = read_record::<Record>(rest) {
+		for (key, value) in record.changes {
+			match value {
+				Some(value) => pairs.insert(key, value),
+				None => pairs.remove(&key),
+			};
+		}
+		rest = &rest[len..];
+		block_hash = Some(record.block_hash);
+		if is_last(&record.block_hash) {
+			break;
+		}
+	}
+	let state = block_hash
+		.map(|block_hash| CommittedState { block_hash, pairs: pairs.into_iter().collect() });
+	(state, bytes.len() - rest.len())
+}
+
+impl Backend for FileBackend {
+	fn load(&self) -> Option<&CommittedState> {
+		self.state.as_ref()
+	}
+
+	fn commit(&mut self, state: CommittedState) -> Result<(), BackendError> {
+		let old = self.state.as_ref().map(|state| state.pairs.as_slice()).unwrap_or_default();
+		let record = Record { block_hash: state.block_hash, changes: changes(old, &state.pairs) };
+		let bytes = encode_record(&record);
+
+		// The state is only committed once the record is on the disk.
+		if let Err(e) = self.file.write_all(&bytes).and_then(|_| self.file.sync_data()) {
+			// Remove whatever part of the record was written, so the log stays consistent.
+			let _ = self.file.set_len(self.len);
+			return Err(BackendError::Io(e));
+		}
+		self.len += bytes.len() as u64;
+		self.committed.insert(state.block_hash);
+		self.state = Some(state);
+		Ok(())
+	}
+}
+
+// A log of the blocks of the canonical chain, kept in a file on disk next to the state.
+//
+// The file has the same format as the log of a `FileBackend`, with one record per block holding
+// the encoded block, so a block which was only partially written is dropped when the file is
+// opened.
+pub struct BlockStore<Block> {
+	file: File,
+	// The length of the log up to the end of each block.
+	ends: Vec<u64>,
+	blocks: Vec<Block>,
+}
+
+impl<Block: Encode + Decode> BlockStore<Block> {
+	// Open the log at `path`, creating it if it does not exist, and read its blocks.
+	pub fn open(path: &Path) -> Result<Self, BackendError> {
+		let mut file = OpenOptions::new()
+			.read(true)
+			.append(true)
+			.create(true)
+			.open(path)
+			.map_err(BackendError::Io)?;
+		let mut bytes = Vec::new();
+		file.read_to_end(&mut bytes).map_err(BackendError::Io)?;
+
+		let (mut ends, mut blocks) = (Vec::new(), Vec::new());
+		let mut rest = bytes.as_slice();
+		while let Some((block, len)) = read_record(rest) {
+			rest = &rest[len..];
+			ends.push((bytes.len() - rest.len()) as u64);
+			blocks.push(block);
+		}
+		let mut store = Self { file, ends, blocks };
+		if !rest.is_empty() {
+			store.truncate(store.blocks.len())?;
+		}
+		Ok(store)
+	}
+
+	// The blocks in the log, in the order they were appended.
+	pub fn blocks(&self) -> &[Block] {
+		&self.blocks
+	}
+
+	// Append `block` to the log. Once this returns `Ok`, the block is on the disk.
+	pub fn append(&mut self, block: Block) -> Result<(), BackendError> {
+		let bytes = encode_record(&block);
+		let len = self.ends.last().copied().unwrap_or_default();
+		if let Err(e) = self.file.write_all(&bytes).and_then(|_| self.file.sync_data()) {
+			// Remove whatever part of the record was written, so the log stays consistent.
+			let _ = self.file.set_len(len);
+			return Err(BackendError::Io(e));
+		}
+		self.ends.push(len + bytes.len() as u64);
+		self.blocks.push(block);
+		Ok(())
+	}
+
+	// Remove every block after the first `count` ones from the log.
+	pub fn truncate(&mut self, count: usize) -> Result<(), BackendError> {
+		let len = match count {
+			0 => 0,
+			count => self.ends[count - 1],
+		};
+		self.file
+			.set_len(len)
+			.and_then(|_| self.file.sync_all())
+			.map_err(BackendError::Io)?;
+		self.ends.truncate(count);
+		self.blocks.truncate(count);
+		Ok(())
+	}
+}
+
+// Encode `record` as a record of a log: its length and its hash, followed by its encoding.
+fn encode_record<R: Encode>(record: &R) -> Vec<u8> {
+	let payload = record.encode();
+	let mut bytes = (payload.len() as u32).to_le_bytes().to_vec();
+	bytes.extend_from_slice(&hash(&payload));
+	bytes.extend_from_slice(&payload);
+	bytes
+}
+
+// Read the record at the start of `bytes`, and return it along with its length in the log.
+//
+// Returns `None` if `bytes` does not start with a complete record, matching its hash.
+fn read_record<R: Decode>(bytes: &[u8]) -> Option<(R, usize)> {
+	let len = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+	let checksum = bytes.get(4..RECORD_HEADER_LEN)?;
+	let payload = bytes.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)?;
+	if hash(payload) != checksum {
+		return None;
+	}
+	let record = R::decode_all(payload).ok()?;
+	Some((record, RECORD_HEADER_LEN + len))
+}
+
+// The changes turning the state made of the pairs `old` into the state made of the pairs `new`.
+fn changes(
+	old: &[(Vec<u8>, Vec<u8>)],
+	new: &[(Vec<u8>, Vec<u8>)],
+) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+	let old = old.iter().cloned().collect::<BTreeMap<_, _>>();
+	let new = new.iter().cloned().collect::<BTreeMap<_, _>>();
+	let removed = old.keys().filter(|key| !new.contains_key(*key)).map(|key| (key.clone(), None));
+	let modified = new
+		.iter()
+		.filter(|(key, value)| old.get(*key) != Some(value))
+		.map(|(key, value)| (key.clone(), Some(value.clone())));
+	removed.chain(modified).collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Backend, BlockStore, CommittedState, FileBackend, InMemoryBackend};
+	use std::path::PathBuf;
+
+	// A path in the temporary directory, which does not exist yet.
+	fn temp_path(name: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!(
+			"rust-state-machine-{}-backend-{}",
+			std::process::id(),
+			name
+		));
+		let _ = std::fs::remove_file(&path);
+		path
+	}
+
+	fn state(block: u8, pairs: &[(&[u8], &[u8])]) -> CommittedState {
+		CommittedState {
+			block_hash: [block; 32],
+			pairs: pairs.iter().map(|(key, value)| (key.to_vec(), value.to_vec())).collect(),
+		}
+	}
+
+	#[test]
+	fn in_memory_backend() {
+		let mut backend = InMemoryBackend::default();
+		assert_eq!(backend.load(), None);
+		backend.commit(state(1, &[(b"a", b"1")])).unwrap();
+		assert_eq!(backend.load(), Some(&state(1, &[(b"a", b"1")])));
+	}
+
+	#[test]
+	fn file_backend_is_resumed() {
+		let path = temp_path("resumed");
+		let first = state(1, &[(b"a", b"1"), (b"b", b"2")]);
+		// Modifies a key, removes a key and inserts a key.
+		let second = state(2, &[(b"a", b"3"), (b"c", b"4")]);
+
+		let mut backend = FileBackend::open(&path).unwrap();
+		assert_eq!(backend.load(), None);
+		backend.commit(first.clone()).unwrap();
+		drop(backend);
+		assert_eq!(FileBackend::open(&path).unwrap().load(), Some(&first));
+
+		let mut backend = FileBackend::open(&path).unwrap();
+		backend.commit(second.clone()).unwrap();
+		assert_eq!(backend.load(), Some(&second));
+		drop(backend);
+		assert_eq!(FileBackend::open(&path).unwrap().load(), Some(&second));
+
+		// Empty blocks are committed too.
+		let third = CommittedState { block_hash: [3; 32], ..second.clone() };
+		FileBackend::open(&path).unwrap().commit(third.clone()).unwrap();
+		assert_eq!(FileBackend::open(&path).unwrap().load(), Some(&third));
+
+		// The states after older blocks are rebuilt from the log, even once the chain went back to
+		// one of them.
+		let mut backend = FileBackend::open(&path).unwrap();
+		backend.commit(first.clone()).unwrap();
+		let backend = FileBackend::open(&path).unwrap();
+		assert_eq!(backend.load(), Some(&first));
+		assert_eq!(backend.load_at(&[1; 32]).unwrap(), Some(first));
+		assert_eq!(backend.load_at(&[2; 32]).unwrap(), Some(second));
+		assert!(backend.is_committed(&[3; 32]));
+		assert_eq!(backend.load_at(&[4; 32]).unwrap(), None);
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn partially_written_blocks_are_dropped() {
+		let path = temp_path("partial");
+		let first = state(1, &[(b"a", b"1")]);
+		let second = state(2, &[(b"a", b"2"), (b"b", b"2")]);
+		let third = state(3, &[(b"b", b"3")]);
+
+		let mut backend = FileBackend::open(&path).unwrap();
+		backend.commit(first.clone()).unwrap();
+		let first_len = std::fs::metadata(&path).unwrap().len() as usize;
+		backend.commit(second).unwrap();
+		drop(backend);
+		let log = std::fs::read(&path).unwrap();
+
+		// Whatever part of the second record was written, the state is the one of the first block.
+		for len in first_len..log.len() {
+			std::fs::write(&path, &log[..len]).unwrap();
+			let mut backend = FileBackend::open(&path).unwrap();
+			assert_eq!(backend.load(), Some(&first));
+			// The partial record is dropped, so the next block is appended after the first one.
+			backend.commit(third.clone()).unwrap();
+			drop(backend);
+			assert_eq!(FileBackend::open(&path).unwrap().load(), Some(&third));
+		}
+
+		// A record whose content does not match its hash is dropped too.
+		let mut corrupted = log.clone();
+		*corrupted.last_mut().unwrap() ^= 1;
+		std::fs::write(&path, &corrupted).unwrap();
+		assert_eq!(FileBackend::open(&path).unwrap().load(), Some(&first));
+		assert_eq!(std::fs::metadata(&path).unwrap().len() as usize, first_len);
+
+		// Nothing is committed until the first record is complete.
+		std::fs::write(&path, &log[..first_len - 1]).unwrap();
+		assert_eq!(FileBackend::open(&path).unwrap().load(), None);
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn block_store() {
+		let path = temp_path("blocks");
+		let mut store = BlockStore::<Vec<u8>>::open(&path).unwrap();
+		assert!(store.blocks().is_empty());
+		store.append(vec![1]).unwrap();
+		store.append(vec![2, 2]).unwrap();
+		store.append(vec![3, 3, 3]).unwrap();
+		drop(store);
+		let log = std::fs::read(&path).unwrap();
+
+		let mut store = BlockStore::<Vec<u8>>::open(&path).unwrap();
+		assert_eq!(store.blocks(), &[vec![1], vec![2, 2], vec![3, 3, 3]]);
+		store.truncate(1).unwrap();
+		store.append(vec![4]).unwrap();
+		drop(store);
+		assert_eq!(BlockStore::<Vec<u8>>::open(&path).unwrap().blocks(), &[vec![1], vec![4]]);
+
+		// A partially written block is dropped.
+		std::fs::write(&path, &log[..log.len() - 1]).unwrap();
+		let mut store = BlockStore::<Vec<u8>>::open(&path).unwrap();
+		assert_eq!(store.blocks(), &[vec![1], vec![2, 2]]);
+		store.append(vec![5]).unwrap();
+		drop(store);
+		let store = BlockStore::<Vec<u8>>::open(&path).unwrap();
+		assert_eq!(store.blocks(), &[vec![1], vec![2, 2], vec![5]]);
+		std::fs::remove_file(path).unwrap();
+	}
+}
@@ -1,62 +1,258 @@
-use num::traits::{CheckedAdd, CheckedSub, Zero};
-use std::collections::BTreeMap;
+use crate::{
+	codec::{Decode, Encode},
+	storage::StorageMap,
+	support::{
+		DispatchResult, DispatchResultWithPostInfo, EventQueue, Hooks, OnRuntimeUpgrade,
+		PostDispatchInfo, Weight,
+	},
+	system::{ensure_root, ensure_signed, BadOrigin},
+};
+use num::traits::{CheckedAdd, CheckedSub, SaturatingAdd, SaturatingMul, Zero};
 
 pub trait Config: crate::system::Config {
-	type Balance: CheckedAdd + CheckedSub + Zero + Copy;
+	type Balance: CheckedAdd
+		+ CheckedSub
+		+ SaturatingAdd
+		+ SaturatingMul
+		+ Zero
+		+ From<u64>
+		+ Copy
+		+ Default
+		+ Encode
+		+ Decode;
+	// The fee charged for every byte of an extrinsic.
+	const BYTE_FEE: Self::Balance;
+	// The fee charged for every unit of weight of the call of an extrinsic.
+	const WEIGHT_FEE: Self::Balance;
+	// The account receiving the fees paid for extrinsics. When `None`, the fees are burned.
+	fn fee_recipient() -> Option<Self::AccountId>;
 }
 
-#[derive(Debug)]
+// The weight of a transfer to an account which does not exist yet.
+const TRANSFER_WEIGHT: Weight = 10_000;
+// The weight of a transfer to an existing account, which is cheaper since no account is created.
+const TRANSFER_EXISTING_WEIGHT: Weight = 7_500;
+
+// The events which can be emitted by the calls of this pallet.
+#[derive(Debug, Clone, PartialEq, macros::EnumMetadata)]
+pub enum Event<T: Config> {
+	/// An amount of funds was transferred between two accounts.
+	Transfer { from: T::AccountId, to: T::AccountId, amount: T::Balance },
+}
+
+// The errors which can be returned by the calls of this pallet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, macros::EnumMetadata)]
+pub enum Error {
+	/// The sender does not have enough funds to make the transfer.
+	InsufficientBalance,
+	/// The transfer would overflow the balance of the receiver.
+	Overflow,
+	/// The call was made from an origin it does not accept.
+	BadOrigin,
+}
+
+impl crate::support::PalletError for Error {
+	fn error_index(&self) -> u8 {
+		*self as u8
+	}
+}
+
+impl From<BadOrigin> for Error {
+	fn from(_: BadOrigin) -> Self {
+		Error::BadOrigin
+	}
+}
+
+impl core::fmt::Display for Error {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			Error::InsufficientBalance => write!(f, "Not enough funds."),
+			Error::Overflow => write!(f, "Overflow"),
+			Error::BadOrigin => write!(f, "Bad origin."),
+		}
+	}
+}
+
+// The initial state of the balances pallet.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(
+	bound(
+		serialize = "T::AccountId: serde::Serialize, T::Balance: serde::Serialize",
+		deserialize = "T::AccountId: serde::Deserialize<'de>, T::Balance: serde::Deserialize<'de>"
+	),
+	default,
+	deny_unknown_fields
+)]
+pub struct GenesisConfig<T: Config> {
+	// The accounts which are funded from the start, with their balance.
+	pub balances: Vec<(T::AccountId, T::Balance)>,
+}
+
+impl<T: Config> Default for GenesisConfig<T> {
+	fn default() -> Self {
+		Self { balances: Vec::new() }
+	}
+}
+
+impl<T: Config> GenesisConfig<T> {
+	// Write this initial state into `pallet`. If an account is listed more than once, its last
+	// balance is kept.
+	pub fn build(&self, pallet: &mut Pallet<T>) {
+		for (who, amount) in &self.balances {
+			pallet.set_balance(who, *amount);
+		}
+	}
+}
+
+#[macros::storage]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Pallet<T: Config> {
-	balances: BTreeMap<T::AccountId, T::Balance>,
+	// The balance of every account. Accounts which are not in the map have no funds.
+	#[getter(balance)]
+	balances: StorageMap<T::AccountId, T::Balance>,
+	// The events emitted by this pallet, waiting to be collected by the runtime.
+	#[transactional]
+	events: EventQueue<Event<T>>,
 }
 
 impl<T: Config> Pallet<T> {
 	pub fn new() -> Self {
-		Self { balances: BTreeMap::new() }
+		Self { balances: StorageMap::new(), events: EventQueue::new() }
 	}
 
 	pub fn set_balance(&mut self, who: &T::AccountId, amount: T::Balance) {
 		self.balances.insert(who.clone(), amount);
 	}
 
-	pub fn balance(&self, who: &T::AccountId) -> T::Balance {
-		*self.balances.get(who).unwrap_or(&T::Balance::zero())
+	// Compute the fee of an extrinsic which is `len` bytes long, and whose call has `weight`.
+	pub fn compute_fee(len: usize, weight: Weight) -> T::Balance {
+		let length_fee = T::BYTE_FEE.saturating_mul(&T::Balance::from(len as u64));
+		let weight_fee = T::WEIGHT_FEE.saturating_mul(&T::Balance::from(weight));
+		length_fee.saturating_add(&weight_fee)
+	}
+
+	// Check if `who` has enough funds to pay `fee`.
+	pub fn can_withdraw_fee(&self, who: &T::AccountId, fee: T::Balance) -> bool {
+		self.balance(who).checked_sub(&fee).is_some()
+	}
+
+	// Withdraw the fee of an extrinsic from the account of `who`, before it is dispatched.
+	pub fn withdraw_fee(&mut self, who: &T::AccountId, fee: T::Balance) -> DispatchResult<Error> {
+		let new_balance = self.balance(who).checked_sub(&fee).ok_or(Error::InsufficientBalance)?;
+		self.balances.insert(who.clone(), new_balance);
+		Ok(())
+	}
+
+	// Settle the fee of a dispatched extrinsic: `charged` was withdrawn from `who`, but only
+	// `actual` is due. The difference is refunded to `who`, and the fee which is due is paid to
+	// the fee recipient, or burned if there is none.
+	pub fn settle_fee(&mut self, who: &T::AccountId, charged: T::Balance, actual: T::Balance) {
+		let refund = charged.checked_sub(&actual).unwrap_or(T::Balance::zero());
+		let paid = charged.checked_sub(&refund).unwrap_or(T::Balance::zero());
+		self.deposit(who, refund);
+		if let Some(recipient) = T::fee_recipient() {
+			self.deposit(&recipient, paid);
+		}
+	}
+
+	// Add `amount` to the balance of `who`.
+	fn deposit(&mut self, who: &T::AccountId, amount: T::Balance) {
+		if !amount.is_zero() {
+			let new_balance = self.balance(who).saturating_add(&amount);
+			self.balances.insert(who.clone(), new_balance);
+		}
+	}
+
+	// Take all the events emitted by this pallet since they were last taken.
+	pub fn take_events(&mut self) -> Vec<Event<T>> {
+		self.events.take()
+	}
+
+	// Emit an event from this pallet.
+	fn deposit_event(&mut self, event: Event<T>) {
+		self.events.deposit(event);
 	}
 }
 
+// Balances only change through calls, so there is nothing to do on every block.
+impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {}
+
+// The storage of balances never changed its layout, so there is nothing to migrate.
+impl<T: Config> OnRuntimeUpgrade for Pallet<T> {}
+
 #[macros::call]
 impl<T: Config> Pallet<T> {
+	/// Transfer `amount` from the signed account of `origin` to `to`.
+	/// The declared weight assumes that the account of `to` is created, and the unused weight is
+	/// refunded if it already exists.
+	#[call_index(0)]
+	#[weight(TRANSFER_WEIGHT)]
 	pub fn transfer(
 		&mut self,
-		caller: T::AccountId,
+		origin: T::RuntimeOrigin,
 		to: T::AccountId,
 		amount: T::Balance,
-	) -> crate::support::DispatchResult {
+	) -> DispatchResultWithPostInfo<Error> {
+		let caller = ensure_signed(origin)?;
+		let actual_weight = if self.balances.contains_key(&to) {
+			TRANSFER_EXISTING_WEIGHT
+		} else {
+			TRANSFER_WEIGHT
+		};
 		let caller_balance = self.balance(&caller);
 		let to_balance = self.balance(&to);
 
-		let new_caller_balance = caller_balance.checked_sub(&amount).ok_or("Not enough funds.")?;
-		let new_to_balance = to_balance.checked_add(&amount).ok_or("Overflow")?;
+		let new_caller_balance =
+			caller_balance.checked_sub(&amount).ok_or(Error::InsufficientBalance)?;
+		let new_to_balance = to_balance.checked_add(&amount).ok_or(Error::Overflow)?;
 
-		self.balances.insert(caller, new_caller_balance);
-		self.balances.insert(to, new_to_balance);
+		self.balances.insert(caller.clone(), new_caller_balance);
+		self.balances.insert(to.clone(), new_to_balance);
 
+		self.deposit_event(Event::Transfer { from: caller, to, amount });
+		Ok(PostDispatchInfo { actual_weight: Some(actual_weight) })
+	}
+
+	/// Set the balance of `who` to `amount`. Only the root can make this call.
+	#[call_index(1)]
+	#[weight(5_000)]
+	pub fn force_set_balance(
+		&mut self,
+		origin: T::RuntimeOrigin,
+		who: T::AccountId,
+		amount: T::Balance,
+	) -> DispatchResult<Error> {
+		ensure_root(origin)?;
+		self.set_balance(&who, amount);
 		Ok(())
 	}
 }
 
 #[cfg(test)]
 mod tests {
+	use crate::{support::PostDispatchInfo, system::RawOrigin};
+
+	#[derive(Debug, Clone, PartialEq)]
 	struct TestConfig;
 
 	impl crate::system::Config for TestConfig {
 		type AccountId = String;
 		type BlockNumber = u32;
 		type Nonce = u32;
+		type RuntimeOrigin = crate::system::RawOrigin<String>;
+		type RuntimeEvent = ();
+		const MAX_BLOCK_WEIGHT: u64 = u64::MAX;
+		const VERSION: crate::support::RuntimeVersion =
+			crate::support::RuntimeVersion { spec_name: "test", spec_version: 1 };
 	}
 
 	impl super::Config for TestConfig {
 		type Balance = u128;
+		const BYTE_FEE: u128 = 2;
+		const WEIGHT_FEE: u128 = 1;
+		fn fee_recipient() -> Option<String> {
+			Some("treasury".to_string())
+		}
 	}
 
 	#[test]
@@ -69,23 +265,102 @@ mod tests {
 		assert_eq!(balances.balance(&"bob".to_string()), 0);
 	}
 
+	#[test]
 	fn transfer_balance() {
 		let mut balances = super::Pallet::<TestConfig>::new();
 
 		// Transfer will fail because Alice's balance is 0
 		assert_eq!(
-			balances.transfer("alice".to_string(), "bob".to_string(), 51),
-			Err("Not enough funds.")
+			balances.transfer(RawOrigin::Signed("alice".to_string()), "bob".to_string(), 51),
+			Err(super::Error::InsufficientBalance)
 		);
 
 		balances.set_balance(&"alice".to_string(), 100);
-		assert_eq!(balances.transfer("alice".to_string(), "bob".to_string(), 51), Ok(()));
+		assert_eq!(
+			balances.transfer(RawOrigin::Signed("alice".to_string()), "bob".to_string(), 51),
+			Ok(PostDispatchInfo { actual_weight: Some(super::TRANSFER_WEIGHT) })
+		);
 		assert_eq!(balances.balance(&"alice".to_string()), 49);
 		assert_eq!(balances.balance(&"bob".to_string()), 51);
+		assert_eq!(
+			balances.take_events(),
+			vec![super::Event::Transfer {
+				from: "alice".to_string(),
+				to: "bob".to_string(),
+				amount: 51
+			}]
+		);
+
+		// Transfers to an existing account use less weight.
+		assert_eq!(
+			balances.transfer(RawOrigin::Signed("alice".to_string()), "bob".to_string(), 0),
+			Ok(PostDispatchInfo { actual_weight: Some(super::TRANSFER_EXISTING_WEIGHT) })
+		);
+		balances.take_events();
+
+		balances.set_balance(&"bob".to_string(), u128::MAX);
+		assert_eq!(
+			balances.transfer(RawOrigin::Signed("alice".to_string()), "bob".to_string(), 1),
+			Err(super::Error::Overflow)
+		);
+
+		assert_eq!(
+			balances.transfer(RawOrigin::Signed("alice".to_string()), "bob".to_string(), 100),
+			Err(super::Error::InsufficientBalance)
+		);
+		assert_eq!(balances.take_events(), vec![]);
+	}
+
+	#[test]
+	fn charge_fees() {
+		let mut balances = super::Pallet::<TestConfig>::new();
+		let alice = "alice".to_string();
+		let treasury = "treasury".to_string();
+		balances.set_balance(&alice, 1_000);
+
+		// Each byte costs 2, and each unit of weight costs 1.
+		let fee = super::Pallet::<TestConfig>::compute_fee(100, 300);
+		assert_eq!(fee, 500);
+
+		// The fee cannot be withdrawn if alice cannot pay it.
+		assert_eq!(balances.withdraw_fee(&alice, 1_001), Err(super::Error::InsufficientBalance));
+		assert_eq!(balances.withdraw_fee(&alice, fee), Ok(()));
+		assert_eq!(balances.balance(&alice), 500);
+
+		// Only the fee which is due is paid to the treasury, and the rest is refunded.
+		balances.settle_fee(&alice, fee, 400);
+		assert_eq!(balances.balance(&alice), 600);
+		assert_eq!(balances.balance(&treasury), 400);
+
+		// Nothing is refunded if more is due than what was charged.
+		assert_eq!(balances.withdraw_fee(&alice, 100), Ok(()));
+		balances.settle_fee(&alice, 100, 200);
+		assert_eq!(balances.balance(&alice), 500);
+		assert_eq!(balances.balance(&treasury), 500);
+	}
+
+	#[test]
+	fn only_root_can_force_set_balance() {
+		let mut balances = super::Pallet::<TestConfig>::new();
+		let alice = "alice".to_string();
+
+		assert_eq!(
+			balances.force_set_balance(RawOrigin::Signed(alice.clone()), alice.clone(), 100),
+			Err(super::Error::BadOrigin)
+		);
+		assert_eq!(
+			balances.force_set_balance(RawOrigin::None, alice.clone(), 100),
+			Err(super::Error::BadOrigin)
+		);
+		assert_eq!(balances.balance(&alice), 0);
+
+		assert_eq!(balances.force_set_balance(RawOrigin::Root, alice.clone(), 100), Ok(()));
+		assert_eq!(balances.balance(&alice), 100);
 
+		// Transfers must be made by a signed account.
 		assert_eq!(
-			balances.transfer("alice".to_string(), "bob".to_string(), 100),
-			Err("Not enough funds.")
+			balances.transfer(RawOrigin::Root, alice.clone(), 10),
+			Err(super::Error::BadOrigin)
 		);
 	}
 }
@@ -0,0 +1,186 @@
+use crate::{
+	support::{self, BlockError},
+	types, Runtime,
+};
+
+// Builds a new block on top of the last block executed by a runtime.
+//
+// The extrinsics are applied one by one to a copy of the runtime, so the state root of the block is
+// known once they are all applied. An extrinsic which cannot be included, for example because its
+// nonce was already used or because the block is full, is skipped without changing the state.
+pub struct BlockBuilder {
+	// The copy of the runtime the extrinsics of the block are applied to.
+	runtime: Runtime,
+	header: types::Header,
+	extrinsics: Vec<types::Extrinsic>,
+}
+
+impl BlockBuilder {
+	// Start a new block, following the last block executed by `runtime`.
+	pub fn new(runtime: &Runtime) -> Self {
+		let header = support::Header {
+			parent_hash: runtime.system.parent_hash(),
+			block_number: runtime.system.block_number() + 1,
+			state_root: support::Hash::default(),
+			extrinsics_root: support::Hash::default(),
+		};
+		let mut runtime = runtime.clone();
+		runtime
+			.initialize_block(&header)
+			.expect("the header follows the last executed block");
+		Self { runtime, header, extrinsics: Vec::new() }
+	}
+
+	// Apply `extrinsic` after the extrinsics already in the block, and include it in the block.
+	//
+	// Returns the receipt of the extrinsic, or an error, without including the extrinsic, if it
+	// cannot be applied.
+	pub fn push(
+		&mut self,
+		extrinsic: types::Extrinsic,
+	) -> Result<types::ExtrinsicReceipt, BlockError> {
+		let index = self.extrinsics.len() as u32;
+		let receipt = self.runtime.apply_extrinsic(index, extrinsic.clone())?;
+		self.extrinsics.push(extrinsic);
+		Ok(receipt)
+	}
+
+	// Push every extrinsic of `extrinsics`, in order, like the ready extrinsics of a transaction
+	// pool. Returns the extrinsics which were skipped, along with the reason why.
+	pub fn push_all(
+		&mut self,
+		extrinsics: impl IntoIterator<Item = types::Extrinsic>,
+	) -> Vec<(types::Extrinsic, BlockError)> {
+		extrinsics
+			.into_iter()
+			.filter_map(|extrinsic| self.push(extrinsic.clone()).err().map(|e| (extrinsic, e)))
+			.collect()
+	}
+
+	// Finish the block: run the `on_finalize` hooks, and compute the roots of the header.
+	pub fn build(mut self) -> types::Block {
+		self.runtime.finalize_block();
+		self.header.extrinsics_root = support::extrinsics_root(&self.extrinsics);
+		self.header.state_root = self.runtime.state_root();
+		types::Block { header: self.header, extrinsics: self.extrinsics }
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::BlockBuilder;
+	use crate::{
+		balances,
+		crypto::Pair,
+		support::{self, BlockError},
+		system,
+		transaction_pool::{PoolLimits, TransactionPool},
+		types, Runtime, RuntimeCall,
+	};
+
+	fn transfer(runtime: &Runtime, signer: &Pair, nonce: u32, amount: u128) -> types::Extrinsic {
+		let to = Pair::from_seed("bob").account_id();
+		let call = RuntimeCall::balances(balances::Call::transfer { to, amount });
+		support::Extrinsic::new_signed(signer, nonce, call, &runtime.system.signing_context())
+	}
+
+	#[test]
+	fn invalid_extrinsics_are_skipped() {
+		let alice = Pair::from_seed("alice");
+		let charlie = Pair::from_seed("charlie");
+		let mut runtime = crate::dev_genesis().build();
+		runtime.system.inc_block_number();
+		let mut forged = transfer(&runtime, &charlie, 0, 1);
+		forged.signer = alice.public();
+
+		let mut builder = BlockBuilder::new(&runtime);
+		let skipped = builder.push_all(vec![
+			transfer(&runtime, &alice, 0, 10),
+			forged,
+			transfer(&runtime, &alice, 0, 10),
+			transfer(&runtime, &alice, 2, 10),
+			transfer(&runtime, &charlie, 0, 10),
+			transfer(&runtime, &alice, 1, 20),
+		]);
+		let errors = skipped.into_iter().map(|(_, e)| e).collect::<Vec<_>>();
+		assert_eq!(
+			errors,
+			vec![
+				BlockError::BadSignature,
+				BlockError::StaleNonce,
+				BlockError::FutureNonce,
+				BlockError::CannotPayFees
+			]
+		);
+
+		// The header follows the last block, and the block only contains the valid extrinsics.
+		let block = builder.build();
+		assert_eq!(block.header.block_number, 2);
+		assert_eq!(block.header.parent_hash, runtime.system.parent_hash());
+		assert_eq!(
+			block.extrinsics,
+			vec![transfer(&runtime, &alice, 0, 10), transfer(&runtime, &alice, 1, 20)]
+		);
+		assert!(runtime.execute_block(block).is_ok());
+		assert_eq!(runtime.balances.balance(&Pair::from_seed("bob").account_id()), 30);
+	}
+
+	#[test]
+	fn full_blocks_skip_extrinsics() {
+		let alice = Pair::from_seed("alice");
+		let mut runtime = Runtime::new();
+		runtime.balances.set_balance(&alice.account_id(), u128::MAX / 2);
+		let weight = transfer(&runtime, &alice, 0, 1).call.weight();
+		let max_transfers = <Runtime as system::Config>::MAX_BLOCK_WEIGHT / weight;
+
+		let mut builder = BlockBuilder::new(&runtime);
+		for nonce in 0..max_transfers as u32 {
+			assert!(builder.push(transfer(&runtime, &alice, nonce, 1)).is_ok());
+		}
+		let next = transfer(&runtime, &alice, max_transfers as u32, 1);
+		assert_eq!(builder.push(next), Err(BlockError::ExhaustsResources));
+		let block = builder.build();
+		assert_eq!(block.extrinsics.len(), max_transfers as usize);
+		assert!(runtime.execute_block(block).is_ok());
+	}
+
+	#[test]
+	fn built_chain_is_replayed_by_a_fresh_runtime() {
+		let alice = Pair::from_seed("alice");
+		let mut runtime = crate::dev_genesis().build();
+		let mut pool = TransactionPool::new(PoolLimits::default());
+
+		// Extrinsics are submitted out of order, so some of them only become ready later.
+		let mut blocks = Vec::new();
+		let rounds = vec![
+			vec![transfer(&runtime, &alice, 1, 100)],
+			vec![transfer(&runtime, &alice, 0, 200)],
+			vec![transfer(&runtime, &alice, 2, 300)],
+			vec![],
+		];
+		for extrinsics in rounds {
+			for extrinsic in extrinsics {
+				pool.submit(&runtime, extrinsic).unwrap();
+			}
+			let mut builder = BlockBuilder::new(&runtime);
+			builder.push_all(pool.ready().into_iter().cloned());
+			let block = builder.build();
+			runtime.execute_block(block.clone()).unwrap();
+			pool.on_block_imported(&runtime);
+			blocks.push(block);
+		}
+		assert_eq!(runtime.system.block_number(), 4);
+		assert!(blocks[0].extrinsics.is_empty());
+		assert_eq!(blocks[1].extrinsics.len(), 2);
+		assert_eq!(runtime.balances.balance(&Pair::from_seed("bob").account_id()), 600);
+		assert_eq!(runtime.system.nonce(&alice.account_id()), 3);
+
+		// A new node replaying the chain from the genesis ends up in the same state.
+		let mut replay = crate::dev_genesis().build();
+		for block in blocks {
+			assert!(replay.execute_block(block).is_ok());
+		}
+		assert_eq!(replay.state_root(), runtime.state_root());
+		assert_eq!(replay.system.parent_hash(), runtime.system.parent_hash());
+	}
+}
@@ -0,0 +1,452 @@
+use crate::{
+	support::{BlockError, Hash},
+	types, Runtime,
+};
+use std::collections::BTreeMap;
+
+// A tree of blocks, following every fork built on top of a starting state.
+//
+// Each imported block is executed on top of the state after its parent, and the resulting state is
+// kept along with the block. The fork choice rule then decides which block is the head of the
+// canonical chain. When the head moves to another fork, the canonical state is the state after the
+// new head, which was computed by executing the blocks of that fork.
+//
+// The canonical block `FINALITY_DEPTH` blocks below the head is final: the head never moves to a
+// fork branching off below it. So the forks which do are dropped, along with the states after the
+// blocks below it, and the tree only keeps the states of the last blocks of each fork.
+
+// The number of blocks after which a canonical block is final.
+pub const FINALITY_DEPTH: types::BlockNumber = 64;
+
+// The rule choosing the head of the canonical chain among the leaves of the tree.
+//
+// When two chains are equally good, the head stays on the chain which was seen first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForkChoice {
+	// The chain with the highest block number.
+	LongestChain,
+	// The chain whose blocks consumed the most weight in total, or the longest one among chains of
+	// the same weight.
+	HeaviestChain,
+}
+
+impl core::str::FromStr for ForkChoice {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"longest" => Ok(ForkChoice::LongestChain),
+			"heaviest" => Ok(ForkChoice::HeaviestChain),
+			_ => Err(format!("unknown fork choice `{}`, expected `longest` or `heaviest`", s)),
+		}
+	}
+}
+
+// The reasons why a block cannot be imported into the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportError {
+	// The block is already in the tree.
+	AlreadyImported,
+	// The parent of the block is not in the tree.
+	UnknownParent,
+	// The parent of the block is below the last final block, so the block can never be canonical.
+	BelowFinality,
+	// The block is rejected by the runtime, on top of the state after its parent.
+	Invalid(BlockError),
+}
+
+impl core::fmt::Display for ImportError {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			ImportError::AlreadyImported => write!(f, "block is already imported"),
+			ImportError::UnknownParent => write!(f, "parent of the block is not imported"),
+			ImportError::BelowFinality => write!(f, "block forks off below the final block"),
+			ImportError::Invalid(e) => write!(f, "invalid block: {}", e),
+		}
+	}
+}
+
+// A change of the canonical chain to another fork.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reorg {
+	// The blocks which are no longer canonical, from the old head down to the common ancestor.
+	pub retracted: Vec<Hash>,
+	// The blocks which became canonical, from the common ancestor up to the new head.
+	pub enacted: Vec<Hash>,
+}
+
+// The outcome of importing a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportOutcome {
+	// The receipt of the execution of the block.
+	pub receipt: types::BlockReceipt,
+	// Whether the block is the new head of the canonical chain.
+	pub is_best: bool,
+	// The blocks retracted and enacted, if the new head is not a child of the old one.
+	pub reorg: Option<Reorg>,
+}
+
+// A block of the tree, along with the state after executing it. The state is dropped once the
+// block is below the last final block.
+struct TreeEntry {
+	// The block, or `None` for the root of the tree, whose block is not known.
+	block: Option<types::Block>,
+	parent_hash: Hash,
+	block_number: types::BlockNumber,
+	// The weight consumed by the blocks of the chain since the root, including this one.
+	total_weight: u128,
+	state: Option<Runtime>,
+}
+
+// A tree of blocks, built on top of a root state.
+pub struct BlockTree {
+	fork_choice: ForkChoice,
+	// The number of blocks after which a canonical block is final, which is `FINALITY_DEPTH`.
+	finality_depth: types::BlockNumber,
+	entries: BTreeMap<Hash, TreeEntry>,
+	best: Hash,
+	// The last final block.
+	finalized: Hash,
+}
+
+impl BlockTree {
+	// Create a tree whose root is the current state of `runtime`. Imported blocks must descend from
+	// the last block executed by `runtime`.
+	pub fn new(runtime: Runtime, fork_choice: ForkChoice) -> Self {
+		let root = runtime.system.parent_hash();
+		let entry = TreeEntry {
+			block: None,
+			parent_hash: Hash::default(),
+			block_number: runtime.system.block_number(),
+			total_weight: 0,
+			state: Some(runtime),
+		};
+		Self {
+			fork_choice,
+			finality_depth: FINALITY_DEPTH,
+			entries: BTreeMap::from([(root, entry)]),
+			best: root,
+			finalized: root,
+		}
+	}
+
+	// The hash of the head of the canonical chain.
+	pub fn best_hash(&self) -> Hash {
+		self.best
+	}
+
+	// The state after the head of the canonical chain.
+	pub fn best(&self) -> &Runtime {
+		self.state(&self.best).expect("the head is never below the final block")
+	}
+
+	// The state after the block with `hash`, if it is in the tree and not below the final block.
+	pub fn state(&self, hash: &Hash) -> Option<&Runtime> {
+		self.entries.get(hash)?.state.as_ref()
+	}
+
+	// The blocks of the canonical chain, from the first block after the root up to the head.
+	pub fn best_chain(&self) -> Vec<&types::Block> {
+		let mut chain = Vec::new();
+		let mut entry = &self.entries[&self.best];
+		while let Some(block) = &entry.block {
+			chain.push(block);
+			entry = &self.entries[&entry.parent_hash];
+		}
+		chain.reverse();
+		chain
+	}
+
+	// Execute `block` on top of the state after its parent, and add it to the tree.
+	//
+	// The block becomes the head of the canonical chain if the fork choice rule prefers its chain
+	// to the current one.
+	pub fn import(&mut self, block: types::Block) -> Result<ImportOutcome, ImportError> {
+		let hash = block.header.hash();
+		if self.entries.contains_key(&hash) {
+			return Err(ImportError::AlreadyImported);
+		}
+		let parent_hash = block.header.parent_hash;
+		let parent = self.entries.get(&parent_hash).ok_or(ImportError::UnknownParent)?;
+		let mut state = parent.state.clone().ok_or(ImportError::BelowFinality)?;
+		let receipt = state.execute_block(block.clone()).map_err(ImportError::Invalid)?;
+		let entry = TreeEntry {
+			block_number: block.header.block_number,
+			block: Some(block),
+			parent_hash,
+			total_weight: parent.total_weight + state.system.block_weight() as u128,
+			state: Some(state),
+		};
+		self.entries.insert(hash, entry);
+
+		let is_best = self.score(&hash) > self.score(&self.best);
+		let mut reorg = None;
+		if is_best {
+			if parent_hash != self.best {
+				reorg = Some(self.route(self.best, hash));
+			}
+			self.best = hash;
+			self.finalize();
+		}
+		Ok(ImportOutcome { receipt, is_best, reorg })
+	}
+
+	// Make final the canonical block `finality_depth` blocks below the head. The states of the
+	// blocks below it are dropped, along with the forks which do not descend from it.
+	fn finalize(&mut self) {
+		let best_number = self.entries[&self.best].block_number;
+		let mut finalized = self.best;
+		while self.entries[&finalized].block_number + self.finality_depth > best_number {
+			match self.entries[&finalized].block {
+				Some(_) => finalized = self.entries[&finalized].parent_hash,
+				None => break,
+			}
+		}
+		if finalized == self.finalized {
+			return;
+		}
+		self.finalized = finalized;
+
+		// The canonical chain up to the final block is kept, without its states.
+		let finalized_number = self.entries[&finalized].block_number;
+		let mut hash = self.entries[&finalized].parent_hash;
+		while let Some(entry) = self.entries.get_mut(&hash) {
+			if entry.state.take().is_none() {
+				break;
+			}
+			hash = entry.parent_hash;
+		}
+		// Every other block must be an ancestor of the final block, or descend from it.
+		let dropped = self
+			.entries
+			.iter()
+			.filter(|(hash, entry)| match entry.block_number < finalized_number {
+				true => self.ancestor(finalized, entry.block_number) != **hash,
+				false => self.ancestor(**hash, finalized_number) != finalized,
+			})
+			.map(|(hash, _)| *hash)
+			.collect::<Vec<_>>();
+		for hash in dropped {
+			self.entries.remove(&hash);
+		}
+	}
+
+	// The ancestor of the block with `hash` whose number is `block_number`.
+	fn ancestor(&self, mut hash: Hash, block_number: types::BlockNumber) -> Hash {
+		while self.entries[&hash].block_number > block_number {
+			hash = self.entries[&hash].parent_hash;
+		}
+		hash
+	}
+
+	// How good the chain ending at the block with `hash` is, according to the fork choice rule.
+	fn score(&self, hash: &Hash) -> (u128, types::BlockNumber) {
+		let entry = &self.entries[hash];
+		match self.fork_choice {
+			ForkChoice::LongestChain => (entry.block_number as u128, 0),
+			// Empty blocks consume no weight, but still extend the chain.
+			ForkChoice::HeaviestChain => (entry.total_weight, entry.block_number),
+		}
+	}
+
+	// The blocks to retract and enact to move the head of the canonical chain from `from` to `to`.
+	fn route(&self, mut from: Hash, mut to: Hash) -> Reorg {
+		let mut retracted = Vec::new();
+		let mut enacted = Vec::new();
+		while from != to {
+			// Step back from the highest of both blocks, until they meet at their common ancestor.
+			let (from_entry, to_entry) = (&self.entries[&from], &self.entries[&to]);
+			if from_entry.block_number >= to_entry.block_number {
+				retracted.push(from);
+				from = from_entry.parent_hash;
+			} else {
+				enacted.push(to);
+				to = to_entry.parent_hash;
+			}
+		}
+		enacted.reverse();
+		Reorg { retracted, enacted }
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{BlockTree, ForkChoice, ImportError, Reorg};
+	use crate::{
+		balances, block_builder::BlockBuilder, crypto::Pair, proof_of_existence, support, types,
+		RuntimeCall,
+	};
+
+	// Build a block on top of the block with `parent` in `tree`, including `calls` signed by alice.
+	fn block(tree: &BlockTree, parent: support::Hash, calls: Vec<RuntimeCall>) -> types::Block {
+		let alice = Pair::from_seed("alice");
+		let state = tree.state(&parent).expect("the parent is in the tree");
+		let mut builder = BlockBuilder::new(state);
+		let nonce = state.system.nonce(&alice.account_id());
+		let context = state.system.signing_context();
+		for (i, call) in calls.into_iter().enumerate() {
+			builder
+				.push(support::Extrinsic::new_signed(&alice, nonce + i as u32, call, &context))
+				.unwrap();
+		}
+		builder.build()
+	}
+
+	fn transfer(amount: u128) -> RuntimeCall {
+		let to = Pair::from_seed("bob").account_id();
+		RuntimeCall::balances(balances::Call::transfer { to, amount })
+	}
+
+	fn claim(claim: &str) -> RuntimeCall {
+		let claim = claim.to_string();
+		RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim { claim })
+	}
+
+	#[test]
+	fn longest_chain_reorgs() {
+		let bob = Pair::from_seed("bob").account_id();
+		let genesis = crate::dev_genesis().build();
+		let root = genesis.system.parent_hash();
+		let mut tree = BlockTree::new(genesis, ForkChoice::LongestChain);
+
+		// Two competing blocks at height 1. The first one seen stays the head.
+		let a1 = block(&tree, root, vec![transfer(100), claim("a")]);
+		let b1 = block(&tree, root, vec![transfer(200)]);
+		let (a1_hash, b1_hash) = (a1.header.hash(), b1.header.hash());
+		assert!(tree.import(a1.clone()).unwrap().is_best);
+		let outcome = tree.import(b1).unwrap();
+		assert!(!outcome.is_best && outcome.reorg.is_none());
+		assert_eq!(tree.best_hash(), a1_hash);
+		assert_eq!(tree.best().balances.balance(&bob), 100);
+
+		// Extending the second fork makes it the longest chain, so the head moves to it.
+		let b2 = block(&tree, b1_hash, vec![claim("b")]);
+		let b2_hash = b2.header.hash();
+		let outcome = tree.import(b2).unwrap();
+		assert!(outcome.is_best);
+		assert_eq!(
+			outcome.reorg,
+			Some(Reorg { retracted: vec![a1_hash], enacted: vec![b1_hash, b2_hash] })
+		);
+		assert_eq!(tree.best().balances.balance(&bob), 200);
+		assert_eq!(tree.best().proof_of_existence.get_claim(&"a".to_string()), None);
+		assert!(tree.best().proof_of_existence.get_claim(&"b".to_string()).is_some());
+
+		// And back to the first fork once it is longer again.
+		let a2 = block(&tree, a1_hash, vec![]);
+		let a2_hash = a2.header.hash();
+		tree.import(a2).unwrap();
+		let a3 = block(&tree, a2_hash, vec![transfer(5)]);
+		let a3_hash = a3.header.hash();
+		let outcome = tree.import(a3).unwrap();
+		assert_eq!(
+			outcome.reorg,
+			Some(Reorg {
+				retracted: vec![b2_hash, b1_hash],
+				enacted: vec![a1_hash, a2_hash, a3_hash]
+			})
+		);
+		assert_eq!(tree.best().system.block_number(), 3);
+		let best_chain = tree.best_chain().into_iter().map(|block| block.header.hash());
+		assert_eq!(best_chain.collect::<Vec<_>>(), vec![a1_hash, a2_hash, a3_hash]);
+		assert_eq!(tree.best().balances.balance(&bob), 105);
+		assert!(tree.best().proof_of_existence.get_claim(&"a".to_string()).is_some());
+		assert_eq!(tree.best().proof_of_existence.get_claim(&"b".to_string()), None);
+
+		// The canonical state is the one a fresh runtime gets by replaying the canonical chain.
+		let mut replay = crate::dev_genesis().build();
+		for block in tree.best_chain() {
+			replay.execute_block(block.clone()).unwrap();
+		}
+		assert_eq!(replay.state_root(), tree.best().state_root());
+
+		// Blocks must be new, valid, and build on a known block.
+		assert_eq!(tree.import(a1), Err(ImportError::AlreadyImported));
+		let mut orphan = block(&tree, a3_hash, vec![]);
+		orphan.header.parent_hash = [1; 32];
+		assert_eq!(tree.import(orphan), Err(ImportError::UnknownParent));
+		let mut invalid = block(&tree, a3_hash, vec![]);
+		invalid.header.state_root = support::Hash::default();
+		assert_eq!(
+			tree.import(invalid),
+			Err(ImportError::Invalid(support::BlockError::StateRootMismatch))
+		);
+		assert_eq!(tree.best_hash(), a3_hash);
+	}
+
+	#[test]
+	fn final_blocks_are_pruned() {
+		let genesis = crate::dev_genesis().build();
+		let root = genesis.system.parent_hash();
+		let mut tree = BlockTree::new(genesis, ForkChoice::LongestChain);
+		tree.finality_depth = 2;
+
+		// A canonical chain of 3 blocks, so block 1 is final, and a fork off block 1.
+		let mut canonical = vec![root];
+		for _ in 0..3 {
+			let block = block(&tree, *canonical.last().unwrap(), vec![]);
+			canonical.push(block.header.hash());
+			tree.import(block).unwrap();
+		}
+		assert_eq!(tree.finalized, canonical[1]);
+		assert!(tree.state(&root).is_none());
+		let fork = block(&tree, canonical[1], vec![claim("fork")]);
+		let fork_hash = fork.header.hash();
+		assert!(!tree.import(fork).unwrap().is_best);
+		assert!(tree.state(&fork_hash).is_some());
+
+		// Once the head is 2 blocks above block 2, block 2 is final. The states below it are
+		// dropped, and so is the fork, which can never become canonical.
+		let block_4 = block(&tree, canonical[3], vec![]);
+		tree.import(block_4.clone()).unwrap();
+		assert_eq!(tree.finalized, canonical[2]);
+		assert!(tree.state(&canonical[0]).is_none());
+		assert!(tree.state(&canonical[1]).is_none());
+		assert!(tree.state(&canonical[2]).is_some());
+		assert!(tree.state(&fork_hash).is_none());
+		assert_eq!(tree.entries.len(), 5);
+		assert_eq!(tree.best_chain().len(), 4);
+
+		// Blocks may still fork off the final block, but not below it.
+		let block_3 = block(&tree, canonical[2], vec![claim("above")]);
+		assert!(!tree.import(block_3).unwrap().is_best);
+		let mut below = block_4;
+		below.header.parent_hash = canonical[1];
+		assert_eq!(tree.import(below), Err(ImportError::BelowFinality));
+	}
+
+	#[test]
+	fn heaviest_chain_prefers_weight_over_length() {
+		let genesis = crate::dev_genesis().build();
+		let root = genesis.system.parent_hash();
+		let mut longest = BlockTree::new(genesis.clone(), ForkChoice::LongestChain);
+		let mut heaviest = BlockTree::new(genesis, ForkChoice::HeaviestChain);
+
+		// A single heavy block, competing with two empty blocks.
+		let heavy = block(&longest, root, vec![transfer(1), transfer(2), claim("heavy")]);
+		let empty_1 = block(&longest, root, vec![]);
+		for tree in [&mut longest, &mut heaviest] {
+			tree.import(heavy.clone()).unwrap();
+			tree.import(empty_1.clone()).unwrap();
+		}
+		let empty_2 = block(&longest, empty_1.header.hash(), vec![]);
+		for tree in [&mut longest, &mut heaviest] {
+			assert_eq!(
+				tree.import(empty_2.clone()).unwrap().is_best,
+				tree.fork_choice == ForkChoice::LongestChain
+			);
+		}
+		assert_eq!(longest.best_hash(), empty_2.header.hash());
+		assert_eq!(heaviest.best_hash(), heavy.header.hash());
+
+		// An empty block consumes no weight, but still extends the heaviest chain.
+		let empty_3 = block(&heaviest, heavy.header.hash(), vec![]);
+		let outcome = heaviest.import(empty_3.clone()).unwrap();
+		assert!(outcome.is_best && outcome.reorg.is_none());
+		assert_eq!(heaviest.best_hash(), empty_3.header.hash());
+
+		assert_eq!("longest".parse(), Ok(ForkChoice::LongestChain));
+		assert_eq!("heaviest".parse(), Ok(ForkChoice::HeaviestChain));
+		assert!("shortest".parse::<ForkChoice>().is_err());
+	}
+}
@@ -0,0 +1,98 @@
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+// A chain spec describes the initial state of a chain, so a network can be reproduced from a file
+// instead of recompiling the node. Its content is the `RuntimeGenesisConfig` of the runtime.
+
+// The formats a chain spec can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	Json,
+	Toml,
+}
+
+impl Format {
+	// Guess the format of the chain spec at `path` from its extension.
+	pub fn from_path(path: &Path) -> Option<Self> {
+		match path.extension()?.to_str()? {
+			"json" => Some(Format::Json),
+			"toml" => Some(Format::Toml),
+			_ => None,
+		}
+	}
+}
+
+// The error returned when a chain spec cannot be loaded.
+#[derive(Debug)]
+pub enum ChainSpecError {
+	// The file could not be read.
+	Io(std::io::Error),
+	// The extension of the file is neither `.json` nor `.toml`.
+	UnknownFormat,
+	// The file is not a valid chain spec in its format.
+	Invalid(String),
+}
+
+impl core::fmt::Display for ChainSpecError {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			ChainSpecError::Io(e) => write!(f, "cannot read the chain spec: {}", e),
+			ChainSpecError::UnknownFormat => {
+				write!(f, "the chain spec must be a `.json` or a `.toml` file")
+			},
+			ChainSpecError::Invalid(e) => write!(f, "invalid chain spec: {}", e),
+		}
+	}
+}
+
+// Parse a chain spec written in `format`.
+pub fn parse<G: DeserializeOwned>(contents: &str, format: Format) -> Result<G, ChainSpecError> {
+	match format {
+		Format::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+		Format::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+	}
+	.map_err(ChainSpecError::Invalid)
+}
+
+// Load the chain spec at `path`, whose format is given by its extension.
+pub fn load<G: DeserializeOwned>(path: &Path) -> Result<G, ChainSpecError> {
+	let format = Format::from_path(path).ok_or(ChainSpecError::UnknownFormat)?;
+	let contents = std::fs::read_to_string(path).map_err(ChainSpecError::Io)?;
+	parse(&contents, format)
+}
+
+#[cfg(test)]
+mod test {
+	use super::{load, parse, ChainSpecError, Format};
+	use std::path::Path;
+
+	#[derive(Debug, PartialEq, serde::Deserialize)]
+	struct Genesis {
+		balances: Vec<(String, u128)>,
+	}
+
+	#[test]
+	fn parse_every_format() {
+		let expected = Genesis { balances: vec![("alice".to_string(), 100)] };
+		let json = r#"{ "balances": [["alice", 100]] }"#;
+		let toml = r#"balances = [["alice", 100]]"#;
+
+		assert_eq!(parse::<Genesis>(json, Format::Json).unwrap(), expected);
+		assert_eq!(parse::<Genesis>(toml, Format::Toml).unwrap(), expected);
+		assert!(matches!(parse::<Genesis>(json, Format::Toml), Err(ChainSpecError::Invalid(_))));
+	}
+
+	#[test]
+	fn format_is_given_by_the_extension() {
+		assert_eq!(Format::from_path(Path::new("dev.json")), Some(Format::Json));
+		assert_eq!(Format::from_path(Path::new("specs/dev.toml")), Some(Format::Toml));
+		assert_eq!(Format::from_path(Path::new("dev.yaml")), None);
+		assert_eq!(Format::from_path(Path::new("dev")), None);
+
+		assert!(matches!(
+			load::<Genesis>(Path::new("dev.yaml")),
+			Err(ChainSpecError::UnknownFormat)
+		));
+		assert!(matches!(load::<Genesis>(Path::new("missing.json")), Err(ChainSpecError::Io(_))));
+	}
+}
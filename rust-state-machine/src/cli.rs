@@ -0,0 +1,399 @@
+use crate::{
+	balances,
+	block_tree::ForkChoice,
+	chain_spec,
+	codec::{self, Decode, Encode},
+	crypto::{AccountId, Pair},
+	node::Node,
+	proof_of_existence,
+	rpc::RpcServer,
+	support, types, Runtime, RuntimeCall, RuntimeGenesisConfig,
+};
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+// The command line of the node. Every command opens the chain kept in the data directory, runs
+// and exits, so a scenario can be scripted as a sequence of commands.
+
+pub const USAGE: &str = "\
+Usage: rust-state-machine [--data-dir <dir>] <command>
+
+Commands:
+  init [--genesis <chain spec>]
+  import-blocks [--fork-choice <longest|heaviest>] <file>
+  export-blocks [<file>]
+  query balance <account>
+  query claim <content>
+  sign [--nonce <nonce>] <seed> transfer <account> <amount>
+  sign [--nonce <nonce>] <seed> create-claim <content>
+  sign [--nonce <nonce>] <seed> revoke-claim <content>
+  submit <extrinsic>
+  produce-block
+  inspect-block <block number>
+  metadata
+  serve [--port <port>] [--block-time <seconds>]
+
+The data directory is `data` unless given. Accounts are 0x prefixed hex account ids, or the seed of
+a development key, like `alice`. Extrinsics are 0x prefixed hex strings, as printed by `sign`.";
+
+// A command of the node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+	// Start a new chain from a chain spec, or from the development genesis.
+	Init { genesis: Option<PathBuf> },
+	// Import the encoded blocks in a file, as written by `export-blocks`.
+	ImportBlocks { file: PathBuf, fork_choice: ForkChoice },
+	// Write the encoded blocks of the chain to a file, or to the standard output.
+	ExportBlocks { file: Option<PathBuf> },
+	QueryBalance { who: AccountId },
+	QueryClaim { claim: types::Content },
+	// Print an extrinsic making `call`, signed with the development key of `seed` for the chain
+	// in the data directory. Without a nonce, it follows the extrinsics of the signer in the chain
+	// and in the pool.
+	Sign { seed: String, nonce: Option<types::Nonce>, call: RuntimeCall },
+	// Add an extrinsic to the transaction pool.
+	Submit { extrinsic: types::Extrinsic },
+	// Produce a block with the ready extrinsics of the pool.
+	ProduceBlock,
+	InspectBlock { block_number: types::BlockNumber },
+	// Print the metadata of the runtime, as JSON.
+	Metadata,
+	// Serve JSON-RPC requests on a port of localhost, and produce a block every `block_time`
+	// seconds, until the process is stopped.
+	Serve { port: u16, block_time: u64 },
+}
+
+// A parsed command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+	pub data_dir: PathBuf,
+	pub command: Command,
+}
+
+// Parse the arguments of the command line, without the name of the binary.
+pub fn parse(args: &[String]) -> Result<Cli, String> {
+	// Options are `--name value` pairs, which can be anywhere on the command line.
+	let mut options = BTreeMap::new();
+	let mut words = Vec::new();
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		match arg.strip_prefix("--") {
+			Some(name) => {
+				let value = args.next().ok_or(format!("missing value for `{}`", arg))?;
+				if options.insert(name, value.as_str()).is_some() {
+					return Err(format!("duplicate option `{}`", arg));
+				}
+			},
+			None => words.push(arg.as_str()),
+		}
+	}
+	let data_dir = PathBuf::from(options.remove("data-dir").unwrap_or("data"));
+
+	let command = match words.as_slice() {
+		["init"] => Command::Init { genesis: options.remove("genesis").map(PathBuf::from) },
+		["import-blocks", file] => {
+			let fork_choice = match options.remove("fork-choice") {
+				Some(rule) => rule.parse()?,
+				None => ForkChoice::LongestChain,
+			};
+			Command::ImportBlocks { file: PathBuf::from(file), fork_choice }
+		},
+		["export-blocks"] => Command::ExportBlocks { file: None },
+		["export-blocks", file] => Command::ExportBlocks { file: Some(PathBuf::from(file)) },
+		["query", "balance", who] => Command::QueryBalance { who: parse_account(who)? },
+		["query", "claim", claim] => Command::QueryClaim { claim: claim.to_string() },
+		["sign", seed, call @ ..] => {
+			let nonce = options.remove("nonce").map(parse_number).transpose()?;
+			Command::Sign { seed: seed.to_string(), nonce, call: parse_call(call)? }
+		},
+		["submit", extrinsic] => {
+			let bytes = codec::from_hex(extrinsic).map_err(|e| e.to_string())?;
+			let extrinsic = types::Extrinsic::decode_all(&bytes).map_err(|e| e.to_string())?;
+			Command::Submit { extrinsic }
+		},
+		["produce-block"] => Command::ProduceBlock,
+		["inspect-block", block_number] => {
+			Command::InspectBlock { block_number: parse_number(block_number)? }
+		},
+		["metadata"] => Command::Metadata,
+		["serve"] => {
+			let port = options.remove("port").map(parse_number).transpose()?.unwrap_or(9944);
+			let block_time =
+				options.remove("block-time").map(parse_number).transpose()?.unwrap_or(6);
+			if block_time == 0 {
+				return Err("the block time is at least one second".to_string());
+			}
+			Command::Serve { port, block_time }
+		},
+		_ => return Err(USAGE.to_string()),
+	};
+	if let Some(name) = options.keys().next() {
+		return Err(format!("unexpected option `--{}`\n\n{}", name, USAGE));
+	}
+	Ok(Cli { data_dir, command })
+}
+
+// An account, given as its hex account id or as the seed of a development key.
+fn parse_account(s: &str) -> Result<AccountId, String> {
+	match s.starts_with("0x") {
+		true => s.parse().map_err(|e| format!("invalid account `{}`: {}", s, e)),
+		false => Ok(Pair::from_seed(s).account_id()),
+	}
+}
+
+fn parse_number<N: core::str::FromStr>(s: &str) -> Result<N, String> {
+	s.parse().map_err(|_| format!("invalid number `{}`", s))
+}
+
+// A call, given as its name in the command line followed by its arguments.
+fn parse_call(args: &[&str]) -> Result<RuntimeCall, String> {
+	Ok(match args {
+		["transfer", to, amount] => RuntimeCall::balances(balances::Call::transfer {
+			to: parse_account(to)?,
+			amount: parse_number(amount)?,
+		}),
+		["create-claim", claim] => {
+			RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+				claim: claim.to_string(),
+			})
+		},
+		["revoke-claim", claim] => {
+			RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
+				claim: claim.to_string(),
+			})
+		},
+		_ => return Err(format!("unknown call\n\n{}", USAGE)),
+	})
+}
+
+// Run `cli`, and write what it prints to `out`.
+pub fn run<W: Write>(cli: Cli, out: &mut W) -> Result<(), String> {
+	let open = || Node::open(&cli.data_dir).map_err(|e| e.to_string());
+	let output = match cli.command {
+		Command::Init { genesis } => {
+			let genesis: RuntimeGenesisConfig = match genesis {
+				Some(path) => chain_spec::load(&path).map_err(|e| e.to_string())?,
+				None => crate::dev_genesis(),
+			};
+			let node = Node::init(&cli.data_dir, &genesis).map_err(|e| e.to_string())?;
+			format!("Started a new chain at {}\n", head(&node))
+		},
+		Command::ImportBlocks { file, fork_choice } => {
+			let mut node = open()?;
+			let bytes = std::fs::read(&file).map_err(|e| e.to_string())?;
+			let blocks = Vec::<types::Block>::decode_all(&bytes).map_err(|e| e.to_string())?;
+			let count = node.import_blocks(blocks, fork_choice).map_err(|e| e.to_string())?;
+			format!("Imported {} blocks, the chain is at {}\n", count, head(&node))
+		},
+		Command::ExportBlocks { file } => {
+			let node = open()?;
+			let bytes = node.blocks().encode();
+			match file {
+				Some(file) => {
+					std::fs::write(&file, bytes).map_err(|e| e.to_string())?;
+					format!("Exported {} blocks\n", node.blocks().len())
+				},
+				None => return write(out, &bytes),
+			}
+		},
+		Command::QueryBalance { who } => format!("{}\n", open()?.runtime().balances.balance(&who)),
+		Command::QueryClaim { claim } => {
+			match open()?.runtime().proof_of_existence.get_claim(&claim) {
+				Some((owner, block_number)) => {
+					format!("Owned by {:?} since block {}\n", owner, block_number)
+				},
+				None => "Not claimed\n".to_string(),
+			}
+		},
+		Command::Sign { seed, nonce, call } => {
+			let pair = Pair::from_seed(&seed);
+			// The extrinsic is signed for the chain in the data directory.
+			let node = open()?;
+			let nonce = nonce.unwrap_or_else(|| node.next_nonce(&pair.account_id()));
+			let context = node.runtime().system.signing_context();
+			let extrinsic = support::Extrinsic::new_signed(&pair, nonce, call, &context);
+			format!("{}\n", codec::to_hex(&extrinsic.encode()))
+		},
+		Command::Submit { extrinsic } => {
+			let hash = open()?.submit(extrinsic).map_err(|e| e.to_string())?;
+			format!("{}\n", codec::to_hex(&hash))
+		},
+		Command::ProduceBlock => {
+			let mut node = open()?;
+			let (block, receipt) = node.produce_block().map_err(|e| e.to_string())?;
+			let mut output = produced(&block, &receipt);
+			let status = node.pool().status();
+			output.push_str(&format!(
+				"Transaction pool: {} ready, {} future\n",
+				status.ready, status.future
+			));
+			output
+		},
+		Command::InspectBlock { block_number } => {
+			let node = open()?;
+			let block = node.block(block_number).ok_or(format!("no block #{}", block_number))?;
+			let hash = codec::to_hex(&block.header.hash());
+			format!("Block #{} {}\n{:#?}\n", block_number, hash, block)
+		},
+		Command::Metadata => format!("{}\n", Runtime::metadata().to_json()),
+		// The server runs until the process is stopped, so it prints as it goes.
+		Command::Serve { port, block_time } => {
+			let server =
+				RpcServer::start(open()?, ("127.0.0.1", port)).map_err(|e| e.to_string())?;
+			let address = server.address();
+			write(out, format!("Serving JSON-RPC over HTTP and WebSocket on {}\n", address))?;
+			loop {
+				std::thread::sleep(std::time::Duration::from_secs(block_time));
+				let output = match server.produce_block() {
+					Ok((block, receipt)) => produced(&block, &receipt),
+					Err(e) => format!("Cannot produce a block: {}\n", e),
+				};
+				write(out, output)?;
+			}
+		},
+	};
+	write(out, output)
+}
+
+// Write `output` to `out` right away, since the server prints while it runs.
+fn write<W: Write>(out: &mut W, output: impl AsRef<[u8]>) -> Result<(), String> {
+	out.write_all(output.as_ref())
+		.and_then(|()| out.flush())
+		.map_err(|e| e.to_string())
+}
+
+// Describe a block which was just produced, and its failed extrinsics.
+fn produced(block: &types::Block, receipt: &types::BlockReceipt) -> String {
+	let hash = codec::to_hex(&block.header.hash());
+	let mut output = format!(
+		"Produced block #{} {} with {} extrinsics\n",
+		block.header.block_number,
+		hash,
+		block.extrinsics.len()
+	);
+	for (i, extrinsic) in receipt.extrinsics.iter().enumerate() {
+		if let Err(e) = &extrinsic.result {
+			output.push_str(&format!("Extrinsic {} failed: {}\n", i, e));
+		}
+	}
+	output
+}
+
+// The number and the hash of the last block of the chain of `node`.
+fn head(node: &Node) -> String {
+	let runtime = node.runtime();
+	let hash = codec::to_hex(&runtime.system.parent_hash());
+	format!("block #{} {}", runtime.system.block_number(), hash)
+}
+
+#[cfg(test)]
+mod test {
+	use super::{parse, run, Cli, Command};
+	use crate::{block_tree::ForkChoice, crypto::Pair};
+	use std::path::PathBuf;
+
+	fn args(line: &str) -> Vec<String> {
+		line.split_whitespace().map(str::to_string).collect()
+	}
+
+	// Run the command line `line`, and return what it prints.
+	fn run_line(line: &str) -> Result<String, String> {
+		let mut output = Vec::new();
+		run(parse(&args(line))?, &mut output)?;
+		Ok(String::from_utf8(output).unwrap())
+	}
+
+	#[test]
+	fn parse_command_lines() {
+		let alice = Pair::from_seed("alice").account_id();
+		assert_eq!(
+			parse(&args("query balance alice")),
+			Ok(Cli {
+				data_dir: "data".into(),
+				command: Command::QueryBalance { who: alice.clone() }
+			})
+		);
+		assert_eq!(
+			parse(&args("--fork-choice heaviest import-blocks blocks.bin --data-dir chain")),
+			Ok(Cli {
+				data_dir: "chain".into(),
+				command: Command::ImportBlocks {
+					file: PathBuf::from("blocks.bin"),
+					fork_choice: ForkChoice::HeaviestChain
+				},
+			})
+		);
+		assert_eq!(
+			parse(&args(&format!("query balance {:?}", alice))).unwrap().command,
+			Command::QueryBalance { who: alice }
+		);
+
+		assert_eq!(
+			parse(&args("serve --port 9000")).unwrap().command,
+			Command::Serve { port: 9000, block_time: 6 }
+		);
+
+		assert!(parse(&args("")).is_err());
+		assert!(parse(&args("serve --block-time 0")).is_err());
+		assert!(parse(&args("query balance 0x1234")).is_err());
+		assert!(parse(&args("inspect-block one")).is_err());
+		assert!(parse(&args("produce-block --genesis spec.json")).is_err());
+		assert!(parse(&args("init --genesis")).is_err());
+		assert!(parse(&args("sign alice transfer bob")).is_err());
+		assert!(parse(&args("submit 0x00")).is_err());
+	}
+
+	#[test]
+	fn scripted_scenario() {
+		let dir = std::env::temp_dir()
+			.join(format!("rust-state-machine-{}-cli-scenario", std::process::id()));
+		let other = dir.with_extension("import");
+		let _ = std::fs::remove_dir_all(&dir);
+		let _ = std::fs::remove_dir_all(&other);
+		let (dir_arg, other_arg) = (dir.to_str().unwrap(), other.to_str().unwrap());
+		let node = |line: &str| run_line(&format!("--data-dir {} {}", dir_arg, line));
+
+		assert!(node("query balance alice").is_err());
+		assert!(node("init").unwrap().starts_with("Started a new chain at block #0"));
+		assert!(node("init").is_err());
+
+		// Alice funds bob and charlie, and claims some content, in the same block.
+		for call in ["transfer bob 100000", "transfer charlie 300", "create-claim hello"] {
+			let extrinsic = node(&format!("sign alice {}", call)).unwrap();
+			node(&format!("submit {}", extrinsic.trim())).unwrap();
+		}
+		let produced = node("produce-block").unwrap();
+		assert!(produced.starts_with("Produced block #1"));
+		assert!(produced.contains("with 3 extrinsics"));
+		// Bob tries to revoke the claim of alice, and fails.
+		let revoke = node("sign --nonce 0 bob revoke-claim hello").unwrap();
+		node(&format!("submit {}", revoke.trim())).unwrap();
+		assert!(node(&format!("submit {}", revoke.trim())).is_err());
+		let produced = node("produce-block").unwrap();
+		assert!(produced.starts_with("Produced block #2"));
+		assert!(produced.contains("Extrinsic 0 failed: proof_of_existence"));
+
+		assert_eq!(node("query balance charlie").unwrap(), "300\n");
+		let alice = Pair::from_seed("alice").account_id();
+		assert_eq!(
+			node("query claim hello").unwrap(),
+			format!("Owned by {:?} since block 1\n", alice)
+		);
+		assert_eq!(node("query claim goodbye").unwrap(), "Not claimed\n");
+		assert!(node("inspect-block 1").unwrap().starts_with("Block #1 0x"));
+		assert!(node("inspect-block 3").is_err());
+
+		// The blocks are replayed by another node.
+		let blocks = dir.join("blocks.bin");
+		let export = node(&format!("export-blocks {}", blocks.to_str().unwrap())).unwrap();
+		assert_eq!(export, "Exported 2 blocks\n");
+		let other_node = |line: &str| run_line(&format!("--data-dir {} {}", other_arg, line));
+		other_node("init").unwrap();
+		let import = other_node(&format!("import-blocks {}", blocks.to_str().unwrap())).unwrap();
+		assert!(import.starts_with("Imported 2 blocks, the chain is at block #2"));
+		assert_eq!(other_node("query balance charlie").unwrap(), "300\n");
+		assert_eq!(other_node("inspect-block 2"), node("inspect-block 2"));
+
+		std::fs::remove_dir_all(dir).unwrap();
+		std::fs::remove_dir_all(other).unwrap();
+	}
+}
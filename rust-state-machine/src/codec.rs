@@ -0,0 +1,443 @@
+// A compact and deterministic binary codec, in the style of SCALE.
+//
+// The encoding is deterministic: equal values always produce the same bytes. This allows us to
+// hash values, for example to compute the hash of a block header. Decoding is strict, so every
+// value has exactly one encoding which decodes successfully.
+//
+// - Integers are encoded as fixed width little endian bytes, unless wrapped in `Compact`.
+// - Sequences are encoded as their length as a `Compact<u32>`, followed by each of their items.
+// - Enums are encoded as the index of their variant as a `u8`, followed by the variant's fields.
+//
+// Structs and enums can derive this encoding with `#[derive(macros::Encode, macros::Decode)]`.
+
+// A trait for types which can be encoded into bytes.
+pub trait Encode {
+	// Append the encoding of `self` to `dest`.
+	fn encode_to(&self, dest: &mut Vec<u8>);
+
+	// Encode `self` into a new vector of bytes.
+	fn encode(&self) -> Vec<u8> {
+		let mut dest = Vec::new();
+		self.encode_to(&mut dest);
+		dest
+	}
+}
+
+// A trait for types which can be decoded from the bytes produced by their `Encode` implementation.
+pub trait Decode: Sized {
+	// Decode a value from the start of `input`, and advance `input` past the decoded bytes.
+	fn decode(input: &mut &[u8]) -> Result<Self, Error>;
+
+	// Decode a value from `bytes`, which must contain nothing but the encoding of the value.
+	fn decode_all(mut bytes: &[u8]) -> Result<Self, Error> {
+		let value = Self::decode(&mut bytes)?;
+		if !bytes.is_empty() {
+			return Err(Error("trailing bytes after the encoded value"));
+		}
+		Ok(value)
+	}
+}
+
+// The error returned when some bytes are not a valid encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error(pub &'static str);
+
+impl core::fmt::Display for Error {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "could not decode: {}", self.0)
+	}
+}
+
+// Take the next `len` bytes of `input`.
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+	if input.len() < len {
+		return Err(Error("not enough bytes"));
+	}
+	let (bytes, rest) = input.split_at(len);
+	*input = rest;
+	Ok(bytes)
+}
+
+macro_rules! impl_codec_for_int {
+	( $( $t:ty ),* ) => {
+		$(
+			impl Encode for $t {
+				fn encode_to(&self, dest: &mut Vec<u8>) {
+					dest.extend_from_slice(&self.to_le_bytes());
+				}
+			}
+
+			impl Decode for $t {
+				fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+					let bytes = take(input, core::mem::size_of::<$t>())?;
+					Ok(<$t>::from_le_bytes(bytes.try_into().expect("the length was checked")))
+				}
+			}
+		)*
+	};
+}
+
+impl_codec_for_int!(u8, u16, u32, u64, u128);
+
+// An unsigned integer encoded in as few bytes as possible.
+//
+// The two lowest bits of the first byte give the mode of the encoding:
+// - `0b00`: a single byte, for values below `2^6`.
+// - `0b01`: two bytes, for values below `2^14`.
+// - `0b10`: four bytes, for values below `2^30`.
+// - `0b11`: the upper six bits give the number of bytes which follow, minus four.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compact<T>(pub T);
+
+impl<T: Copy + Into<u128>> Encode for Compact<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		let value: u128 = self.0.into();
+		match value {
+			0..=0x3f => dest.push((value as u8) << 2),
+			0x40..=0x3fff => dest.extend_from_slice(&(((value as u16) << 2) | 0b01).to_le_bytes()),
+			0x4000..=0x3fff_ffff => {
+				dest.extend_from_slice(&(((value as u32) << 2) | 0b10).to_le_bytes())
+			},
+			_ => {
+				let bytes = value.to_le_bytes();
+				let len = 16 - value.leading_zeros() as usize / 8;
+				dest.push((((len - 4) as u8) << 2) | 0b11);
+				dest.extend_from_slice(&bytes[..len]);
+			},
+		}
+	}
+}
+
+impl<T: TryFrom<u128>> Decode for Compact<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+		let first = u8::decode(input)?;
+		let (value, min) = match first & 0b11 {
+			0b00 => (u128::from(first >> 2), 0),
+			0b01 => {
+				let value = u16::from_le_bytes([first, u8::decode(input)?]) >> 2;
+				(u128::from(value), 0x40)
+			},
+			0b10 => {
+				let rest = take(input, 3)?;
+				let value = u32::from_le_bytes([first, rest[0], rest[1], rest[2]]) >> 2;
+				(u128::from(value), 0x4000)
+			},
+			_ => {
+				let len = (first >> 2) as usize + 4;
+				if len > 16 {
+					return Err(Error("compact integer is too large"));
+				}
+				let mut bytes = [0; 16];
+				bytes[..len].copy_from_slice(take(input, len)?);
+				let value = u128::from_le_bytes(bytes);
+				// The last byte must be used, otherwise a shorter encoding exists.
+				if bytes[len - 1] == 0 {
+					return Err(Error("compact integer is not in its shortest form"));
+				}
+				(value, 0x4000_0000)
+			},
+		};
+		if value < min {
+			return Err(Error("compact integer is not in its shortest form"));
+		}
+		T::try_from(value)
+			.map(Compact)
+			.map_err(|_| Error("compact integer is out of range"))
+	}
+}
+
+impl Encode for bool {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		dest.push(*self as u8);
+	}
+}
+
+impl Decode for bool {
+	fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+		match u8::decode(input)? {
+			0 => Ok(false),
+			1 => Ok(true),
+			_ => Err(Error("invalid bool")),
+		}
+	}
+}
+
+impl Encode for () {
+	fn encode_to(&self, _dest: &mut Vec<u8>) {}
+}
+
+impl Decode for () {
+	fn decode(_input: &mut &[u8]) -> Result<Self, Error> {
+		Ok(())
+	}
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		(**self).encode_to(dest);
+	}
+}
+
+// Decode the length prefix of a sequence.
+fn decode_len(input: &mut &[u8]) -> Result<usize, Error> {
+	let Compact(len) = Compact::<u32>::decode(input)?;
+	Ok(len as usize)
+}
+
+impl<T: Encode> Encode for [T] {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		Compact(self.len() as u32).encode_to(dest);
+		for item in self {
+			item.encode_to(dest);
+		}
+	}
+}
+
+impl<T: Encode> Encode for Vec<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.as_slice().encode_to(dest);
+	}
+}
+
+impl<T: Decode> Decode for Vec<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+		let len = decode_len(input)?;
+		// Every item takes at least one byte in most cases, so we do not trust a length which is
+		// bigger than the input to allocate memory.
+		let mut items = Vec::with_capacity(len.min(input.len()));
+		for _ in 0..len {
+			items.push(T::decode(input)?);
+		}
+		Ok(items)
+	}
+}
+
+// Arrays have a fixed length, so it is not part of their encoding.
+impl<T: Encode, const N: usize> Encode for [T; N] {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		for item in self {
+			item.encode_to(dest);
+		}
+	}
+}
+
+impl<T: Decode, const N: usize> Decode for [T; N] {
+	fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+		let items = (0..N).map(|_| T::decode(input)).collect::<Result<Vec<_>, _>>()?;
+		Ok(items
+			.try_into()
+			.unwrap_or_else(|_| unreachable!("exactly N items were decoded")))
+	}
+}
+
+impl Encode for str {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.as_bytes().encode_to(dest);
+	}
+}
+
+impl Encode for String {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.as_str().encode_to(dest);
+	}
+}
+
+impl Decode for String {
+	fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+		let len = decode_len(input)?;
+		let bytes = take(input, len)?;
+		String::from_utf8(bytes.to_vec()).map_err(|_| Error("invalid utf-8 string"))
+	}
+}
+
+impl<T: Encode> Encode for Option<T> {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		match self {
+			None => dest.push(0),
+			Some(value) => {
+				dest.push(1);
+				value.encode_to(dest);
+			},
+		}
+	}
+}
+
+impl<T: Decode> Decode for Option<T> {
+	fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+		match u8::decode(input)? {
+			0 => Ok(None),
+			1 => Ok(Some(T::decode(input)?)),
+			_ => Err(Error("invalid option variant")),
+		}
+	}
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		self.0.encode_to(dest);
+		self.1.encode_to(dest);
+	}
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+	fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+		Ok((A::decode(input)?, B::decode(input)?))
+	}
+}
+
+// Write `bytes` as a `0x` prefixed hex string, which is how encoded values are shown to users.
+pub fn to_hex(bytes: &[u8]) -> String {
+	let mut hex = "0x".to_string();
+	for byte in bytes {
+		hex.push_str(&format!("{:02x}", byte));
+	}
+	hex
+}
+
+// Read the bytes of a `0x` prefixed hex string.
+pub fn from_hex(s: &str) -> Result<Vec<u8>, Error> {
+	let hex = s.strip_prefix("0x").ok_or(Error("hex strings start with 0x"))?;
+	if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+		return Err(Error("invalid hex digit"));
+	}
+	if hex.len() % 2 != 0 {
+		return Err(Error("hex strings have an even number of digits"));
+	}
+	let digit = |byte: u8| (byte as char).to_digit(16).expect("checked to be a hex digit") as u8;
+	Ok(hex
+		.as_bytes()
+		.chunks(2)
+		.map(|pair| digit(pair[0]) << 4 | digit(pair[1]))
+		.collect())
+}
+
+#[cfg(test)]
+mod test {
+	use super::{from_hex, to_hex, Compact, Decode, Encode, Error};
+
+	#[test]
+	fn encode_primitives() {
+		assert_eq!(1u8.encode(), vec![1]);
+		assert_eq!(258u32.encode(), vec![2, 1, 0, 0]);
+		assert_eq!(true.encode(), vec![1]);
+		assert_eq!("ab".encode(), vec![8, b'a', b'b']);
+		assert_eq!("ab".to_string().encode(), "ab".encode());
+		assert_eq!(vec![1u16, 2].encode(), vec![8, 1, 0, 2, 0]);
+		assert_eq!([7u8; 2].encode(), vec![7, 7]);
+		assert_eq!(None::<u8>.encode(), vec![0]);
+		assert_eq!(Some(5u8).encode(), vec![1, 5]);
+		assert_eq!((1u8, 2u8).encode(), vec![1, 2]);
+	}
+
+	#[test]
+	fn compact_integers() {
+		let cases: [(u128, &[u8]); 8] = [
+			(0, &[0]),
+			(63, &[0xfc]),
+			(64, &[0x01, 0x01]),
+			(16383, &[0xfd, 0xff]),
+			(16384, &[0x02, 0x00, 0x01, 0x00]),
+			(1 << 30, &[0x03, 0x00, 0x00, 0x00, 0x40]),
+			(u64::MAX as u128, &[0x13, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+			(
+				u128::MAX,
+				&[
+					0x33, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+					0xff, 0xff, 0xff, 0xff,
+				],
+			),
+		];
+		for (value, encoded) in cases {
+			assert_eq!(Compact(value).encode(), encoded);
+			assert_eq!(Compact::<u128>::decode_all(encoded), Ok(Compact(value)));
+		}
+
+		// Every value has a single valid encoding.
+		assert!(Compact::<u32>::decode_all(&[0x01, 0x00]).is_err());
+		assert!(Compact::<u32>::decode_all(&[0x03, 0xff, 0xff, 0xff, 0x00]).is_err());
+		// The value must fit in the integer type.
+		assert_eq!(
+			Compact::<u8>::decode_all(&[0x01, 0x04]),
+			Err(Error("compact integer is out of range"))
+		);
+	}
+
+	#[test]
+	fn decode_round_trips() {
+		fn round_trip<T: Encode + Decode + PartialEq + core::fmt::Debug>(value: T) {
+			assert_eq!(T::decode_all(&value.encode()), Ok(value));
+		}
+
+		round_trip(u128::MAX);
+		round_trip(false);
+		round_trip(());
+		round_trip("Hello, world!".to_string());
+		round_trip(vec![vec![1u32, 2], vec![], vec![3]]);
+		round_trip([[1u8; 3]; 2]);
+		round_trip(Some((7u64, "seven".to_string())));
+		round_trip(None::<u8>);
+		round_trip(vec![0u8; 100]);
+	}
+
+	#[test]
+	fn decode_invalid_bytes() {
+		assert_eq!(u32::decode_all(&[1, 2, 3]), Err(Error("not enough bytes")));
+		assert_eq!(u8::decode_all(&[1, 2]), Err(Error("trailing bytes after the encoded value")));
+		assert_eq!(bool::decode_all(&[2]), Err(Error("invalid bool")));
+		assert_eq!(Option::<u8>::decode_all(&[2, 1]), Err(Error("invalid option variant")));
+		assert_eq!(String::decode_all(&[4, 0xff]), Err(Error("invalid utf-8 string")));
+		// The length of a sequence cannot be longer than the input.
+		assert_eq!(
+			Vec::<u8>::decode_all(&[0xfe, 0xff, 0xff, 0xff]),
+			Err(Error("not enough bytes"))
+		);
+	}
+
+	#[derive(Debug, PartialEq, macros::Encode, macros::Decode)]
+	struct Named<T> {
+		id: u8,
+		value: T,
+	}
+
+	#[derive(Debug, PartialEq, macros::Encode, macros::Decode)]
+	struct Unnamed(u16, bool);
+
+	#[derive(Debug, PartialEq, macros::Encode, macros::Decode)]
+	enum Shape<T> {
+		Empty,
+		Point(T, T),
+		Labelled { label: String, shape: Vec<Shape<T>> },
+	}
+
+	#[test]
+	fn derived_codec() {
+		assert_eq!(Named { id: 1, value: 2u16 }.encode(), vec![1, 2, 0]);
+		assert_eq!(Unnamed(1, true).encode(), vec![1, 0, 1]);
+		assert_eq!(Shape::<u8>::Empty.encode(), vec![0]);
+		assert_eq!(Shape::Point(3u8, 4).encode(), vec![1, 3, 4]);
+
+		let shape = Shape::Labelled {
+			label: "square".to_string(),
+			shape: vec![Shape::Point(0u32, 0), Shape::Empty, Shape::Point(1, 1)],
+		};
+		assert_eq!(Shape::decode_all(&shape.encode()), Ok(shape));
+		assert_eq!(
+			Named::<String>::decode_all(&Named { id: 7, value: "seven".to_string() }.encode()),
+			Ok(Named { id: 7, value: "seven".to_string() })
+		);
+		assert_eq!(Unnamed::decode_all(&[1, 0, 1]), Ok(Unnamed(1, true)));
+		assert_eq!(Shape::<u8>::decode_all(&[3]), Err(Error("invalid enum variant index")));
+	}
+
+	#[test]
+	fn hex_strings() {
+		assert_eq!(to_hex(&[]), "0x");
+		assert_eq!(to_hex(&[0, 1, 0xab, 0xff]), "0x0001abff");
+		assert_eq!(from_hex("0x0001abFF"), Ok(vec![0, 1, 0xab, 0xff]));
+		assert_eq!(from_hex("0x"), Ok(vec![]));
+		assert!(from_hex("0001").is_err());
+		assert!(from_hex("0x001").is_err());
+		assert!(from_hex("0x0g").is_err());
+		assert!(from_hex("0x+1").is_err());
+	}
+}
@@ -0,0 +1,158 @@
+use ed25519_dalek::{Signer, Verifier};
+
+// The public key of an ed25519 key pair, used to check signatures made by its owner.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, macros::Encode, macros::Decode)]
+pub struct PublicKey(pub [u8; 32]);
+
+// An ed25519 signature over some message.
+#[derive(Clone, Copy, PartialEq, Eq, macros::Encode, macros::Decode)]
+pub struct Signature(pub [u8; 64]);
+
+// The identifier of an account on our blockchain.
+//
+// It is derived from the public key controlling the account. For ed25519 keys, the account id is
+// simply the bytes of the public key.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, macros::Encode, macros::Decode)]
+pub struct AccountId(pub [u8; 32]);
+
+impl From<PublicKey> for AccountId {
+	fn from(public: PublicKey) -> Self {
+		Self(public.0)
+	}
+}
+
+impl PublicKey {
+	// Check that `signature` was made over `message` by the owner of this public key.
+	pub fn verify(&self, message: &[u8], signature: &Signature) -> bool {
+		let Ok(key) = ed25519_dalek::VerifyingKey::from_bytes(&self.0) else { return false };
+		let signature = ed25519_dalek::Signature::from_bytes(&signature.0);
+		key.verify(message, &signature).is_ok()
+	}
+}
+
+// An ed25519 key pair, able to sign messages.
+pub struct Pair(ed25519_dalek::SigningKey);
+
+impl Pair {
+	// Create a key pair from a seed phrase. The private key is the hash of the seed.
+	//
+	// Anyone knowing the seed knows the private key, so this should only be used for development
+	// accounts like "alice" and "bob".
+	pub fn from_seed(seed: &str) -> Self {
+		Self(ed25519_dalek::SigningKey::from_bytes(&crate::support::hash(seed.as_bytes())))
+	}
+
+	// The public key of this key pair.
+	pub fn public(&self) -> PublicKey {
+		PublicKey(self.0.verifying_key().to_bytes())
+	}
+
+	// The id of the account controlled by this key pair.
+	pub fn account_id(&self) -> AccountId {
+		self.public().into()
+	}
+
+	// Sign `message` with the private key of this key pair.
+	pub fn sign(&self, message: &[u8]) -> Signature {
+		Signature(self.0.sign(message).to_bytes())
+	}
+}
+
+// Write `bytes` as a `0x` prefixed hex string.
+fn write_hex(f: &mut core::fmt::Formatter, bytes: &[u8]) -> core::fmt::Result {
+	write!(f, "0x")?;
+	bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+}
+
+impl core::fmt::Debug for PublicKey {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write_hex(f, &self.0)
+	}
+}
+
+impl core::fmt::Debug for Signature {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write_hex(f, &self.0)
+	}
+}
+
+impl core::fmt::Debug for AccountId {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write_hex(f, &self.0)
+	}
+}
+
+// The error returned when parsing an account id which is not a `0x` prefixed hex string of
+// 32 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidAccountId;
+
+impl core::fmt::Display for InvalidAccountId {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "expected a 0x prefixed hex string of 32 bytes")
+	}
+}
+
+impl core::str::FromStr for AccountId {
+	type Err = InvalidAccountId;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let hex = s.strip_prefix("0x").ok_or(InvalidAccountId)?;
+		if hex.len() != 64 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+			return Err(InvalidAccountId);
+		}
+		let mut bytes = [0u8; 32];
+		for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+			let pair = core::str::from_utf8(pair).map_err(|_| InvalidAccountId)?;
+			*byte = u8::from_str_radix(pair, 16).map_err(|_| InvalidAccountId)?;
+		}
+		Ok(Self(bytes))
+	}
+}
+
+// Account ids are written as hex strings in files like chain specs, so they can be read by humans.
+impl serde::Serialize for AccountId {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(&format_args!("{:?}", self))
+	}
+}
+
+impl<'de> serde::Deserialize<'de> for AccountId {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+		s.parse().map_err(serde::de::Error::custom)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{AccountId, InvalidAccountId, Pair};
+
+	#[test]
+	fn sign_and_verify() {
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		let signature = alice.sign(b"hello");
+
+		assert!(alice.public().verify(b"hello", &signature));
+		assert!(!alice.public().verify(b"goodbye", &signature));
+		assert!(!bob.public().verify(b"hello", &signature));
+
+		// Key pairs are deterministic, and the account id is derived from the public key.
+		assert_eq!(Pair::from_seed("alice").public(), alice.public());
+		assert_eq!(alice.account_id(), AccountId::from(alice.public()));
+		assert_ne!(alice.account_id(), bob.account_id());
+	}
+
+	#[test]
+	fn parse_account_ids() {
+		let alice = Pair::from_seed("alice").account_id();
+		assert_eq!(format!("{:?}", alice).parse(), Ok(alice.clone()));
+		assert_eq!(format!("{:?}", alice).to_uppercase().replace("0X", "0x").parse(), Ok(alice));
+
+		assert_eq!("00".repeat(32).parse::<AccountId>(), Err(InvalidAccountId));
+		assert_eq!(format!("0x{}", "00".repeat(31)).parse::<AccountId>(), Err(InvalidAccountId));
+		assert_eq!(format!("0x{}", "zz".repeat(32)).parse::<AccountId>(), Err(InvalidAccountId));
+		assert_eq!(format!("0x{}", "+f".repeat(32)).parse::<AccountId>(), Err(InvalidAccountId));
+	}
+}
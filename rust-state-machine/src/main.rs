@@ -1,7 +1,21 @@
+mod backend;
 mod balances;
+mod block_builder;
+mod block_tree;
+mod chain_spec;
+mod cli;
+mod codec;
+mod crypto;
+mod merkle;
+mod metadata;
+mod node;
 mod proof_of_existence;
+mod rpc;
+mod storage;
 mod support;
 mod system;
+mod transaction_pool;
+mod websocket;
 
 // Need to import this to access the `dispatch` fn
 use crate::support::Dispatch;
@@ -12,21 +26,27 @@ use crate::support::Dispatch;
 mod types {
 	use crate::RuntimeCall;
 
-	pub type AccountId = String;
+	pub type AccountId = crate::crypto::AccountId;
 	pub type Balance = u128;
 	pub type BlockNumber = u32;
 	pub type Nonce = u32;
-	pub type Extrinsic = crate::support::Extrinsic<AccountId, RuntimeCall>;
+	pub type RuntimeOrigin = crate::system::RawOrigin<AccountId>;
+	pub type Extrinsic = crate::support::Extrinsic<Nonce, RuntimeCall>;
 	pub type Header = crate::support::Header<BlockNumber>;
 	pub type Block = crate::support::Block<Header, Extrinsic>;
-	pub type Content = &'static str;
+	pub type ExtrinsicReceipt =
+		crate::support::ExtrinsicReceipt<crate::RuntimeEvent, crate::RuntimeError>;
+	pub type BlockReceipt = crate::support::BlockReceipt<crate::RuntimeEvent, crate::RuntimeError>;
+	pub type Content = String;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 #[macros::runtime]
 pub struct Runtime {
 	system: system::Pallet<Self>,
+	#[pallet_index(1)]
 	balances: balances::Pallet<Self>,
+	#[pallet_index(2)]
 	proof_of_existence: proof_of_existence::Pallet<Self>,
 }
 
@@ -34,85 +54,1420 @@ impl system::Config for Runtime {
 	type AccountId = types::AccountId;
 	type BlockNumber = types::BlockNumber;
 	type Nonce = types::Nonce;
+	type RuntimeOrigin = types::RuntimeOrigin;
+	type RuntimeEvent = RuntimeEvent;
+	const MAX_BLOCK_WEIGHT: support::Weight = 1_000_000;
+	// Version 2 stores the block number of every claim.
+	const VERSION: support::RuntimeVersion =
+		support::RuntimeVersion { spec_name: "rust-state-machine", spec_version: 2 };
 }
 
 impl balances::Config for Runtime {
 	type Balance = types::Balance;
+	const BYTE_FEE: types::Balance = 10;
+	const WEIGHT_FEE: types::Balance = 1;
+	// The fees are burned.
+	fn fee_recipient() -> Option<types::AccountId> {
+		None
+	}
 }
 
 impl proof_of_existence::Config for Runtime {
 	type Content = types::Content;
 }
 
+// The fees of extrinsics are paid with the balances pallet.
+impl support::ChargeTransaction for Runtime {
+	type AccountId = types::AccountId;
+	type Balance = types::Balance;
+
+	fn compute_fee(len: usize, weight: support::Weight) -> types::Balance {
+		balances::Pallet::<Self>::compute_fee(len, weight)
+	}
+
+	fn can_pay_fee(&self, who: &types::AccountId, fee: types::Balance) -> bool {
+		self.balances.can_withdraw_fee(who, fee)
+	}
+
+	fn withdraw_fee(&mut self, who: &types::AccountId, fee: types::Balance) -> bool {
+		self.balances.withdraw_fee(who, fee).is_ok()
+	}
+
+	fn settle_fee(
+		&mut self,
+		who: &types::AccountId,
+		charged: types::Balance,
+		actual: types::Balance,
+	) {
+		self.balances.settle_fee(who, charged, actual);
+	}
+}
+
+// Build a block containing all of `extrinsics`, on top of the current state of `runtime`.
+//
+// Tests use it to build blocks which must contain every extrinsic they are given.
+#[cfg(test)]
+fn build_block(runtime: &Runtime, extrinsics: Vec<types::Extrinsic>) -> types::Block {
+	let mut builder = block_builder::BlockBuilder::new(runtime);
+	for extrinsic in extrinsics {
+		builder
+			.push(extrinsic)
+			.expect("the extrinsics are correctly signed, with the next nonce of their signer");
+	}
+	builder.build()
+}
+
+// The initial state of the development chain, where alice is funded.
+fn dev_genesis() -> RuntimeGenesisConfig {
+	let alice = crypto::Pair::from_seed("alice").account_id();
+	RuntimeGenesisConfig {
+		balances: balances::GenesisConfig { balances: vec![(alice, 1_000_000)] },
+		..Default::default()
+	}
+}
+
 fn main() {
-	// Create a new instance of the Runtime,
-	// with all the modules it uses.
-	let mut runtime = Runtime::new();
-	let alice = "alice".to_string();
-	let bob = "bob".to_string();
-	let charlie = "charlie".to_string();
-
-	// Initialize the system with some initial balance.
-	runtime.balances.set_balance(&"alice".to_string(), 100);
-
-	// Create a block and an extrinsic
-	let block_1 = types::Block {
-		header: support::Header { block_number: 1 },
-		extrinsics: vec![
-			support::Extrinsic {
-				caller: alice.clone(),
-				call: RuntimeCall::balances(balances::Call::transfer {
-					to: bob.clone(),
-					amount: 30,
-				}),
-			},
-			support::Extrinsic {
-				caller: alice.clone(),
-				call: RuntimeCall::balances(balances::Call::transfer { to: charlie, amount: 50 }),
-			},
-		],
+	// The node runs a single command on the chain kept in its data directory, see `cli::USAGE`.
+	let args = std::env::args().skip(1).collect::<Vec<_>>();
+	if let Err(e) = cli::parse(&args).and_then(|cli| cli::run(cli, &mut std::io::stdout())) {
+		eprintln!("{}", e);
+		std::process::exit(1)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		backend::{Backend, CommittedState, FileBackend},
+		balances, build_block,
+		chain_spec::{self, ChainSpecError, Format},
+		codec::{Decode, Encode, Error},
+		crypto::Pair,
+		merkle::{verify_storage_proof, InvalidProof, StorageProof},
+		metadata::{FieldMetadata, VariantMetadata},
+		proof_of_existence,
+		storage::{self, Transactional},
+		support::{
+			self, BlockError, ChargeTransaction, Dispatch, DispatchError, EventRecord, Phase,
+		},
+		system::{self, RawOrigin},
+		transaction_pool::TransactionValidator,
+		types, Runtime, RuntimeCall, RuntimeError, RuntimeEvent, RuntimeGenesisConfig,
 	};
 
-	let block_2 = types::Block {
-		header: support::Header { block_number: 2 },
-		extrinsics: vec![
-			support::Extrinsic {
-				caller: alice.clone(),
-				call: RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
-					claim: &"Hello, world!",
-				}),
-			},
-			support::Extrinsic {
-				caller: bob.clone(),
-				call: RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
-					claim: &"Hello, world!",
+	#[test]
+	fn metadata_describes_every_pallet() {
+		let metadata = Runtime::metadata();
+		let pallets = metadata.pallets.iter().map(|p| (p.name, p.index)).collect::<Vec<_>>();
+		assert_eq!(pallets, vec![("balances", 1), ("proof_of_existence", 2)]);
+
+		// Calls are described with their call index, arguments and doc comments.
+		let balances = &metadata.pallets[0];
+		assert_eq!(
+			balances.calls[1],
+			VariantMetadata {
+				name: "force_set_balance",
+				index: 1,
+				fields: vec![
+					FieldMetadata { name: Some("who"), type_name: "T::AccountId" },
+					FieldMetadata { name: Some("amount"), type_name: "T::Balance" },
+				],
+				docs: vec![
+					"Set the balance of `who` to `amount`. Only the root can make this call."
+				],
+			}
+		);
+		let errors = balances.errors.iter().map(|e| (e.name, e.index)).collect::<Vec<_>>();
+		assert_eq!(errors, vec![("InsufficientBalance", 0), ("Overflow", 1), ("BadOrigin", 2)]);
+		assert_eq!(balances.events[0].name, "Transfer");
+		assert_eq!(balances.events[0].fields.len(), 3);
+
+		// The indices in the metadata are the ones used to encode calls.
+		let poe = &metadata.pallets[1];
+		let call = RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
+			claim: String::new(),
+		});
+		assert_eq!(call.encode()[..2], [poe.index, poe.calls[1].index]);
+		assert_eq!(poe.calls[1].name, "revoke_claim");
+
+		// The JSON rendering keeps the same structure.
+		let json = metadata.to_json();
+		assert!(json.contains("\"name\": \"proof_of_existence\""));
+		assert!(json.contains("\"type\": \"T::Content\""));
+	}
+
+	#[test]
+	fn calls_round_trip_through_their_encoding() {
+		let alice = Pair::from_seed("alice");
+		let context = Runtime::new().system.signing_context();
+		let bob = Pair::from_seed("bob").account_id();
+		let calls = vec![
+			RuntimeCall::balances(balances::Call::transfer { to: bob.clone(), amount: 30 }),
+			RuntimeCall::balances(balances::Call::force_set_balance {
+				who: bob.clone(),
+				amount: u128::MAX,
+			}),
+			RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+				claim: "Hello, world!".to_string(),
+			}),
+			RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
+				claim: String::new(),
+			}),
+		];
+
+		for call in &calls {
+			let bytes = call.encode();
+			assert_eq!(RuntimeCall::decode_all(&bytes), Ok(call.clone()));
+			// Trailing or missing bytes are rejected.
+			assert!(RuntimeCall::decode_all(&[bytes.as_slice(), &[0]].concat()).is_err());
+			assert!(RuntimeCall::decode_all(&bytes[..bytes.len() - 1]).is_err());
+		}
+
+		// Each call starts with the `#[pallet_index(n)]` of its pallet, and the `#[call_index(n)]`
+		// of the call within the pallet.
+		assert_eq!(calls[0].encode()[..2], [1, 0]);
+		assert_eq!(calls[1].encode()[..2], [1, 1]);
+		assert_eq!(calls[3].encode()[..2], [2, 1]);
+		assert_eq!(RuntimeCall::decode_all(&[0]), Err(Error("invalid pallet index")));
+		assert_eq!(RuntimeCall::decode_all(&[1, 2]), Err(Error("invalid call index")));
+
+		// Pallet level calls round trip on their own too.
+		let transfer = balances::Call::<Runtime>::transfer { to: bob, amount: 1 };
+		assert_eq!(balances::Call::decode_all(&transfer.encode()), Ok(transfer));
+
+		// So do whole blocks, including their signed extrinsics.
+		let extrinsics = calls
+			.into_iter()
+			.enumerate()
+			.map(|(i, call)| support::Extrinsic::new_signed(&alice, i as u32, call, &context));
+		let block = build_block(&Runtime::new(), vec![]);
+		let block = types::Block { extrinsics: extrinsics.collect(), ..block };
+		let decoded = types::Block::decode_all(&block.encode()).unwrap();
+		assert!(decoded.extrinsics.iter().all(|extrinsic| extrinsic.verify_signature(&context)));
+		assert_eq!(decoded, block);
+	}
+
+	#[test]
+	fn dispatch_wraps_pallet_errors() {
+		let mut runtime = Runtime::new();
+		let alice = Pair::from_seed("alice").account_id();
+		let bob = Pair::from_seed("bob").account_id();
+
+		let err = runtime
+			.dispatch(
+				RawOrigin::Signed(alice.clone()),
+				RuntimeCall::balances(balances::Call::transfer { to: bob.clone(), amount: 10 }),
+			)
+			.unwrap_err();
+		assert_eq!(err.pallet_index, 1);
+		assert_eq!(err.error_index, 0);
+		assert_eq!(err.error, RuntimeError::balances(balances::Error::InsufficientBalance));
+		assert_eq!(err.to_string(), "balances: Not enough funds. (pallet 1, error 0)");
+
+		let call = proof_of_existence::Call::revoke_claim { claim: "Hello, world!".to_string() };
+		let err = runtime
+			.dispatch(RawOrigin::Signed(bob), RuntimeCall::proof_of_existence(call))
+			.unwrap_err();
+		assert_eq!(err.pallet_index, 2);
+		assert_eq!(err.error_index, 1);
+		assert_eq!(
+			err.error,
+			RuntimeError::proof_of_existence(proof_of_existence::Error::ClaimNotExist)
+		);
+	}
+
+	#[test]
+	fn failed_batch_reverts_every_pallet() {
+		let mut runtime = Runtime::new();
+		let alice = Pair::from_seed("alice").account_id();
+		let bob = Pair::from_seed("bob").account_id();
+		runtime.balances.set_balance(&alice, 100);
+
+		// A batch of calls, executed in a single transaction where the last call fails.
+		let result = runtime.with_transaction(|runtime| {
+			let claim =
+				proof_of_existence::Call::create_claim { claim: "Hello, world!".to_string() };
+			runtime.dispatch(
+				RawOrigin::Signed(alice.clone()),
+				RuntimeCall::proof_of_existence(claim),
+			)?;
+			let transfer = balances::Call::transfer { to: bob.clone(), amount: 60 };
+			runtime.dispatch(RawOrigin::Signed(alice.clone()), RuntimeCall::balances(transfer))?;
+			// The first transfer succeeded, but there is not enough left for a second one.
+			assert_eq!(runtime.balances.balance(&bob), 60);
+			let transfer = balances::Call::transfer { to: bob.clone(), amount: 60 };
+			runtime.dispatch(RawOrigin::Signed(alice.clone()), RuntimeCall::balances(transfer))
+		});
+
+		assert!(result.is_err());
+		assert_eq!(runtime.balances.balance(&alice), 100);
+		assert_eq!(runtime.balances.balance(&bob), 0);
+		assert_eq!(runtime.proof_of_existence.get_claim(&"Hello, world!".to_string()), None);
+	}
+
+	#[test]
+	fn events_are_collected_per_block() {
+		let mut runtime = Runtime::new();
+		let context = runtime.system.signing_context();
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		runtime.balances.set_balance(&alice.account_id(), 100_000);
+		runtime.balances.set_balance(&bob.account_id(), 100_000);
+
+		let block_1 = build_block(
+			&runtime,
+			vec![
+				// This extrinsic fails, since bob has paid its fee, so it emits no events.
+				support::Extrinsic::new_signed(
+					&bob,
+					0,
+					RuntimeCall::balances(balances::Call::transfer {
+						to: alice.account_id(),
+						amount: 100_000,
+					}),
+					&context,
+				),
+				support::Extrinsic::new_signed(
+					&alice,
+					0,
+					RuntimeCall::balances(balances::Call::transfer {
+						to: bob.account_id(),
+						amount: 30,
+					}),
+					&context,
+				),
+				support::Extrinsic::new_signed(
+					&bob,
+					1,
+					RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+						claim: "Hello, world!".to_string(),
+					}),
+					&context,
+				),
+			],
+		);
+		let block_hash = block_1.header.hash();
+		let receipt = runtime.execute_block(block_1).unwrap();
+
+		// The receipt of the block tells the outcome of every extrinsic.
+		let transfer = RuntimeEvent::balances(balances::Event::Transfer {
+			from: alice.account_id(),
+			to: bob.account_id(),
+			amount: 30,
+		});
+		assert_eq!(receipt.block_hash, block_hash);
+		assert_eq!(
+			receipt.extrinsics[0].result,
+			Err(DispatchError {
+				pallet_index: 1,
+				error_index: 0,
+				error: RuntimeError::balances(balances::Error::InsufficientBalance),
+			})
+		);
+		assert!(receipt.extrinsics[0].events.is_empty());
+		assert_eq!(receipt.extrinsics[0].weight, 10_000);
+		assert_eq!(receipt.extrinsics[1].result, Ok(()));
+		assert_eq!(receipt.extrinsics[1].events, vec![transfer]);
+		// The account of bob already exists, so the unused weight of the transfer is refunded.
+		assert_eq!(receipt.extrinsics[1].weight, 7_500);
+		assert_eq!(receipt.extrinsics[2].result, Ok(()));
+		assert_eq!(receipt.extrinsics[2].events.len(), 1);
+
+		assert_eq!(
+			runtime.system.events(),
+			&[
+				EventRecord {
+					phase: Phase::ApplyExtrinsic(1),
+					event: RuntimeEvent::balances(balances::Event::Transfer {
+						from: alice.account_id(),
+						to: bob.account_id(),
+						amount: 30,
+					}),
+				},
+				EventRecord {
+					phase: Phase::ApplyExtrinsic(2),
+					event: RuntimeEvent::proof_of_existence(
+						proof_of_existence::Event::ClaimCreated {
+							who: bob.account_id(),
+							claim: "Hello, world!".to_string(),
+						}
+					),
+				},
+			]
+		);
+
+		// The events are cleared when the next block starts.
+		let block_2 = build_block(&runtime, vec![]);
+		runtime.execute_block(block_2).unwrap();
+		assert!(runtime.system.events().is_empty());
+	}
+
+	#[test]
+	fn rejected_blocks_keep_the_events_of_the_previous_block() {
+		let mut runtime = Runtime::new();
+		let context = runtime.system.signing_context();
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		runtime.balances.set_balance(&alice.account_id(), 100_000);
+		let transfer = |nonce| {
+			support::Extrinsic::new_signed(
+				&alice,
+				nonce,
+				RuntimeCall::balances(balances::Call::transfer {
+					to: bob.account_id(),
+					amount: 30,
 				}),
-			},
-		],
-	};
+				&context,
+			)
+		};
+
+		let good_block = build_block(&runtime, vec![transfer(0)]);
+		runtime.execute_block(good_block).unwrap();
+		let events = runtime.system.events().to_vec();
+		let weight = runtime.system.block_weight();
+		assert_eq!(events.len(), 1);
+		assert!(weight > 0);
+
+		// The bad block starts executing, clearing the events and weight of the good block, and
+		// applies its extrinsics before its state root is checked.
+		let mut bad_block = build_block(&runtime, vec![transfer(1), transfer(2)]);
+		bad_block.header.state_root = support::Hash::default();
+		assert_eq!(runtime.execute_block(bad_block), Err(BlockError::StateRootMismatch));
+		assert_eq!(runtime.system.events(), events.as_slice());
+		assert_eq!(runtime.system.block_weight(), weight);
+	}
 
-	let block_3 = types::Block {
-		header: support::Header { block_number: 3 },
-		extrinsics: vec![
-			support::Extrinsic {
-				caller: alice.clone(),
-				call: RuntimeCall::proof_of_existence(proof_of_existence::Call::revoke_claim {
-					claim: &"Hello, world!",
+	#[test]
+	fn blocks_must_match_their_header() {
+		let mut runtime = Runtime::new();
+		let context = runtime.system.signing_context();
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		runtime.balances.set_balance(&alice.account_id(), 100_000);
+		let transfer = |nonce| {
+			support::Extrinsic::new_signed(
+				&alice,
+				nonce,
+				RuntimeCall::balances(balances::Call::transfer {
+					to: bob.account_id(),
+					amount: 30,
 				}),
-			},
-			support::Extrinsic {
-				caller: bob.clone(),
-				call: RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
-					claim: &"Hello, world!",
+				&context,
+			)
+		};
+
+		let block_1 = build_block(&runtime, vec![transfer(0)]);
+		// The hash of a block is deterministic.
+		assert_eq!(block_1.header.hash(), block_1.clone().header.hash());
+
+		let mut wrong_number = block_1.clone();
+		wrong_number.header.block_number = 2;
+		assert_eq!(runtime.execute_block(wrong_number), Err(BlockError::WrongBlockNumber));
+
+		let mut wrong_parent = block_1.clone();
+		wrong_parent.header.parent_hash = [1; 32];
+		assert_eq!(runtime.execute_block(wrong_parent), Err(BlockError::UnknownParent));
+
+		let mut wrong_extrinsics = block_1.clone();
+		wrong_extrinsics.extrinsics.push(transfer(1));
+		assert_eq!(
+			runtime.execute_block(wrong_extrinsics),
+			Err(BlockError::ExtrinsicsRootMismatch)
+		);
+
+		let mut wrong_state = block_1.clone();
+		wrong_state.header.state_root = [1; 32];
+		assert_eq!(runtime.execute_block(wrong_state), Err(BlockError::StateRootMismatch));
+
+		// None of the rejected blocks changed the state.
+		assert_eq!(runtime.system.block_number(), 0);
+		assert_eq!(runtime.balances.balance(&alice.account_id()), 100_000);
+		assert!(runtime.system.events().is_empty());
+
+		let block_1_hash = block_1.header.hash();
+		assert!(runtime.execute_block(block_1).is_ok());
+		assert_eq!(runtime.system.parent_hash(), block_1_hash);
+		assert_eq!(runtime.balances.balance(&bob.account_id()), 30);
+
+		// The next block must build on top of the first one.
+		let block_2 = build_block(&runtime, vec![transfer(1)]);
+		assert_eq!(block_2.header.parent_hash, block_1_hash);
+		let mut wrong_parent = block_2.clone();
+		wrong_parent.header.parent_hash = support::Hash::default();
+		assert_eq!(runtime.execute_block(wrong_parent), Err(BlockError::UnknownParent));
+		assert!(runtime.execute_block(block_2).is_ok());
+		assert_eq!(runtime.balances.balance(&bob.account_id()), 60);
+	}
+
+	#[test]
+	fn state_root_commits_to_every_pallet() {
+		let alice = Pair::from_seed("alice").account_id();
+		let bob = Pair::from_seed("bob").account_id();
+		let new_runtime = || {
+			let mut runtime = Runtime::new();
+			runtime.balances.set_balance(&alice, 100);
+			runtime
+		};
+
+		// The state root is deterministic.
+		let mut runtime = new_runtime();
+		let root = runtime.state_root();
+		assert_eq!(root, new_runtime().state_root());
+		assert_ne!(root, Runtime::new().state_root());
+
+		// Changing a balance changes the state root.
+		runtime.balances.set_balance(&bob, 1);
+		let balance_root = runtime.state_root();
+		assert_ne!(balance_root, root);
+
+		// Changing a nonce changes the state root.
+		runtime.system.inc_nonce(&alice);
+		let nonce_root = runtime.state_root();
+		assert_ne!(nonce_root, balance_root);
+
+		// Changing a claim changes the state root.
+		let claim = proof_of_existence::Call::create_claim { claim: "Hello, world!".to_string() };
+		runtime
+			.dispatch(RawOrigin::Signed(alice.clone()), RuntimeCall::proof_of_existence(claim))
+			.unwrap();
+		let claim_root = runtime.state_root();
+		assert_ne!(claim_root, nonce_root);
+
+		// Reverting a change brings back the previous state root.
+		runtime.balances.set_balance(&bob, 0);
+		assert_ne!(runtime.state_root(), claim_root);
+		runtime.balances.set_balance(&bob, 1);
+		assert_eq!(runtime.state_root(), claim_root);
+	}
+
+	#[test]
+	fn genesis_is_loaded_from_a_chain_spec() {
+		// The chain spec of the development chain is kept in the repository.
+		let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("specs/dev.toml");
+		let dev: RuntimeGenesisConfig = chain_spec::load(&path).unwrap();
+		assert_eq!(dev, crate::dev_genesis());
+
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob").account_id();
+		let json = format!(
+			r#"{{
+				"system": {{ "block_number": 10 }},
+				"balances": {{ "balances": [["{alice:?}", 500], ["{bob:?}", 200]] }},
+				"proof_of_existence": {{ "claims": [["Hello, world!", "{bob:?}"]] }}
+			}}"#,
+			alice = alice.account_id(),
+		);
+		let genesis: RuntimeGenesisConfig = chain_spec::parse(&json, Format::Json).unwrap();
+		let mut runtime = genesis.build();
+		assert_eq!(runtime.system.block_number(), 10);
+		assert_eq!(runtime.balances.balance(&alice.account_id()), 500);
+		assert_eq!(runtime.balances.balance(&bob), 200);
+		assert_eq!(
+			runtime.proof_of_existence.get_claim(&"Hello, world!".to_string()),
+			Some(&(bob.clone(), 0))
+		);
+
+		// The chain continues from the block number of the genesis.
+		let block = build_block(&runtime, vec![]);
+		assert_eq!(block.header.block_number, 11);
+		assert!(runtime.execute_block(block).is_ok());
+
+		// Missing sections use the default genesis of their pallet, but unknown ones are rejected.
+		let genesis: RuntimeGenesisConfig = chain_spec::parse("", Format::Toml).unwrap();
+		assert_eq!(genesis, RuntimeGenesisConfig::default());
+		// The initial state always records the version of the runtime, and the chain is identified
+		// by the root of the initial state.
+		let mut new = Runtime::new();
+		new.system.set_last_runtime_upgrade(<Runtime as system::Config>::VERSION);
+		let genesis_hash = new.state_root();
+		new.system.set_genesis_hash(genesis_hash);
+		assert_eq!(genesis.build(), new);
+		assert_eq!(new.system.signing_context().genesis_hash, genesis_hash);
+		assert_ne!(genesis_hash, runtime.system.signing_context().genesis_hash);
+		let unknown = chain_spec::parse::<RuntimeGenesisConfig>("[staking]", Format::Toml);
+		assert!(matches!(unknown, Err(ChainSpecError::Invalid(_))));
+		let bad_account = r#"{ "balances": { "balances": [["alice", 500]] } }"#;
+		let bad_account = chain_spec::parse::<RuntimeGenesisConfig>(bad_account, Format::Json);
+		assert!(matches!(bad_account, Err(ChainSpecError::Invalid(_))));
+	}
+
+	#[test]
+	fn runtime_is_resumed_from_a_file_backend() {
+		let dir = std::env::temp_dir()
+			.join(format!("rust-state-machine-{}-runtime-resumed", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("state.log");
+		let _ = std::fs::remove_file(&path);
+
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob").account_id();
+		let claim = "Hello, world!".to_string();
+		let mut runtime = crate::dev_genesis().build();
+		let context = runtime.system.signing_context();
+		let mut backend = FileBackend::open(&path).unwrap();
+		let block = build_block(
+			&runtime,
+			vec![
+				support::Extrinsic::new_signed(
+					&alice,
+					0,
+					RuntimeCall::balances(balances::Call::transfer { to: bob.clone(), amount: 30 }),
+					&context,
+				),
+				support::Extrinsic::new_signed(
+					&alice,
+					1,
+					RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+						claim: claim.clone(),
+					}),
+					&context,
+				),
+			],
+		);
+		runtime.execute_block(block).unwrap();
+		backend.commit(runtime.committed_state()).unwrap();
+		drop(backend);
+
+		// After a restart, the runtime is at the same block, with the same balances and claims.
+		let backend = FileBackend::open(&path).unwrap();
+		let mut resumed = Runtime::restore(backend.load().unwrap()).unwrap();
+		assert_eq!(resumed.system.block_number(), 1);
+		assert_eq!(resumed.system.parent_hash(), runtime.system.parent_hash());
+		assert_eq!(resumed.system.nonce(&alice.account_id()), 2);
+		assert_eq!(resumed.balances.balance(&bob), 30);
+		assert_eq!(
+			resumed.balances.balance(&alice.account_id()),
+			runtime.balances.balance(&alice.account_id())
+		);
+		assert_eq!(resumed.proof_of_existence.get_claim(&claim), Some(&(alice.account_id(), 1)));
+		assert_eq!(resumed.state_root(), runtime.state_root());
+
+		// It continues the chain from there, with the next nonce of alice.
+		let transfer =
+			RuntimeCall::balances(balances::Call::transfer { to: bob.clone(), amount: 5 });
+		let block = build_block(
+			&resumed,
+			vec![support::Extrinsic::new_signed(&alice, 2, transfer, &context)],
+		);
+		assert!(resumed.execute_block(block.clone()).is_ok());
+		assert!(runtime.execute_block(block).is_ok());
+		assert_eq!(resumed.balances.balance(&bob), 35);
+		std::fs::remove_dir_all(dir).unwrap();
+
+		// A state with a key which belongs to no storage item is rejected.
+		let mut state = resumed.committed_state();
+		state.pairs.push((b"unknown".to_vec(), vec![]));
+		assert!(Runtime::restore(&state).is_err());
+		// So is a state with a value which does not decode.
+		let mut state = resumed.committed_state();
+		state.pairs[0].1.push(0);
+		assert!(Runtime::restore(&state).is_err());
+	}
+
+	#[test]
+	fn persisted_claims_are_upgraded() {
+		let alice = Pair::from_seed("alice");
+		let claim = "Hello, world!".to_string();
+		let mut runtime = crate::dev_genesis().build();
+		let context = runtime.system.signing_context();
+		assert_eq!(runtime.system.last_runtime_upgrade(), ("rust-state-machine".to_string(), 2));
+		let create_claim =
+			RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+				claim: claim.clone(),
+			});
+		let block = build_block(
+			&runtime,
+			vec![support::Extrinsic::new_signed(&alice, 0, create_claim, &context)],
+		);
+		runtime.execute_block(block).unwrap();
+
+		// Turn the committed state into the one version 1 of the runtime would have written, where
+		// claims only hold their owner.
+		let version_key = storage::storage_prefix("system", "last_runtime_upgrade");
+		let with_version = |pairs: &storage::StoragePairs, name: &str, version: u32| {
+			let mut pairs = pairs.clone();
+			let value = (name.to_string(), version).encode();
+			pairs.iter_mut().find(|(key, _)| *key == version_key).unwrap().1 = value;
+			pairs
+		};
+		let mut state = runtime.committed_state();
+		let claims = storage::storage_prefix("proof_of_existence", "claims");
+		for (_, value) in state.pairs.iter_mut().filter(|(key, _)| key.starts_with(&claims)) {
+			let (owner, _) = <(types::AccountId, types::BlockNumber)>::decode_all(value).unwrap();
+			*value = owner.encode();
+		}
+		storage::set_storage_version("proof_of_existence", &mut state.pairs, 1);
+		state.pairs = with_version(&state.pairs, "rust-state-machine", 1);
+		state.pairs.sort();
+
+		// The claims are migrated when the state is restored, and the state records the upgrade.
+		let mut upgraded = Runtime::restore(&state).unwrap();
+		assert_eq!(upgraded.proof_of_existence.get_claim(&claim), Some(&(alice.account_id(), 0)));
+		assert_eq!(upgraded.system.last_runtime_upgrade(), ("rust-state-machine".to_string(), 2));
+		let committed = upgraded.committed_state();
+		assert_eq!(storage::storage_version("proof_of_existence", &committed.pairs), Ok(2));
+		assert_eq!(Runtime::restore(&committed).unwrap(), upgraded);
+
+		// The upgraded chain continues from the same block.
+		let block = build_block(&upgraded, vec![]);
+		assert!(upgraded.execute_block(block).is_ok());
+		assert_eq!(upgraded.system.block_number(), 2);
+
+		// Claims of version 1 do not decode if the state claims to be at the current version.
+		let unmigrated = with_version(&state.pairs, "rust-state-machine", 2);
+		assert!(Runtime::restore(&CommittedState { pairs: unmigrated, ..state.clone() }).is_err());
+		// States of another runtime, or of a newer version, are rejected.
+		let other = with_version(&state.pairs, "another-runtime", 1);
+		assert!(Runtime::restore(&CommittedState { pairs: other, ..state.clone() }).is_err());
+		let newer = with_version(&state.pairs, "rust-state-machine", 3);
+		assert!(Runtime::restore(&CommittedState { pairs: newer, ..state }).is_err());
+	}
+
+	#[test]
+	fn prove_storage_to_light_clients() {
+		let mut runtime = Runtime::new();
+		let context = runtime.system.signing_context();
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		runtime.balances.set_balance(&alice.account_id(), 100_000);
+
+		let transfer = |nonce, amount| {
+			support::Extrinsic::new_signed(
+				&alice,
+				nonce,
+				RuntimeCall::balances(balances::Call::transfer { to: bob.account_id(), amount }),
+				&context,
+			)
+		};
+		let create_claim = support::Extrinsic::new_signed(
+			&alice,
+			1,
+			RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+				claim: "Hello, world!".to_string(),
+			}),
+			&context,
+		);
+
+		// The client only keeps the headers of the blocks.
+		let mut headers = Vec::new();
+		for extrinsics in [vec![transfer(0, 10)], vec![create_claim], vec![transfer(2, 20)]] {
+			let block = build_block(&runtime, extrinsics);
+			headers.push(block.header.clone());
+			runtime.execute_block(block).unwrap();
+		}
+		let header_3 = &headers[2];
+
+		// Bob's balance is 30 at block 3.
+		let key = storage::storage_map_key("balances", "balances", &bob.account_id());
+		let proof = runtime.prove_storage(&key);
+		assert_eq!(verify_storage_proof(header_3, &key, &proof), Ok(Some(30u128.encode())));
+		// The same proof does not hold at block 2, where his balance was 10.
+		assert_eq!(verify_storage_proof(&headers[1], &key, &proof), Err(InvalidProof));
+
+		// Alice owns the claim on "Hello, world!", since block 2.
+		let key = storage::storage_map_key("proof_of_existence", "claims", &"Hello, world!");
+		let proof = runtime.prove_storage(&key);
+		assert_eq!(
+			verify_storage_proof(header_3, &key, &proof),
+			Ok(Some((alice.account_id(), 2u32).encode()))
+		);
+
+		// Nobody owns a claim on "Goodbye, world!".
+		let key = storage::storage_map_key("proof_of_existence", "claims", &"Goodbye, world!");
+		let proof = runtime.prove_storage(&key);
+		assert!(matches!(proof, StorageProof::Absent { .. }));
+		assert_eq!(verify_storage_proof(header_3, &key, &proof), Ok(None));
+
+		// A proof cannot be used for another key.
+		let other_key = storage::storage_map_key("balances", "balances", &alice.account_id());
+		assert_eq!(verify_storage_proof(header_3, &other_key, &proof), Err(InvalidProof));
+	}
+
+	#[test]
+	fn extrinsics_must_be_signed_by_their_signer() {
+		let mut runtime = Runtime::new();
+		let context = runtime.system.signing_context();
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		runtime.balances.set_balance(&alice.account_id(), 100_000);
+		let steal = RuntimeCall::balances(balances::Call::transfer {
+			to: bob.account_id(),
+			amount: 50_000,
+		});
+
+		// Bob cannot sign an extrinsic on behalf of alice.
+		let mut forged = support::Extrinsic::new_signed(&bob, 0, steal.clone(), &context);
+		forged.signer = alice.public();
+		let block = build_block(&runtime, vec![]);
+		let mut forged_block = block.clone();
+		forged_block.extrinsics = vec![forged];
+		forged_block.header.extrinsics_root = support::extrinsics_root(&forged_block.extrinsics);
+		assert_eq!(runtime.execute_block(forged_block), Err(BlockError::BadSignature));
+
+		// The call cannot be changed once the extrinsic is signed.
+		let mut tampered = support::Extrinsic::new_signed(
+			&alice,
+			0,
+			RuntimeCall::balances(balances::Call::transfer { to: bob.account_id(), amount: 1 }),
+			&context,
+		);
+		tampered.call = steal;
+		let mut tampered_block = block.clone();
+		tampered_block.extrinsics = vec![tampered];
+		tampered_block.header.extrinsics_root =
+			support::extrinsics_root(&tampered_block.extrinsics);
+		assert_eq!(runtime.execute_block(tampered_block), Err(BlockError::BadSignature));
+
+		assert_eq!(runtime.balances.balance(&alice.account_id()), 100_000);
+		assert!(runtime.execute_block(block).is_ok());
+	}
+
+	#[test]
+	fn extrinsics_cannot_be_replayed_on_another_chain() {
+		let mut runtime = crate::dev_genesis().build();
+		let alice = Pair::from_seed("alice");
+		let transfer = RuntimeCall::balances(balances::Call::transfer {
+			to: Pair::from_seed("bob").account_id(),
+			amount: 10,
+		});
+		let context = runtime.system.signing_context();
+		let version = support::RuntimeVersion { spec_version: 1, ..context.version };
+		let other_chains = [
+			// A chain started from another genesis.
+			Runtime::new().system.signing_context(),
+			// Or running an older runtime.
+			support::SigningContext { version, ..context },
+		];
+
+		for other in other_chains {
+			let extrinsic = support::Extrinsic::new_signed(&alice, 0, transfer.clone(), &other);
+			assert!(!extrinsic.verify_signature(&context));
+			assert_eq!(runtime.validate_transaction(&extrinsic), Err(BlockError::BadSignature));
+			let mut block = build_block(&runtime, vec![]);
+			block.extrinsics = vec![extrinsic];
+			block.header.extrinsics_root = support::extrinsics_root(&block.extrinsics);
+			assert_eq!(runtime.execute_block(block), Err(BlockError::BadSignature));
+		}
+
+		let extrinsic = support::Extrinsic::new_signed(&alice, 0, transfer, &context);
+		assert!(runtime.execute_block(build_block(&runtime, vec![extrinsic])).is_ok());
+	}
+
+	#[test]
+	fn extrinsics_must_use_the_next_nonce() {
+		let mut runtime = Runtime::new();
+		let context = runtime.system.signing_context();
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		runtime.balances.set_balance(&alice.account_id(), 100_000);
+		let transfer = |nonce| {
+			support::Extrinsic::new_signed(
+				&alice,
+				nonce,
+				RuntimeCall::balances(balances::Call::transfer {
+					to: bob.account_id(),
+					amount: 10,
 				}),
-			},
-		],
-	};
+				&context,
+			)
+		};
+		// Build a block with the given extrinsics, but without checking them.
+		let unchecked_block = |runtime: &Runtime, extrinsics| {
+			let mut block = build_block(runtime, vec![]);
+			block.extrinsics = extrinsics;
+			block.header.extrinsics_root = support::extrinsics_root(&block.extrinsics);
+			block
+		};
+
+		let block_1 = build_block(&runtime, vec![transfer(0), transfer(1)]);
+		runtime.execute_block(block_1.clone()).unwrap();
+		assert_eq!(runtime.system.nonce(&alice.account_id()), 2);
+		assert_eq!(runtime.system.nonce(&bob.account_id()), 0);
+
+		// The same extrinsic cannot be included twice, in the same block or in a later one.
+		let replayed = unchecked_block(&runtime, block_1.extrinsics);
+		assert_eq!(runtime.execute_block(replayed), Err(BlockError::StaleNonce));
+		let duplicated = unchecked_block(&runtime, vec![transfer(2), transfer(2)]);
+		assert_eq!(runtime.execute_block(duplicated), Err(BlockError::StaleNonce));
+
+		// Nonces cannot be skipped.
+		let future = unchecked_block(&runtime, vec![transfer(3)]);
+		assert_eq!(runtime.execute_block(future), Err(BlockError::FutureNonce));
+		let stale = unchecked_block(&runtime, vec![transfer(1)]);
+		assert_eq!(runtime.execute_block(stale), Err(BlockError::StaleNonce));
+
+		// None of the rejected extrinsics were dispatched.
+		assert_eq!(runtime.system.nonce(&alice.account_id()), 2);
+		assert_eq!(runtime.balances.balance(&bob.account_id()), 20);
+
+		let block_2 = build_block(&runtime, vec![transfer(2)]);
+		assert!(runtime.execute_block(block_2).is_ok());
+		assert_eq!(runtime.system.nonce(&alice.account_id()), 3);
+	}
+
+	#[test]
+	fn blocks_cannot_exceed_the_maximum_weight() {
+		let mut runtime = Runtime::new();
+		let context = runtime.system.signing_context();
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		runtime.balances.set_balance(&alice.account_id(), 10_000_000);
+		let transfer = |nonce| {
+			support::Extrinsic::new_signed(
+				&alice,
+				nonce,
+				RuntimeCall::balances(balances::Call::transfer { to: bob.account_id(), amount: 1 }),
+				&context,
+			)
+		};
+
+		// The weight of a call is declared by its pallet, and may depend on its arguments.
+		let call = transfer(0).call;
+		assert_eq!(call.weight(), 10_000);
+		assert_eq!(
+			call.weight(),
+			balances::Call::<Runtime>::transfer { to: bob.account_id(), amount: 1 }.weight()
+		);
+		let short_claim =
+			proof_of_existence::Call::<Runtime>::create_claim { claim: "a".to_string() };
+		let long_claim =
+			proof_of_existence::Call::<Runtime>::create_claim { claim: "abcdef".to_string() };
+		assert!(short_claim.weight() < long_claim.weight());
+
+		// Exactly `MAX_BLOCK_WEIGHT` can be consumed by a block.
+		let max_transfers = <Runtime as system::Config>::MAX_BLOCK_WEIGHT / call.weight();
+		let full_block = build_block(&runtime, (0..max_transfers as u32).map(transfer).collect());
+		let mut overweight_block = full_block.clone();
+		overweight_block.extrinsics.push(transfer(max_transfers as u32));
+		overweight_block.header.extrinsics_root =
+			support::extrinsics_root(&overweight_block.extrinsics);
+
+		assert_eq!(runtime.execute_block(overweight_block), Err(BlockError::ExhaustsResources));
+		assert_eq!(runtime.balances.balance(&alice.account_id()), 10_000_000);
+		assert!(runtime.execute_block(full_block).is_ok());
+		assert_eq!(runtime.system.block_weight(), <Runtime as system::Config>::MAX_BLOCK_WEIGHT);
+		assert_eq!(runtime.balances.balance(&bob.account_id()), max_transfers as u128);
+
+		// The hooks of the system pallet clear the weight and the events of the previous block.
+		assert!(!runtime.system.events().is_empty());
+		runtime.execute_block(build_block(&runtime, vec![])).unwrap();
+		assert_eq!(runtime.system.block_weight(), 0);
+		assert!(runtime.system.events().is_empty());
+	}
+
+	#[test]
+	fn fees_are_charged_before_dispatch() {
+		let mut runtime = Runtime::new();
+		let context = runtime.system.signing_context();
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		let transfer = |nonce, amount| {
+			support::Extrinsic::new_signed(
+				&alice,
+				nonce,
+				RuntimeCall::balances(balances::Call::transfer { to: bob.account_id(), amount }),
+				&context,
+			)
+		};
+		// The fee of an extrinsic depends on its length and the weight of its call.
+		let fee = |extrinsic: &types::Extrinsic, weight| {
+			Runtime::compute_fee(extrinsic.encode().len(), weight)
+		};
+		let first_fee = fee(&transfer(0, 1_000), 10_000);
+		assert_eq!(first_fee, 10 * transfer(0, 1_000).encode().len() as u128 + 10_000);
+
+		// Alice cannot pay the fee, so the block is rejected.
+		runtime.balances.set_balance(&alice.account_id(), first_fee - 1);
+		let block = build_block(&runtime, vec![]);
+		let mut unpaid_block = block.clone();
+		unpaid_block.extrinsics = vec![transfer(0, 0)];
+		unpaid_block.header.extrinsics_root = support::extrinsics_root(&unpaid_block.extrinsics);
+		assert_eq!(runtime.execute_block(unpaid_block), Err(BlockError::CannotPayFees));
+		assert_eq!(runtime.balances.balance(&alice.account_id()), first_fee - 1);
+
+		// The first transfer creates the account of bob, so it uses all of its declared weight.
+		// The second one only updates it, so the unused weight is refunded. The fees are burned.
+		runtime.balances.set_balance(&alice.account_id(), 100_000);
+		let block = build_block(&runtime, vec![transfer(0, 1_000), transfer(1, 1_000)]);
+		runtime.execute_block(block).unwrap();
+		let second_fee = fee(&transfer(1, 1_000), 7_500);
+		assert!(second_fee < first_fee);
+		assert_eq!(
+			runtime.balances.balance(&alice.account_id()),
+			100_000 - 2_000 - first_fee - second_fee
+		);
+		assert_eq!(runtime.balances.balance(&bob.account_id()), 2_000);
+
+		// A failed call still pays its fee.
+		let balance = runtime.balances.balance(&alice.account_id());
+		let block = build_block(&runtime, vec![transfer(2, balance)]);
+		runtime.execute_block(block).unwrap();
+		assert_eq!(
+			runtime.balances.balance(&alice.account_id()),
+			balance - fee(&transfer(2, balance), 10_000)
+		);
+		assert_eq!(runtime.system.nonce(&alice.account_id()), 3);
+	}
+
+	#[test]
+	fn trusted_extrinsics_skip_signatures() {
+		let mut runtime = Runtime::new();
+		let alice = Pair::from_seed("alice").account_id();
+		let bob = Pair::from_seed("bob").account_id();
+		runtime.balances.set_balance(&alice, 100);
+
+		let transfer = balances::Call::transfer { to: bob.clone(), amount: 40 };
+		runtime
+			.apply_trusted_extrinsic(
+				0,
+				RawOrigin::Signed(alice.clone()),
+				RuntimeCall::balances(transfer),
+			)
+			.unwrap();
+		assert_eq!(runtime.balances.balance(&alice), 60);
+		assert_eq!(runtime.balances.balance(&bob), 40);
+		assert_eq!(runtime.system.nonce(&alice), 1);
+
+		// Privileged calls can be made by the root.
+		let force_set_balance = balances::Call::force_set_balance { who: bob.clone(), amount: 0 };
+		runtime
+			.apply_trusted_extrinsic(1, RawOrigin::Root, RuntimeCall::balances(force_set_balance))
+			.unwrap();
+		assert_eq!(runtime.balances.balance(&bob), 0);
+	}
+
+	#[test]
+	fn privileged_calls_require_the_root_origin() {
+		let mut runtime = Runtime::new();
+		let context = runtime.system.signing_context();
+		let alice = Pair::from_seed("alice");
+		runtime.balances.set_balance(&alice.account_id(), 100_000);
+		let force_set_balance =
+			balances::Call::force_set_balance { who: alice.account_id(), amount: 1_000_000 };
+
+		// Signed extrinsics cannot make privileged calls, but they still pay their fee.
+		let extrinsic = support::Extrinsic::new_signed(
+			&alice,
+			0,
+			RuntimeCall::balances(force_set_balance.clone()),
+			&context,
+		);
+		let fee = Runtime::compute_fee(extrinsic.encode().len(), extrinsic.call.weight());
+		let block = build_block(&runtime, vec![extrinsic]);
+		assert!(runtime.execute_block(block).is_ok());
+		assert_eq!(runtime.balances.balance(&alice.account_id()), 100_000 - fee);
+
+		let err = runtime
+			.dispatch(
+				RawOrigin::Signed(alice.account_id()),
+				RuntimeCall::balances(force_set_balance.clone()),
+			)
+			.unwrap_err();
+		assert_eq!(err.error, RuntimeError::balances(balances::Error::BadOrigin));
+		let err = runtime
+			.dispatch(RawOrigin::None, RuntimeCall::balances(force_set_balance.clone()))
+			.unwrap_err();
+		assert_eq!(err.error, RuntimeError::balances(balances::Error::BadOrigin));
+
+		// The root can make them.
+		runtime
+			.dispatch(RawOrigin::Root, RuntimeCall::balances(force_set_balance))
+			.unwrap();
+		assert_eq!(runtime.balances.balance(&alice.account_id()), 1_000_000);
+	}
+
+	// The same runtime, with its pallets declared in another order. Their indices are unchanged, so
+	// it must encode calls and store its state like the runtime does.
+	mod reordered {
+		use crate::{
+			balances,
+			codec::{Decode, Encode},
+			crypto::Pair,
+			proof_of_existence,
+			support::{self, Dispatch},
+			system,
+		};
+
+		mod types {
+			pub type Nonce = crate::types::Nonce;
+			pub type Extrinsic = crate::support::Extrinsic<Nonce, super::RuntimeCall>;
+			pub type Header = crate::types::Header;
+			pub type Block = crate::support::Block<Header, Extrinsic>;
+			pub type ExtrinsicReceipt =
+				crate::support::ExtrinsicReceipt<super::RuntimeEvent, super::RuntimeError>;
+			pub type BlockReceipt =
+				crate::support::BlockReceipt<super::RuntimeEvent, super::RuntimeError>;
+		}
+
+		#[derive(Debug, Clone, PartialEq)]
+		#[macros::runtime]
+		pub struct Runtime {
+			system: system::Pallet<Self>,
+			#[pallet_index(2)]
+			proof_of_existence: proof_of_existence::Pallet<Self>,
+			#[pallet_index(1)]
+			balances: balances::Pallet<Self>,
+		}
+
+		impl system::Config for Runtime {
+			type AccountId = <crate::Runtime as system::Config>::AccountId;
+			type BlockNumber = <crate::Runtime as system::Config>::BlockNumber;
+			type Nonce = <crate::Runtime as system::Config>::Nonce;
+			type RuntimeOrigin = <crate::Runtime as system::Config>::RuntimeOrigin;
+			type RuntimeEvent = RuntimeEvent;
+			const MAX_BLOCK_WEIGHT: support::Weight = crate::Runtime::MAX_BLOCK_WEIGHT;
+			const VERSION: support::RuntimeVersion = crate::Runtime::VERSION;
+		}
+
+		impl balances::Config for Runtime {
+			type Balance = <crate::Runtime as balances::Config>::Balance;
+			const BYTE_FEE: Self::Balance = crate::Runtime::BYTE_FEE;
+			const WEIGHT_FEE: Self::Balance = crate::Runtime::WEIGHT_FEE;
+			fn fee_recipient() -> Option<Self::AccountId> {
+				crate::Runtime::fee_recipient()
+			}
+		}
+
+		impl proof_of_existence::Config for Runtime {
+			type Content = <crate::Runtime as proof_of_existence::Config>::Content;
+		}
+
+		impl support::ChargeTransaction for Runtime {
+			type AccountId = <Self as system::Config>::AccountId;
+			type Balance = <Self as balances::Config>::Balance;
+
+			fn compute_fee(len: usize, weight: support::Weight) -> Self::Balance {
+				balances::Pallet::<Self>::compute_fee(len, weight)
+			}
+
+			fn can_pay_fee(&self, who: &Self::AccountId, fee: Self::Balance) -> bool {
+				self.balances.can_withdraw_fee(who, fee)
+			}
+
+			fn withdraw_fee(&mut self, who: &Self::AccountId, fee: Self::Balance) -> bool {
+				self.balances.withdraw_fee(who, fee).is_ok()
+			}
+
+			fn settle_fee(
+				&mut self,
+				who: &Self::AccountId,
+				charged: Self::Balance,
+				actual: Self::Balance,
+			) {
+				self.balances.settle_fee(who, charged, actual);
+			}
+		}
+
+		#[test]
+		fn encoding_does_not_depend_on_the_declaration_order() {
+			let alice = Pair::from_seed("alice");
+			let bob = Pair::from_seed("bob").account_id();
+			let transfer = balances::Call::transfer { to: bob.clone(), amount: 30 };
+			let claim = "Hello, world!".to_string();
+			let calls = vec![
+				(
+					crate::RuntimeCall::balances(balances::Call::transfer { to: bob, amount: 30 }),
+					RuntimeCall::balances(transfer),
+				),
+				(
+					crate::RuntimeCall::proof_of_existence(
+						proof_of_existence::Call::create_claim { claim: claim.clone() },
+					),
+					RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+						claim,
+					}),
+				),
+			];
+			for (call, reordered_call) in &calls {
+				assert_eq!(call.encode(), reordered_call.encode());
+				assert_eq!(RuntimeCall::decode_all(&call.encode()).as_ref(), Ok(reordered_call));
+			}
+
+			// So blocks built by the runtime are executed by this one, ending in the same state.
+			let mut runtime = crate::dev_genesis().build();
+			let mut reordered = RuntimeGenesisConfig {
+				balances: balances::GenesisConfig {
+					balances: vec![(alice.account_id(), 1_000_000)],
+				},
+				..Default::default()
+			}
+			.build();
+			assert_eq!(runtime.committed_state(), reordered.committed_state());
+			let context = runtime.system.signing_context();
+			let extrinsics = calls.into_iter().enumerate().map(|(i, (call, _))| {
+				support::Extrinsic::new_signed(&alice, i as u32, call, &context)
+			});
+			let block = crate::build_block(&runtime, extrinsics.collect());
+			runtime.execute_block(block.clone()).unwrap();
+			reordered
+				.execute_block(types::Block::decode_all(&block.encode()).unwrap())
+				.unwrap();
+			assert_eq!(runtime.committed_state(), reordered.committed_state());
+		}
+	}
+
+	// A runtime whose pallets have hooks which change their state, emit events and weigh something.
+	// The same pallet is declared twice, so the order of the hooks can be observed.
+	mod hooks {
+		use self::counter as first;
+		use self::counter as second;
+		use crate::{
+			crypto::Pair,
+			support::{self, BlockError, Dispatch, EventRecord, Phase},
+			system,
+		};
+
+		// A pallet counting the blocks in its `on_initialize` hook, and the increments made by its
+		// call. It reports the total in its `on_finalize` hook.
+		mod counter {
+			use crate::{
+				storage::{StorageMap, StorageValue},
+				support::{DispatchResult, EventQueue, Hooks, OnRuntimeUpgrade, Weight},
+				system::{ensure_signed, BadOrigin},
+			};
+
+			pub const ON_INITIALIZE_WEIGHT: Weight = 300;
+			pub const ON_FINALIZE_WEIGHT: Weight = 50;
+			pub const INCREMENT_WEIGHT: Weight = 150;
+
+			pub trait Config: crate::system::Config {}
+
+			#[derive(Debug, Clone, PartialEq, macros::EnumMetadata)]
+			pub enum Event<T: Config> {
+				Initialized { blocks: u32 },
+				Incremented { who: T::AccountId, count: u32 },
+				Finalized { total: u32 },
+			}
+
+			#[derive(Debug, Clone, Copy, PartialEq, Eq, macros::EnumMetadata)]
+			pub enum Error {
+				BadOrigin,
+			}
+
+			impl crate::support::PalletError for Error {
+				fn error_index(&self) -> u8 {
+					*self as u8
+				}
+			}
+
+			impl From<BadOrigin> for Error {
+				fn from(_: BadOrigin) -> Self {
+					Error::BadOrigin
+				}
+			}
+
+			impl core::fmt::Display for Error {
+				fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+					write!(f, "Bad origin.")
+				}
+			}
+
+			// The pallet always starts empty.
+			#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+			#[serde(bound = "", default, deny_unknown_fields)]
+			pub struct GenesisConfig<T: Config> {
+				#[serde(skip)]
+				config: core::marker::PhantomData<T>,
+			}
+
+			impl<T: Config> Default for GenesisConfig<T> {
+				fn default() -> Self {
+					Self { config: core::marker::PhantomData }
+				}
+			}
+
+			impl<T: Config> GenesisConfig<T> {
+				pub fn build(&self, _pallet: &mut Pallet<T>) {}
+			}
+
+			#[macros::storage]
+			#[derive(Debug, Clone, PartialEq)]
+			pub struct Pallet<T: Config> {
+				// The number of blocks initialized so far.
+				#[getter(blocks)]
+				blocks: StorageValue<u32>,
+				// The number of times each account was incremented so far.
+				counts: StorageMap<T::AccountId, u32>,
+				// The sum of the counts of every account.
+				total: StorageValue<u32>,
+				#[transactional]
+				events: EventQueue<Event<T>>,
+			}
+
+			impl<T: Config> Pallet<T> {
+				pub fn new() -> Self {
+					Self {
+						blocks: StorageValue::new(),
+						counts: StorageMap::new(),
+						total: StorageValue::new(),
+						events: EventQueue::new(),
+					}
+				}
+
+				pub fn take_events(&mut self) -> Vec<Event<T>> {
+					self.events.take()
+				}
+			}
+
+			impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {
+				fn on_initialize(&mut self, _n: T::BlockNumber) -> Weight {
+					let blocks = self.blocks.mutate(|blocks| {
+						*blocks += 1;
+						*blocks
+					});
+					self.events.deposit(Event::Initialized { blocks });
+					ON_INITIALIZE_WEIGHT
+				}
+
+				fn on_finalize(&mut self, _n: T::BlockNumber) -> Weight {
+					self.events.deposit(Event::Finalized { total: *self.total.get() });
+					ON_FINALIZE_WEIGHT
+				}
+			}
+
+			impl<T: Config> OnRuntimeUpgrade for Pallet<T> {}
+
+			#[macros::call]
+			impl<T: Config> Pallet<T> {
+				#[call_index(0)]
+				#[weight(INCREMENT_WEIGHT)]
+				pub fn increment(
+					&mut self,
+					origin: T::RuntimeOrigin,
+					who: T::AccountId,
+				) -> DispatchResult<Error> {
+					ensure_signed(origin)?;
+					let count = self.counts.get(&who).copied().unwrap_or(0) + 1;
+					self.counts.insert(who.clone(), count);
+					self.total.mutate(|total| *total += 1);
+					self.events.deposit(Event::Incremented { who, count });
+					Ok(())
+				}
+			}
+		}
+
+		mod types {
+			pub type Nonce = crate::types::Nonce;
+			pub type Extrinsic = crate::support::Extrinsic<Nonce, super::RuntimeCall>;
+			pub type Header = crate::types::Header;
+			pub type Block = crate::support::Block<Header, Extrinsic>;
+			pub type ExtrinsicReceipt =
+				crate::support::ExtrinsicReceipt<super::RuntimeEvent, super::RuntimeError>;
+			pub type BlockReceipt =
+				crate::support::BlockReceipt<super::RuntimeEvent, super::RuntimeError>;
+		}
+
+		#[derive(Debug, Clone, PartialEq)]
+		#[macros::runtime]
+		pub struct Runtime {
+			system: system::Pallet<Self>,
+			#[pallet_index(1)]
+			first: first::Pallet<Self>,
+			#[pallet_index(2)]
+			second: second::Pallet<Self>,
+		}
+
+		impl system::Config for Runtime {
+			type AccountId = <crate::Runtime as system::Config>::AccountId;
+			type BlockNumber = <crate::Runtime as system::Config>::BlockNumber;
+			type Nonce = <crate::Runtime as system::Config>::Nonce;
+			type RuntimeOrigin = <crate::Runtime as system::Config>::RuntimeOrigin;
+			type RuntimeEvent = RuntimeEvent;
+			const MAX_BLOCK_WEIGHT: support::Weight = 1_000;
+			const VERSION: support::RuntimeVersion = crate::Runtime::VERSION;
+		}
+
+		impl counter::Config for Runtime {}
+
+		// Extrinsics are free in this runtime.
+		impl support::ChargeTransaction for Runtime {
+			type AccountId = <Self as system::Config>::AccountId;
+			type Balance = u128;
+
+			fn compute_fee(_len: usize, _weight: support::Weight) -> u128 {
+				0
+			}
+
+			fn can_pay_fee(&self, _who: &Self::AccountId, _fee: u128) -> bool {
+				true
+			}
+
+			fn withdraw_fee(&mut self, _who: &Self::AccountId, _fee: u128) -> bool {
+				true
+			}
+
+			fn settle_fee(&mut self, _who: &Self::AccountId, _charged: u128, _actual: u128) {}
+		}
+
+		// Build a block applying `extrinsics` on top of the last block executed by `runtime`.
+		fn build_block(runtime: &Runtime, extrinsics: Vec<types::Extrinsic>) -> types::Block {
+			let mut header = support::Header {
+				parent_hash: runtime.system.parent_hash(),
+				block_number: runtime.system.block_number() + 1,
+				state_root: support::Hash::default(),
+				extrinsics_root: support::extrinsics_root(&extrinsics),
+			};
+			let mut runtime = runtime.clone();
+			runtime.initialize_block(&header).unwrap();
+			for (index, extrinsic) in extrinsics.iter().enumerate() {
+				runtime.apply_extrinsic(index as u32, extrinsic.clone()).unwrap();
+			}
+			runtime.finalize_block();
+			header.state_root = runtime.state_root();
+			types::Block { header, extrinsics }
+		}
+
+		#[test]
+		fn hooks_change_the_state_and_weigh_against_the_block() {
+			let mut runtime = RuntimeGenesisConfig::default().build();
+			let context = runtime.system.signing_context();
+			let alice = Pair::from_seed("alice");
+			let bob = Pair::from_seed("bob").account_id();
+			let first = RuntimeCall::first(first::Call::increment { who: bob.clone() });
+			let second = RuntimeCall::second(second::Call::increment { who: bob.clone() });
+			let sign = |nonce, call| support::Extrinsic::new_signed(&alice, nonce, call, &context);
+			let block = build_block(&runtime, vec![sign(0, first.clone()), sign(1, second)]);
+			runtime.execute_block(block).unwrap();
+			assert_eq!(runtime.first.blocks(), 1);
+			assert_eq!(runtime.second.blocks(), 1);
 
-	// execute blocks, otherwise panic with "invalid block"
-	runtime.execute_block(block_1).expect("invalid block");
-	runtime.execute_block(block_2).expect("invalid block");
-	runtime.execute_block(block_3).expect("invalid block");
+			// The hooks run in the order the pallets are declared, before and after the
+			// extrinsics.
+			let incremented = counter::Event::Incremented { who: bob, count: 1 };
+			let events = [
+				(
+					Phase::Initialization,
+					RuntimeEvent::first(counter::Event::Initialized { blocks: 1 }),
+				),
+				(
+					Phase::Initialization,
+					RuntimeEvent::second(counter::Event::Initialized { blocks: 1 }),
+				),
+				(Phase::ApplyExtrinsic(0), RuntimeEvent::first(incremented.clone())),
+				(Phase::ApplyExtrinsic(1), RuntimeEvent::second(incremented)),
+				(Phase::Finalization, RuntimeEvent::first(counter::Event::Finalized { total: 1 })),
+				(Phase::Finalization, RuntimeEvent::second(counter::Event::Finalized { total: 1 })),
+			];
+			let events = events.map(|(phase, event)| EventRecord { phase, event });
+			assert_eq!(runtime.system.events(), events);
+			let weight = 2 * counter::ON_INITIALIZE_WEIGHT
+				+ 2 * counter::INCREMENT_WEIGHT
+				+ 2 * counter::ON_FINALIZE_WEIGHT;
+			assert_eq!(runtime.system.block_weight(), weight);
 
-	println!("{:#?}", runtime);
+			// Three calls fit in a block on their own, but not after the `on_initialize` hooks.
+			let max_block_weight = <Runtime as system::Config>::MAX_BLOCK_WEIGHT;
+			assert!(3 * counter::INCREMENT_WEIGHT <= max_block_weight);
+			assert!(
+				2 * counter::ON_INITIALIZE_WEIGHT + 3 * counter::INCREMENT_WEIGHT
+					> max_block_weight
+			);
+			let calls = (2..5).map(|nonce| sign(nonce, first.clone())).collect::<Vec<_>>();
+			let mut block = build_block(&runtime, calls[..2].to_vec());
+			block.extrinsics = calls;
+			block.header.extrinsics_root = support::extrinsics_root(&block.extrinsics);
+			assert_eq!(runtime.execute_block(block), Err(BlockError::ExhaustsResources));
+			assert_eq!(runtime.first.blocks(), 1);
+		}
+	}
 }
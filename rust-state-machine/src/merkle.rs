@@ -0,0 +1,309 @@
+use crate::support::{hash, Hash, Header};
+
+// A binary Merkle tree, committing to a list of leaves with a single root hash.
+//
+// The tree is built one level at a time, by hashing pairs of nodes from left to right. When a level
+// has an odd number of nodes, the last node is moved up to the next level unchanged. Leaves and
+// inner nodes are hashed with a different prefix, so a leaf can never be mistaken for a node.
+
+// The prefix of the data hashed into a leaf.
+const LEAF_PREFIX: u8 = 0;
+// The prefix of the data hashed into an inner node.
+const NODE_PREFIX: u8 = 1;
+
+// The hash of a leaf of the tree, committing to a `(key, value)` pair.
+pub fn leaf_hash(key: &[u8], value: &[u8]) -> Hash {
+	let mut data = vec![LEAF_PREFIX];
+	data.extend_from_slice(&(key.len() as u32).to_le_bytes());
+	data.extend_from_slice(key);
+	data.extend_from_slice(value);
+	hash(&data)
+}
+
+// The hash of an inner node of the tree, committing to its two children.
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+	let mut data = vec![NODE_PREFIX];
+	data.extend_from_slice(left);
+	data.extend_from_slice(right);
+	hash(&data)
+}
+
+// Compute the root of the tree with the given leaves.
+//
+// The root of an empty tree is the hash of no data.
+pub fn root(leaves: Vec<Hash>) -> Hash {
+	if leaves.is_empty() {
+		return hash(&[]);
+	}
+
+	let mut level = leaves;
+	while level.len() > 1 {
+		level = level
+			.chunks(2)
+			.map(|pair| match pair {
+				[left, right] => node_hash(left, right),
+				[last] => *last,
+				_ => unreachable!("chunks have one or two nodes"),
+			})
+			.collect();
+	}
+	level[0]
+}
+
+// A proof that a leaf is part of a tree, made of the sibling of every node on the path from the
+// leaf to the root.
+#[derive(Debug, Clone, PartialEq, Eq, macros::Encode, macros::Decode)]
+pub struct MerkleProof {
+	// The position of the leaf in the tree.
+	pub leaf_index: u32,
+	// The number of leaves in the tree.
+	pub leaf_count: u32,
+	// The siblings of the nodes on the path, from the leaf up. Nodes moved up unchanged have no
+	// sibling.
+	pub siblings: Vec<Hash>,
+}
+
+// Create a proof that the leaf at `index` is part of the tree with the given leaves.
+pub fn prove(leaves: Vec<Hash>, index: usize) -> MerkleProof {
+	assert!(index < leaves.len(), "the leaf to prove is part of the tree");
+	let mut proof =
+		MerkleProof { leaf_index: index as u32, leaf_count: leaves.len() as u32, siblings: vec![] };
+
+	let mut level = leaves;
+	let mut index = index;
+	while level.len() > 1 {
+		// The sibling of a node is its neighbour in its pair, if it is not the last node of an odd
+		// level.
+		if let Some(sibling) = level.get(index ^ 1) {
+			proof.siblings.push(*sibling);
+		}
+		level = level
+			.chunks(2)
+			.map(|pair| match pair {
+				[left, right] => node_hash(left, right),
+				[last] => *last,
+				_ => unreachable!("chunks have one or two nodes"),
+			})
+			.collect();
+		index /= 2;
+	}
+	proof
+}
+
+// Check that `leaf` is part of the tree with the given `root`, at the position given by `proof`.
+pub fn verify(root: &Hash, leaf: Hash, proof: &MerkleProof) -> bool {
+	if proof.leaf_index >= proof.leaf_count {
+		return false;
+	}
+
+	let mut node = leaf;
+	let mut index = proof.leaf_index;
+	let mut len = proof.leaf_count;
+	let mut siblings = proof.siblings.iter();
+	while len > 1 {
+		let is_left = index.is_multiple_of(2);
+		// The last node of an odd level is moved up unchanged.
+		if !(is_left && index + 1 == len) {
+			let Some(sibling) = siblings.next() else { return false };
+			node = if is_left { node_hash(&node, sibling) } else { node_hash(sibling, &node) };
+		}
+		index /= 2;
+		len = len.div_ceil(2);
+	}
+	siblings.next().is_none() && node == *root
+}
+
+// A `(key, value)` pair of the state, along with a proof that it is part of the state.
+#[derive(Debug, Clone, PartialEq, Eq, macros::Encode, macros::Decode)]
+pub struct ProvenPair {
+	pub key: Vec<u8>,
+	pub value: Vec<u8>,
+	pub proof: MerkleProof,
+}
+
+// A proof of the value stored at some key of the state, checked against a state root.
+//
+// The leaves of the state tree are ordered by key. So a key which is not in the state can be proven
+// absent by showing the two leaves next to each other which would surround it.
+#[derive(Debug, Clone, PartialEq, Eq, macros::Encode, macros::Decode)]
+pub enum StorageProof {
+	// The key is in the state, with this value.
+	Present(ProvenPair),
+	// The key is not in the state. `before` is the pair with the greatest key lower than the key,
+	// and `after` the pair with the lowest key greater than the key, if they exist.
+	Absent { before: Option<ProvenPair>, after: Option<ProvenPair> },
+}
+
+// The error returned when a `StorageProof` does not match the state root it is checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidProof;
+
+// Compute the root of a Merkle tree over the `(key, value)` pairs of the state, ordered by key.
+pub fn storage_root(pairs: &[(Vec<u8>, Vec<u8>)]) -> Hash {
+	root(pairs.iter().map(|(key, value)| leaf_hash(key, value)).collect())
+}
+
+// Create a proof of the value stored at `key`, in the state made of `pairs` ordered by key.
+pub fn prove_storage(pairs: &[(Vec<u8>, Vec<u8>)], key: &[u8]) -> StorageProof {
+	let leaves = pairs.iter().map(|(key, value)| leaf_hash(key, value)).collect::<Vec<_>>();
+	let proven_pair = |index: usize| {
+		let (key, value) = pairs[index].clone();
+		ProvenPair { key, value, proof: prove(leaves.clone(), index) }
+	};
+
+	match pairs.binary_search_by(|(k, _)| k.as_slice().cmp(key)) {
+		Ok(index) => StorageProof::Present(proven_pair(index)),
+		Err(index) => StorageProof::Absent {
+			before: index.checked_sub(1).map(proven_pair),
+			after: (index < pairs.len()).then(|| proven_pair(index)),
+		},
+	}
+}
+
+// Check a proof of the value stored at `key` against the state root of `header`.
+//
+// Returns the value stored at `key`, or `None` if the proof shows that `key` is not in the state.
+// This is the verifier of light clients, which only hold headers and check the proofs served by
+// `state_getReadProof`. The node itself never checks proofs.
+#[allow(dead_code)]
+pub fn verify_storage_proof<BlockNumber>(
+	header: &Header<BlockNumber>,
+	key: &[u8],
+	proof: &StorageProof,
+) -> Result<Option<Vec<u8>>, InvalidProof> {
+	let root = &header.state_root;
+	let verify_pair =
+		|pair: &ProvenPair| verify(root, leaf_hash(&pair.key, &pair.value), &pair.proof);
+
+	match proof {
+		StorageProof::Present(pair) if pair.key == key && verify_pair(pair) => {
+			Ok(Some(pair.value.clone()))
+		},
+		StorageProof::Present(_) => Err(InvalidProof),
+		StorageProof::Absent { before, after } => {
+			// Each pair must be part of the state, and be on the correct side of `key`.
+			let before_valid =
+				before.iter().all(|pair| pair.key.as_slice() < key && verify_pair(pair));
+			let after_valid =
+				after.iter().all(|pair| pair.key.as_slice() > key && verify_pair(pair));
+			// The pairs must be next to each other, or at the edges of the state.
+			let adjacent = match (before, after) {
+				(Some(before), Some(after)) => {
+					before.proof.leaf_index + 1 == after.proof.leaf_index
+						&& before.proof.leaf_count == after.proof.leaf_count
+				},
+				(Some(before), None) => before.proof.leaf_index + 1 == before.proof.leaf_count,
+				(None, Some(after)) => after.proof.leaf_index == 0,
+				// Only an empty state has no pairs at all.
+				(None, None) => *root == storage_root(&[]),
+			};
+			if before_valid && after_valid && adjacent {
+				Ok(None)
+			} else {
+				Err(InvalidProof)
+			}
+		},
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{
+		leaf_hash, node_hash, prove, prove_storage, root, storage_root, verify,
+		verify_storage_proof, InvalidProof, StorageProof,
+	};
+	use crate::support::{Hash, Header};
+
+	#[test]
+	fn root_of_leaves() {
+		let a = leaf_hash(b"a", b"1");
+		let b = leaf_hash(b"b", b"2");
+		let c = leaf_hash(b"c", b"3");
+
+		assert_eq!(root(vec![a]), a);
+		assert_eq!(root(vec![a, b]), node_hash(&a, &b));
+		// The last node of an odd level is moved up unchanged.
+		assert_eq!(root(vec![a, b, c]), node_hash(&node_hash(&a, &b), &c));
+		// The order of the leaves matters.
+		assert_ne!(root(vec![a, b]), root(vec![b, a]));
+		assert_ne!(root(vec![]), root(vec![a]));
+	}
+
+	#[test]
+	fn leaf_hash_separates_key_and_value() {
+		assert_ne!(leaf_hash(b"ab", b"c"), leaf_hash(b"a", b"bc"));
+		assert_ne!(leaf_hash(b"a", b"1"), leaf_hash(b"a", b"2"));
+	}
+
+	#[test]
+	fn prove_every_leaf() {
+		for count in 1..=9u8 {
+			let leaves = (0..count).map(|i| leaf_hash(&[i], &[i])).collect::<Vec<_>>();
+			let root = root(leaves.clone());
+			for (index, leaf) in leaves.iter().enumerate() {
+				let proof = prove(leaves.clone(), index);
+				assert!(verify(&root, *leaf, &proof));
+				// The proof is only valid for this leaf, at this position.
+				assert!(!verify(&root, leaf_hash(b"other", b"leaf"), &proof));
+				let mut moved = proof.clone();
+				moved.leaf_index = (moved.leaf_index + 1) % count as u32;
+				assert!(count == 1 || !verify(&root, *leaf, &moved));
+			}
+		}
+	}
+
+	fn header(state_root: Hash) -> Header<u32> {
+		Header {
+			parent_hash: Hash::default(),
+			block_number: 1,
+			state_root,
+			extrinsics_root: Hash::default(),
+		}
+	}
+
+	#[test]
+	fn storage_proofs() {
+		let pairs = vec![
+			(b"b".to_vec(), b"1".to_vec()),
+			(b"d".to_vec(), b"2".to_vec()),
+			(b"f".to_vec(), b"3".to_vec()),
+		];
+		let header = header(storage_root(&pairs));
+
+		// Keys in the state are proven with their value.
+		for (key, value) in &pairs {
+			let proof = prove_storage(&pairs, key);
+			assert_eq!(verify_storage_proof(&header, key, &proof), Ok(Some(value.clone())));
+			// The proof is not valid for another key.
+			assert_eq!(verify_storage_proof(&header, b"c", &proof), Err(InvalidProof));
+		}
+
+		// Keys not in the state are proven absent, including before the first and after the last
+		// key.
+		for key in [b"a", b"c", b"e", b"g"] {
+			let proof = prove_storage(&pairs, key);
+			assert_eq!(verify_storage_proof(&header, key, &proof), Ok(None));
+		}
+
+		// An absence proof cannot skip over a key which is in the state.
+		let StorageProof::Absent { before, .. } = prove_storage(&pairs, b"c") else { panic!() };
+		let StorageProof::Absent { after, .. } = prove_storage(&pairs, b"e") else { panic!() };
+		let skipping = StorageProof::Absent { before, after };
+		assert_eq!(verify_storage_proof(&header, b"d", &skipping), Err(InvalidProof));
+
+		// A proof cannot be checked against another state root.
+		let proof = prove_storage(&pairs, b"b");
+		let other_header = self::header(storage_root(&pairs[1..]));
+		assert_eq!(verify_storage_proof(&other_header, b"b", &proof), Err(InvalidProof));
+	}
+
+	#[test]
+	fn storage_proofs_in_empty_state() {
+		let header = header(storage_root(&[]));
+		let proof = prove_storage(&[], b"a");
+		assert_eq!(verify_storage_proof(&header, b"a", &proof), Ok(None));
+
+		let other_header = self::header(storage_root(&[(b"a".to_vec(), b"1".to_vec())]));
+		assert_eq!(verify_storage_proof(&other_header, b"a", &proof), Err(InvalidProof));
+	}
+}
@@ -0,0 +1,125 @@
+use serde::Serialize;
+
+// A description of the runtime, listing every pallet along with its calls, events and errors.
+//
+// It is generated by the `#[macros::runtime]` macro, so client tooling can discover what the
+// runtime exposes instead of hard-coding it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuntimeMetadata {
+	// The pallets of the runtime, in the order they are declared. The system pallet is not
+	// included, since it is not callable.
+	pub pallets: Vec<PalletMetadata>,
+}
+
+// A description of a single pallet of the runtime.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PalletMetadata {
+	// The name of the pallet in the runtime.
+	pub name: &'static str,
+	// The index of the pallet, which is the first byte of the encoding of its calls.
+	pub index: u8,
+	// The calls of the pallet, described by the `#[macros::call]` macro.
+	pub calls: Vec<VariantMetadata>,
+	// The variants of the `Event` enum of the pallet.
+	pub events: Vec<VariantMetadata>,
+	// The variants of the `Error` enum of the pallet.
+	pub errors: Vec<VariantMetadata>,
+}
+
+// A description of a variant of an enum, like a call, an event or an error.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VariantMetadata {
+	// The name of the variant.
+	pub name: &'static str,
+	// The index of the variant in its encoding.
+	pub index: u8,
+	// The fields of the variant, in the order they are encoded.
+	pub fields: Vec<FieldMetadata>,
+	// The lines of the doc comments on the variant.
+	pub docs: Vec<&'static str>,
+}
+
+// A description of a field of a variant, like the argument of a call.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldMetadata {
+	// The name of the field, or `None` for the fields of a tuple variant.
+	pub name: Option<&'static str>,
+	// The type of the field, as written in the source code.
+	#[serde(rename = "type")]
+	pub type_name: &'static str,
+}
+
+// A trait for enums which can describe their variants.
+//
+// It is implemented for the `Call` enum of a pallet by the `#[macros::call]` macro, and can be
+// derived for other enums with `#[derive(macros::EnumMetadata)]`.
+pub trait EnumMetadata {
+	// A description of every variant of the enum.
+	fn variants() -> Vec<VariantMetadata>;
+}
+
+impl RuntimeMetadata {
+	// Render the metadata as JSON.
+	pub fn to_json(&self) -> String {
+		serde_json::to_string_pretty(self).expect("metadata only contains strings and numbers")
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{EnumMetadata, FieldMetadata, VariantMetadata};
+	use crate::codec::Encode;
+
+	// An enum using every kind of variant.
+	#[derive(macros::Encode, macros::EnumMetadata)]
+	enum Example<T> {
+		/// A variant without fields.
+		Unit,
+		/// A variant with named fields.
+		///
+		/// Its docs span multiple lines.
+		Named {
+			value: Vec<T>,
+			pair: (u8, bool),
+		},
+		Unnamed(u32),
+	}
+
+	#[test]
+	fn derive_enum_metadata() {
+		let field = |name, type_name| FieldMetadata { name, type_name };
+		assert_eq!(
+			Example::<u8>::variants(),
+			vec![
+				VariantMetadata {
+					name: "Unit",
+					index: 0,
+					fields: vec![],
+					docs: vec!["A variant without fields."],
+				},
+				VariantMetadata {
+					name: "Named",
+					index: 1,
+					fields: vec![field(Some("value"), "Vec<T>"), field(Some("pair"), "(u8, bool)")],
+					docs: vec!["A variant with named fields.", "", "Its docs span multiple lines."],
+				},
+				VariantMetadata {
+					name: "Unnamed",
+					index: 2,
+					fields: vec![field(None, "u32")],
+					docs: vec![],
+				},
+			]
+		);
+
+		// The index of each variant is the one it is encoded with.
+		let examples = [
+			Example::Unit,
+			Example::Named { value: vec![1u8], pair: (2, true) },
+			Example::Unnamed(3),
+		];
+		for (example, variant) in examples.iter().zip(Example::<u8>::variants()) {
+			assert_eq!(example.encode()[0], variant.index);
+		}
+	}
+}
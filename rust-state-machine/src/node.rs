@@ -0,0 +1,456 @@
+use crate::{
+	backend::{Backend, BackendError, BlockStore, FileBackend},
+	block_builder::BlockBuilder,
+	block_tree::{BlockTree, ForkChoice, ImportError, FINALITY_DEPTH},
+	codec::{self, Decode, Encode},
+	support::Hash,
+	transaction_pool::{PoolError, PoolLimits, TransactionPool},
+	types, Runtime, RuntimeGenesisConfig,
+};
+use std::path::{Path, PathBuf};
+
+// A node keeps a chain in a data directory, so it can be stopped and started again between any two
+// operations. The directory holds:
+// - `state.log`, the state of the runtime after the last block, kept by a `FileBackend`.
+// - `blocks.log`, every block of the chain since the genesis, kept by a `BlockStore`.
+// - `pool`, the encoded extrinsics waiting in the transaction pool.
+
+// The errors returned by the operations of a node.
+#[derive(Debug)]
+pub enum NodeError {
+	// The data directory could not be read or written.
+	Backend(BackendError),
+	// The data directory does not hold a chain yet.
+	NotInitialized,
+	// The data directory already holds a chain.
+	AlreadyInitialized,
+	// The data directory holds a state or extrinsics which do not decode.
+	Corrupted(codec::Error),
+	// The blocks in the data directory do not lead to its state.
+	Inconsistent,
+	// An extrinsic was rejected by the transaction pool.
+	Pool(PoolError),
+	// A block could not be imported.
+	Import(ImportError),
+}
+
+impl core::fmt::Display for NodeError {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			NodeError::Backend(e) => write!(f, "{}", e),
+			NodeError::NotInitialized => write!(f, "the data directory holds no chain"),
+			NodeError::AlreadyInitialized => write!(f, "the data directory already holds a chain"),
+			NodeError::Corrupted(e) => write!(f, "the data directory is corrupted: {}", e),
+			NodeError::Inconsistent => {
+				write!(f, "the blocks of the data directory do not lead to its state")
+			},
+			NodeError::Pool(e) => write!(f, "extrinsic rejected: {}", e),
+			NodeError::Import(e) => write!(f, "cannot import block: {}", e),
+		}
+	}
+}
+
+impl From<BackendError> for NodeError {
+	fn from(e: BackendError) -> Self {
+		NodeError::Backend(e)
+	}
+}
+
+// The state after `blocks`, the first blocks of the chain kept with `backend`. It is rebuilt from
+// the last state committed among them, or the genesis state, by executing the next blocks again.
+fn state_after(backend: &FileBackend, blocks: &[types::Block]) -> Result<Runtime, NodeError> {
+	let start = (1..=blocks.len())
+		.rev()
+		.find(|count| backend.is_committed(&blocks[count - 1].header.hash()))
+		.unwrap_or(0);
+	let block_hash = match start {
+		0 => Hash::default(),
+		count => blocks[count - 1].header.hash(),
+	};
+	let state = backend.load_at(&block_hash)?.ok_or(NodeError::Inconsistent)?;
+	let mut runtime = Runtime::restore(&state).map_err(NodeError::Corrupted)?;
+	for block in &blocks[start..] {
+		runtime.execute_block(block.clone()).map_err(|_| NodeError::Inconsistent)?;
+	}
+	Ok(runtime)
+}
+
+// A chain kept in a data directory, along with its transaction pool.
+pub struct Node {
+	dir: PathBuf,
+	backend: FileBackend,
+	blocks: BlockStore<types::Block>,
+	runtime: Runtime,
+	pool: TransactionPool<Runtime>,
+}
+
+impl Node {
+	// Start a new chain in `dir` from `genesis`. Fails if `dir` already holds a chain.
+	pub fn init(dir: &Path, genesis: &RuntimeGenesisConfig) -> Result<Self, NodeError> {
+		std::fs::create_dir_all(dir).map_err(BackendError::Io)?;
+		let mut backend = FileBackend::open(&dir.join("state.log"))?;
+		if backend.load().is_some() {
+			return Err(NodeError::AlreadyInitialized);
+		}
+		// Whatever blocks were left by a chain which was never committed belong to no chain.
+		let mut blocks = BlockStore::open(&dir.join("blocks.log"))?;
+		blocks.truncate(0)?;
+		let runtime = genesis.build();
+		backend.commit(runtime.committed_state())?;
+		let pool = TransactionPool::new(PoolLimits::default());
+		let node = Self { dir: dir.to_path_buf(), backend, blocks, runtime, pool };
+		node.save_pool()?;
+		Ok(node)
+	}
+
+	// Open the chain kept in `dir`, at its last block.
+	pub fn open(dir: &Path) -> Result<Self, NodeError> {
+		let state_path = dir.join("state.log");
+		if !state_path.exists() {
+			return Err(NodeError::NotInitialized);
+		}
+		let mut backend = FileBackend::open(&state_path)?;
+		let state = backend.load().ok_or(NodeError::NotInitialized)?;
+		let mut runtime = Runtime::restore(state).map_err(NodeError::Corrupted)?;
+
+		// Blocks are stored before the state after them is committed, so the last blocks may be
+		// ahead of the state if the node stopped in between. They are dropped.
+		let mut blocks = BlockStore::<types::Block>::open(&dir.join("blocks.log"))?;
+		let head = runtime.system.parent_hash();
+		let position = blocks.blocks().iter().position(|block| block.header.hash() == head);
+		let count = match position {
+			Some(i) => i + 1,
+			None if head == Hash::default() => 0,
+			// The node stopped in the middle of a reorg, once the blocks of the new chain were
+			// stored but before the state after them was committed. The state is rebuilt.
+			None => {
+				runtime = state_after(&backend, blocks.blocks())?;
+				backend.commit(runtime.committed_state())?;
+				blocks.blocks().len()
+			},
+		};
+		if count < blocks.blocks().len() {
+			blocks.truncate(count)?;
+		}
+
+		// The extrinsics of the pool are validated again, against the current state.
+		let mut pool = TransactionPool::new(PoolLimits::default());
+		let pool_path = dir.join("pool");
+		if pool_path.exists() {
+			let bytes = std::fs::read(&pool_path).map_err(BackendError::Io)?;
+			let extrinsics =
+				Vec::<types::Extrinsic>::decode_all(&bytes).map_err(NodeError::Corrupted)?;
+			for extrinsic in extrinsics {
+				let _ = pool.submit(&runtime, extrinsic);
+			}
+		}
+
+		Ok(Self { dir: dir.to_path_buf(), backend, blocks, runtime, pool })
+	}
+
+	// The state after the last block of the chain.
+	pub fn runtime(&self) -> &Runtime {
+		&self.runtime
+	}
+
+	// The transaction pool of the node.
+	pub fn pool(&self) -> &TransactionPool<Runtime> {
+		&self.pool
+	}
+
+	// Every block of the chain, in order.
+	pub fn blocks(&self) -> &[types::Block] {
+		self.blocks.blocks()
+	}
+
+	// The block of the chain with the number `block_number`, if any.
+	pub fn block(&self, block_number: types::BlockNumber) -> Option<&types::Block> {
+		self.blocks().iter().find(|block| block.header.block_number == block_number)
+	}
+
+	// The nonce the next extrinsic of `who` must use, after its extrinsics in the pool.
+	pub fn next_nonce(&self, who: &types::AccountId) -> types::Nonce {
+		self.pool.next_nonce(who).unwrap_or_else(|| self.runtime.system.nonce(who))
+	}
+
+	// Add `extrinsic` to the transaction pool, and return its hash.
+	pub fn submit(&mut self, extrinsic: types::Extrinsic) -> Result<Hash, NodeError> {
+		let hash = self.pool.submit(&self.runtime, extrinsic).map_err(NodeError::Pool)?;
+		self.save_pool()?;
+		Ok(hash)
+	}
+
+	// Build a block with the ready extrinsics of the pool, and add it to the chain.
+	//
+	// The extrinsics which cannot be included are skipped. They stay in the pool if they may be
+	// included in a later block.
+	pub fn produce_block(&mut self) -> Result<(types::Block, types::BlockReceipt), NodeError> {
+		let mut builder = BlockBuilder::new(&self.runtime);
+		builder.push_all(self.pool.ready().into_iter().cloned());
+		let block = builder.build();
+		let mut runtime = self.runtime.clone();
+		let receipt = runtime
+			.execute_block(block.clone())
+			.map_err(|e| NodeError::Import(ImportError::Invalid(e)))?;
+		self.append(block.clone(), &runtime)?;
+		self.set_head(runtime)?;
+		Ok((block, receipt))
+	}
+
+	// Import `blocks`, which may belong to competing forks branching off any block of the chain
+	// after its last final block. The blocks already in the chain are skipped.
+	//
+	// The blocks are imported into a tree rooted at the last final block, along with the blocks of
+	// the chain after it. The chain then follows the best chain of the tree according to
+	// `fork_choice`: its blocks which are not in the best chain are retracted, and their extrinsics
+	// go back to the pool. Returns the number of blocks added to the chain. Nothing changes if one
+	// of the blocks cannot be imported.
+	pub fn import_blocks(
+		&mut self,
+		blocks: Vec<types::Block>,
+		fork_choice: ForkChoice,
+	) -> Result<usize, NodeError> {
+		let known = self.blocks().iter().map(|block| block.header.hash()).collect::<Vec<_>>();
+		let head = self.runtime.system.parent_hash();
+		let root = known.len().saturating_sub(FINALITY_DEPTH as usize);
+		let mut tree =
+			BlockTree::new(state_after(&self.backend, &self.blocks()[..root])?, fork_choice);
+		for block in &self.blocks()[root..] {
+			tree.import(block.clone()).map_err(|_| NodeError::Inconsistent)?;
+		}
+		for block in blocks {
+			if known.contains(&block.header.hash()) {
+				continue;
+			}
+			// The number of blocks of the chain up to the parent of the block, if it is stored.
+			let parent = block.header.parent_hash;
+			let parent_count = match known.iter().position(|hash| *hash == parent) {
+				Some(i) => Some(i + 1),
+				None => (parent == Hash::default()).then_some(0),
+			};
+			if parent_count.is_some_and(|count| count < root) {
+				return Err(NodeError::Import(ImportError::BelowFinality));
+			}
+			match tree.import(block) {
+				Ok(_) | Err(ImportError::AlreadyImported) => {},
+				Err(e) => return Err(NodeError::Import(e)),
+			}
+		}
+		if tree.best_hash() == head {
+			return Ok(0);
+		}
+
+		// The blocks of the best chain are stored in place of the retracted ones, and then the
+		// state after the new head is committed. If the node stops in between, it rebuilds the
+		// state when it is opened.
+		let best_chain = tree.best_chain();
+		let common = known[root..]
+			.iter()
+			.zip(&best_chain)
+			.take_while(|(hash, block)| **hash == block.header.hash())
+			.count();
+		let retracted = self.blocks()[root + common..]
+			.iter()
+			.flat_map(|block| block.extrinsics.clone())
+			.collect::<Vec<_>>();
+		self.blocks.truncate(root + common)?;
+		for block in &best_chain[common..] {
+			self.blocks.append((*block).clone())?;
+		}
+		self.backend.commit(tree.best().committed_state())?;
+		self.set_head(tree.best().clone())?;
+		for extrinsic in retracted {
+			let _ = self.pool.submit(&self.runtime, extrinsic);
+		}
+		self.save_pool()?;
+		Ok(best_chain.len() - common)
+	}
+
+	// Store `block`, followed by the state after it.
+	fn append(&mut self, block: types::Block, state: &Runtime) -> Result<(), NodeError> {
+		self.blocks.append(block)?;
+		self.backend.commit(state.committed_state())?;
+		Ok(())
+	}
+
+	// Continue the chain from `runtime`, the state after its new last block.
+	fn set_head(&mut self, runtime: Runtime) -> Result<(), NodeError> {
+		self.runtime = runtime;
+		self.pool.on_block_imported(&self.runtime);
+		self.save_pool()
+	}
+
+	// Write the extrinsics of the pool to the data directory. The file is replaced at once, so it
+	// is never partially written.
+	fn save_pool(&self) -> Result<(), NodeError> {
+		let bytes = self.pool.extrinsics().encode();
+		let path = self.dir.join("pool");
+		let tmp = self.dir.join("pool.tmp");
+		std::fs::write(&tmp, bytes)
+			.and_then(|_| std::fs::rename(&tmp, &path))
+			.map_err(|e| NodeError::Backend(BackendError::Io(e)))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Node, NodeError};
+	use crate::{
+		balances,
+		block_builder::BlockBuilder,
+		block_tree::{ForkChoice, ImportError},
+		crypto::Pair,
+		support::{self, BlockError},
+		transaction_pool::PoolError,
+		types, RuntimeCall,
+	};
+	use std::path::PathBuf;
+
+	// A data directory in the temporary directory, which does not exist yet.
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!(
+			"rust-state-machine-{}-node-{}",
+			std::process::id(),
+			name
+		));
+		let _ = std::fs::remove_dir_all(&dir);
+		dir
+	}
+
+	// A transfer from alice to bob, signed for the chain of `node`.
+	fn transfer(node: &Node, nonce: u32, amount: u128) -> types::Extrinsic {
+		let to = Pair::from_seed("bob").account_id();
+		let call = RuntimeCall::balances(balances::Call::transfer { to, amount });
+		let context = node.runtime().system.signing_context();
+		support::Extrinsic::new_signed(&Pair::from_seed("alice"), nonce, call, &context)
+	}
+
+	#[test]
+	fn chain_is_kept_between_runs() {
+		let dir = temp_dir("runs");
+		let bob = Pair::from_seed("bob").account_id();
+		assert!(matches!(Node::open(&dir), Err(NodeError::NotInitialized)));
+		let mut node = Node::init(&dir, &crate::dev_genesis()).unwrap();
+		assert!(matches!(
+			Node::init(&dir, &crate::dev_genesis()),
+			Err(NodeError::AlreadyInitialized)
+		));
+
+		// Submitted extrinsics are kept in the pool until a block is produced.
+		node.submit(transfer(&node, 0, 10)).unwrap();
+		drop(node);
+		let mut node = Node::open(&dir).unwrap();
+		assert_eq!(node.next_nonce(&Pair::from_seed("alice").account_id()), 1);
+		assert!(matches!(
+			node.submit(transfer(&node, 0, 10)),
+			Err(NodeError::Pool(PoolError::AlreadyImported))
+		));
+		node.submit(transfer(&node, 1, 20)).unwrap();
+		let (block, receipt) = node.produce_block().unwrap();
+		assert_eq!(block.extrinsics.len(), 2);
+		assert!(receipt.extrinsics.iter().all(|extrinsic| extrinsic.result.is_ok()));
+		node.produce_block().unwrap();
+		drop(node);
+
+		let node = Node::open(&dir).unwrap();
+		assert_eq!(node.runtime().system.block_number(), 2);
+		assert_eq!(node.runtime().balances.balance(&bob), 30);
+		assert_eq!(node.blocks().len(), 2);
+		assert_eq!(node.block(1), Some(&block));
+		assert_eq!(node.block(3), None);
+		assert_eq!(node.pool().status().ready, 0);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn blocks_are_imported_into_another_node() {
+		let (source_dir, dir) = (temp_dir("source"), temp_dir("import"));
+		let mut source = Node::init(&source_dir, &crate::dev_genesis()).unwrap();
+		for nonce in 0..3 {
+			source.submit(transfer(&source, nonce, 5)).unwrap();
+			source.produce_block().unwrap();
+		}
+		let blocks = source.blocks().to_vec();
+
+		// Blocks must follow their parent, and the ones already in the chain are skipped.
+		let mut node = Node::init(&dir, &crate::dev_genesis()).unwrap();
+		assert_eq!(node.import_blocks(blocks[..1].to_vec(), ForkChoice::LongestChain).unwrap(), 1);
+		let shuffled = vec![blocks[2].clone(), blocks[0].clone(), blocks[1].clone()];
+		assert!(matches!(
+			node.import_blocks(shuffled, ForkChoice::LongestChain),
+			Err(NodeError::Import(_))
+		));
+		assert_eq!(node.import_blocks(blocks.clone(), ForkChoice::LongestChain).unwrap(), 2);
+		drop(node);
+
+		let node = Node::open(&dir).unwrap();
+		assert_eq!(node.blocks(), source.blocks());
+		assert_eq!(node.runtime().state_root(), source.runtime().state_root());
+
+		// An invalid block is rejected, and changes nothing.
+		let mut node = Node::open(&dir).unwrap();
+		let mut invalid = BlockBuilder::new(node.runtime()).build();
+		invalid.header.state_root = support::Hash::default();
+		assert!(matches!(
+			node.import_blocks(vec![invalid], ForkChoice::LongestChain),
+			Err(NodeError::Import(ImportError::Invalid(BlockError::StateRootMismatch)))
+		));
+		assert_eq!(Node::open(&dir).unwrap().blocks().len(), 3);
+		std::fs::remove_dir_all(source_dir).unwrap();
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn nodes_reorganize_onto_a_better_fork() {
+		let (source_dir, dir) = (temp_dir("fork-source"), temp_dir("fork"));
+		let mut source = Node::init(&source_dir, &crate::dev_genesis()).unwrap();
+		source.produce_block().unwrap();
+		source.produce_block().unwrap();
+		let fork = source.blocks().to_vec();
+
+		// The node produced a block of its own, with a transfer, before it sees the longer fork
+		// branching off the genesis.
+		let mut node = Node::init(&dir, &crate::dev_genesis()).unwrap();
+		node.submit(transfer(&node, 0, 10)).unwrap();
+		let (local, _) = node.produce_block().unwrap();
+		assert_eq!(node.import_blocks(fork[..1].to_vec(), ForkChoice::LongestChain).unwrap(), 0);
+		let state = std::fs::read(dir.join("state.log")).unwrap();
+		assert_eq!(node.import_blocks(fork.clone(), ForkChoice::LongestChain).unwrap(), 2);
+
+		// The local block is retracted, and its transfer goes back to the pool.
+		assert_eq!(node.blocks(), fork.as_slice());
+		assert_eq!(node.runtime().state_root(), source.runtime().state_root());
+		assert_eq!(node.pool().status().ready, 1);
+		drop(node);
+		let node = Node::open(&dir).unwrap();
+		assert_eq!(node.blocks(), fork.as_slice());
+		assert_eq!(node.pool().extrinsics(), vec![&local.extrinsics[0]]);
+
+		// A node which stopped before committing the state after the new chain rebuilds it.
+		std::fs::write(dir.join("state.log"), state).unwrap();
+		let node = Node::open(&dir).unwrap();
+		assert_eq!(node.blocks(), fork.as_slice());
+		assert_eq!(node.runtime().state_root(), source.runtime().state_root());
+		std::fs::remove_dir_all(source_dir).unwrap();
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn blocks_ahead_of_the_state_are_dropped() {
+		let dir = temp_dir("ahead");
+		let mut node = Node::init(&dir, &crate::dev_genesis()).unwrap();
+		node.produce_block().unwrap();
+		let state = std::fs::read(dir.join("state.log")).unwrap();
+		node.produce_block().unwrap();
+		drop(node);
+
+		// The node stopped after storing the second block, but before committing the state.
+		std::fs::write(dir.join("state.log"), state).unwrap();
+		let mut node = Node::open(&dir).unwrap();
+		assert_eq!(node.blocks().len(), 1);
+		assert_eq!(node.runtime().system.block_number(), 1);
+		node.produce_block().unwrap();
+		assert_eq!(Node::open(&dir).unwrap().blocks().len(), 2);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -1,75 +1,236 @@
-use crate::support::DispatchResult;
+use crate::{
+	codec::{Decode, Encode},
+	storage::{self, StorageMap, StoragePairs},
+	support::{DispatchResult, EventQueue, Hooks, OnRuntimeUpgrade, Weight},
+	system::{ensure_signed, BadOrigin},
+};
 use core::fmt::Debug;
-use std::collections::BTreeMap;
 
 pub trait Config: crate::system::Config {
 	// The type which represents the content that can be claimed using this pallet.
 	// The content can be in the form of bytes, or the hash for more economical alternative.
 	// This flexibility could help the runtime developer.
-	type Content: Debug + Ord;
+	type Content: Debug + Ord + Clone + Encode + Decode;
+}
+
+// The events which can be emitted by the calls of this pallet.
+#[derive(Debug, Clone, PartialEq, macros::EnumMetadata)]
+pub enum Event<T: Config> {
+	/// An account claimed some content.
+	ClaimCreated { who: T::AccountId, claim: T::Content },
+	/// The owner of some content revoked their claim.
+	ClaimRevoked { who: T::AccountId, claim: T::Content },
+}
+
+// The errors which can be returned by the calls of this pallet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, macros::EnumMetadata)]
+pub enum Error {
+	/// The content has already been claimed by some account.
+	AlreadyClaimed,
+	/// There is no claim for this content.
+	ClaimNotExist,
+	/// The claim is owned by an account other than the caller.
+	NotClaimOwner,
+	/// The call was made from an origin it does not accept.
+	BadOrigin,
+}
+
+impl crate::support::PalletError for Error {
+	fn error_index(&self) -> u8 {
+		*self as u8
+	}
+}
+
+impl From<BadOrigin> for Error {
+	fn from(_: BadOrigin) -> Self {
+		Error::BadOrigin
+	}
+}
+
+impl core::fmt::Display for Error {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			Error::AlreadyClaimed => write!(f, "This content is already claimed."),
+			Error::ClaimNotExist => write!(f, "This claim does not exist."),
+			Error::NotClaimOwner => write!(f, "This content is owned by another account."),
+			Error::BadOrigin => write!(f, "Bad origin."),
+		}
+	}
+}
+
+// The initial state of the Proof of Existence Module.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(
+	bound(
+		serialize = "T::Content: serde::Serialize, T::AccountId: serde::Serialize",
+		deserialize = "T::Content: serde::Deserialize<'de>, T::AccountId: serde::Deserialize<'de>"
+	),
+	default,
+	deny_unknown_fields
+)]
+pub struct GenesisConfig<T: Config> {
+	// The claims which exist from the start, with their owner.
+	pub claims: Vec<(T::Content, T::AccountId)>,
+}
+
+impl<T: Config> Default for GenesisConfig<T> {
+	fn default() -> Self {
+		Self { claims: Vec::new() }
+	}
+}
+
+impl<T: Config> GenesisConfig<T> {
+	// Write this initial state into `pallet`.
+	pub fn build(&self, pallet: &mut Pallet<T>) {
+		for (claim, owner) in &self.claims {
+			pallet.claims.insert(claim.clone(), (owner.clone(), T::BlockNumber::default()));
+		}
+	}
 }
 
 // The Proof of Existence Module: a simple moudle that allows accounts
 // to claim existence over some data.
-#[derive(Debug)]
+//
+// Since version 2 of its storage, the block number of every claim is stored along with its owner.
+#[macros::storage(version = 2)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Pallet<T: Config> {
-	// A simple storage map from content to the owner of that content.
-	// Accounts can make multiple different claims, but each claim can only have one owner.
-	claims: BTreeMap<T::Content, T::AccountId>,
+	// A simple storage map from content to the owner of that content, and the block it was claimed
+	// in. Accounts can make multiple different claims, but each claim can only have one owner.
+	claims: StorageMap<T::Content, (T::AccountId, T::BlockNumber)>,
+	// The events emitted by this pallet, waiting to be collected by the runtime.
+	#[transactional]
+	events: EventQueue<Event<T>>,
+	// The number of the current block. It is not part of the state, since it is set at the start
+	// of every block.
+	block_number: T::BlockNumber,
 }
 
 impl<T: Config> Pallet<T> {
 	// Create a new instance of the Proof of Existence Module.
 	pub fn new() -> Self {
-		Self { claims: BTreeMap::new() }
+		Self {
+			claims: StorageMap::new(),
+			events: EventQueue::new(),
+			block_number: T::BlockNumber::default(),
+		}
+	}
+
+	// Get the owner (if any) of a claim, along with the block it was claimed in.
+	pub fn get_claim(&self, claim: &T::Content) -> Option<&(T::AccountId, T::BlockNumber)> {
+		self.claims.get(claim)
+	}
+
+	// Take all the events emitted by this pallet since they were last taken.
+	pub fn take_events(&mut self) -> Vec<Event<T>> {
+		self.events.take()
+	}
+
+	// Emit an event from this pallet.
+	fn deposit_event(&mut self, event: Event<T>) {
+		self.events.deposit(event);
 	}
+}
+
+// Claims never expire, so this pallet only keeps track of the current block.
+impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {
+	fn on_initialize(&mut self, n: T::BlockNumber) -> Weight {
+		self.block_number = n;
+		0
+	}
+}
 
-	// Get the owner (if any) of a claim.
-	pub fn get_claim(&self, claim: &T::Content) -> Option<&T::AccountId> {
-		self.claims.get(&claim)
+// Before version 2 of the storage, claims were stored with their owner only. The block they were
+// claimed in is unknown, so they are migrated as claimed in block 0, like the genesis claims.
+impl<T: Config> OnRuntimeUpgrade for Pallet<T> {
+	fn on_runtime_upgrade(
+		pallet: &str,
+		pairs: &mut StoragePairs,
+	) -> Result<(), crate::codec::Error> {
+		if storage::storage_version(pallet, pairs)? < 2 {
+			let prefix = storage::storage_prefix(pallet, "claims");
+			StorageMap::<T::Content, (T::AccountId, T::BlockNumber)>::translate(
+				&prefix,
+				pairs,
+				|owner: T::AccountId| (owner, T::BlockNumber::default()),
+			)?;
+			storage::set_storage_version(pallet, pairs, 2);
+		}
+		Ok(())
 	}
 }
 
 #[macros::call]
 impl<T: Config> Pallet<T> {
-	// Create a new claim on behalf of the `caller`.
-	pub fn create_claim(&mut self, caller: T::AccountId, claim: T::Content) -> DispatchResult {
+	/// Create a new claim on behalf of the signed account of `origin`.
+	/// Bigger claims take more space in storage, so they weigh more.
+	#[call_index(0)]
+	#[weight(10_000 + 100 * crate::codec::Encode::encode(claim).len() as u64)]
+	pub fn create_claim(
+		&mut self,
+		origin: T::RuntimeOrigin,
+		claim: T::Content,
+	) -> DispatchResult<Error> {
+		let caller = ensure_signed(origin)?;
 		// It will return an error if an account has already claimed that content.
 		if self.claims.contains_key(&claim) {
-			return Err(&"this content is already claimed");
+			return Err(Error::AlreadyClaimed);
 		}
 
-		self.claims.insert(claim, caller);
+		self.claims.insert(claim.clone(), (caller.clone(), self.block_number));
+		self.deposit_event(Event::ClaimCreated { who: caller, claim });
 		Ok(())
 	}
 
-	// Revoke an existing claim on some content.
-	// It should only succeed if the caller is the owner of an existing claim,
-	// otherwise it will return an error.
-	pub fn revoke_claim(&mut self, caller: T::AccountId, claim: T::Content) -> DispatchResult {
+	/// Revoke an existing claim on some content.
+	/// It should only succeed if the caller is the owner of an existing claim,
+	/// otherwise it will return an error.
+	#[call_index(1)]
+	#[weight(10_000)]
+	pub fn revoke_claim(
+		&mut self,
+		origin: T::RuntimeOrigin,
+		claim: T::Content,
+	) -> DispatchResult<Error> {
+		let caller = ensure_signed(origin)?;
 		// Get the owner of the `claim` to be revoked.
-		let _claim_owner = self.get_claim(&claim).ok_or("claim does not exist")?;
+		let (_claim_owner, _) = self.get_claim(&claim).ok_or(Error::ClaimNotExist)?;
 		// Check that the `owner` matches the `caller`.
 		if *_claim_owner != caller {
-			return Err(&"This content is owned by another account");
+			return Err(Error::NotClaimOwner);
 		}
 		self.claims.remove(&claim);
+		self.deposit_event(Event::ClaimRevoked { who: caller, claim });
 		Ok(())
 	}
 }
 
 #[cfg(test)]
 mod test {
+	use super::Event;
+	use crate::{
+		codec::Encode,
+		storage::{self, PalletStorage, StorageVersion},
+		support::{Hooks, OnRuntimeUpgrade},
+		system::RawOrigin,
+	};
+
+	#[derive(Debug, Clone, PartialEq)]
 	struct TestConfig;
 
 	impl super::Config for TestConfig {
-		type Content = &'static str;
+		type Content = String;
 	}
 
 	impl crate::system::Config for TestConfig {
 		type AccountId = String;
 		type BlockNumber = u32;
 		type Nonce = u32;
+		type RuntimeOrigin = crate::system::RawOrigin<String>;
+		type RuntimeEvent = ();
+		const MAX_BLOCK_WEIGHT: u64 = u64::MAX;
+		const VERSION: crate::support::RuntimeVersion =
+			crate::support::RuntimeVersion { spec_name: "test", spec_version: 1 };
 	}
 
 	#[test]
@@ -77,16 +238,74 @@ mod test {
 		let mut poe = super::Pallet::<TestConfig>::new();
 		let alice = &"alice";
 		let bob = &"bob";
-		let first_claim = &"Hello, world!";
+		let first_claim = || "Hello, world!".to_string();
 
-		assert_eq!(poe.get_claim(&"Hello, world!"), None);
-		assert_eq!(poe.create_claim(alice.to_string(), first_claim), Ok(()));
-		assert_eq!(poe.get_claim(first_claim), Some(alice.to_string()).as_ref());
+		assert_eq!(poe.get_claim(&first_claim()), None);
+		assert_eq!(poe.create_claim(RawOrigin::Signed(alice.to_string()), first_claim()), Ok(()));
+		assert_eq!(poe.get_claim(&first_claim()), Some(&(alice.to_string(), 0)));
+		assert_eq!(
+			poe.create_claim(RawOrigin::Signed(bob.to_string()), first_claim()),
+			Err(super::Error::AlreadyClaimed)
+		);
+		assert_eq!(
+			poe.revoke_claim(RawOrigin::Signed(bob.to_string()), first_claim()),
+			Err(super::Error::NotClaimOwner)
+		);
+		assert_eq!(poe.revoke_claim(RawOrigin::Signed(alice.to_string()), first_claim()), Ok(()));
 		assert_eq!(
-			poe.create_claim(bob.to_string(), first_claim),
-			Err("This content is already claimed.")
+			poe.revoke_claim(RawOrigin::Signed(alice.to_string()), first_claim()),
+			Err(super::Error::ClaimNotExist)
 		);
-		assert_eq!(poe.revoke_claim(alice.to_string(), first_claim), Ok(()));
-		assert_eq!(poe.create_claim(bob.to_string(), first_claim), Ok(()));
+		assert_eq!(poe.create_claim(RawOrigin::Signed(bob.to_string()), first_claim()), Ok(()));
+		// Claims can only be made by signed accounts.
+		assert_eq!(poe.create_claim(RawOrigin::Root, first_claim()), Err(super::Error::BadOrigin));
+
+		// Only the successful calls emitted events.
+		assert_eq!(
+			poe.take_events(),
+			vec![
+				Event::ClaimCreated { who: alice.to_string(), claim: first_claim() },
+				Event::ClaimRevoked { who: alice.to_string(), claim: first_claim() },
+				Event::ClaimCreated { who: bob.to_string(), claim: first_claim() },
+			]
+		);
+	}
+
+	#[test]
+	fn claims_record_their_block() {
+		let mut poe = super::Pallet::<TestConfig>::new();
+		poe.on_initialize(5);
+		let claim = "Hello, world!".to_string();
+		assert_eq!(poe.create_claim(RawOrigin::Signed("alice".to_string()), claim.clone()), Ok(()));
+		assert_eq!(poe.get_claim(&claim), Some(&("alice".to_string(), 5)));
+	}
+
+	#[test]
+	fn claims_are_migrated_from_v1_to_v2() {
+		let key = |claim: &str| storage::storage_map_key("poe", "claims", &claim.to_string());
+		// In version 1 of the storage, claims only hold their owner.
+		let mut pairs = vec![
+			(key("a"), "alice".to_string().encode()),
+			(key("b"), "bob".to_string().encode()),
+			(storage::storage_version_key("poe"), (1 as StorageVersion).encode()),
+		];
+		let mut poe = super::Pallet::<TestConfig>::new();
+		assert!(poe.load_storage_pairs("poe", &pairs).is_err());
+
+		super::Pallet::<TestConfig>::on_runtime_upgrade("poe", &mut pairs).unwrap();
+		assert_eq!(storage::storage_version("poe", &pairs), Ok(2));
+		poe.load_storage_pairs("poe", &pairs).unwrap();
+		assert_eq!(poe.get_claim(&"a".to_string()), Some(&("alice".to_string(), 0)));
+		assert_eq!(poe.get_claim(&"b".to_string()), Some(&("bob".to_string(), 0)));
+
+		// The migration only runs once.
+		let migrated = pairs.clone();
+		super::Pallet::<TestConfig>::on_runtime_upgrade("poe", &mut pairs).unwrap();
+		assert_eq!(pairs, migrated);
+		let mut stored = Vec::new();
+		poe.storage_pairs("poe", &mut stored);
+		stored.sort();
+		pairs.sort();
+		assert_eq!(stored, pairs);
 	}
 }
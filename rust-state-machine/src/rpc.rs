@@ -0,0 +1,732 @@
+use crate::{
+	codec::{self, Decode, Encode},
+	crypto::AccountId,
+	node::{Node, NodeError},
+	support::Hash,
+	types,
+	websocket::{self, Message, MessageReader},
+};
+use serde_json::{json, Value};
+use std::{
+	collections::BTreeMap,
+	io::{self, BufRead, BufReader, Write},
+	net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+	sync::{
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+		mpsc, Arc, Mutex, MutexGuard,
+	},
+	time::Duration,
+};
+
+// A JSON-RPC 2.0 server, giving clients like a frontend access to a node.
+//
+// Requests are sent in the body of HTTP POST requests, or as text messages over a WebSocket
+// connection, which is opened by upgrading an HTTP request on the same port. Params are given by
+// position. Hashes, storage keys and values, and extrinsics are 0x prefixed hex strings of their
+// encoding, and balances are decimal strings since they may not fit in a JSON number.
+//
+// Methods:
+// - `chain_getHeader([hash])` - the header of the block with `hash`, or of the last block.
+// - `chain_getBlock([hash])` - the header and the extrinsics of the block with `hash`, or of the
+//   last block.
+// - `state_getStorage(key)` - the value stored at `key` after the last block.
+// - `state_getReadProof(key)` - a `merkle::StorageProof` of the value stored at `key`, or of its
+//   absence, after the last block. Returns the hash of the block, `at`, and the proof, which a
+//   light client checks against the state root of the block.
+// - `balances_free(account)` - the balance of `account`.
+// - `poe_getClaim(content)` - the owner of the claim on `content`, and the block it was made in.
+// - `system_accountNonce(account)` - the nonce of the next extrinsic of `account`, after its
+//   extrinsics waiting in the pool.
+// - `author_submitExtrinsic(extrinsic)` - add an extrinsic to the pool, and return its hash.
+// - `chain_subscribeNewHeads()` - only over WebSocket, send a `chain_newHead` notification with
+//   the header of every new block. Returns the id of the subscription.
+// - `chain_unsubscribeNewHeads(id)` - cancel a subscription made on the same connection.
+
+// The error codes defined by JSON-RPC 2.0.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// A subscription was requested over HTTP, which cannot send notifications.
+const SUBSCRIPTIONS_UNSUPPORTED: i64 = -32000;
+// An extrinsic was rejected by the transaction pool, with the same code as Substrate.
+const INVALID_EXTRINSIC: i64 = 1010;
+
+// The longest body accepted in an HTTP request.
+const MAX_BODY_LEN: usize = websocket::MAX_MESSAGE_LEN;
+// The longest request line and headers accepted in an HTTP request.
+const MAX_HEAD_LEN: u64 = 8 * 1024;
+// The number of connections served at the same time. Further connections are answered with an
+// error and closed.
+const MAX_CONNECTIONS: usize = 100;
+// How long a client may take to send its request, or to accept a response.
+const TIMEOUT: Duration = Duration::from_secs(30);
+// How long a WebSocket connection may stay silent before it is pinged. It is closed if it is still
+// silent after another interval.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+// An error returned to the client instead of a result.
+#[derive(Debug, Clone, PartialEq)]
+struct RpcError {
+	code: i64,
+	message: String,
+}
+
+impl RpcError {
+	fn new(code: i64, message: impl Into<String>) -> Self {
+		Self { code, message: message.into() }
+	}
+}
+
+// The frames to send on a WebSocket connection, as their opcode and payload. Responses and
+// notifications are sent by the same thread, so they are never interleaved.
+type Outgoing = mpsc::Sender<(u8, Vec<u8>)>;
+
+// A WebSocket connection, which can receive notifications.
+struct Connection {
+	id: u64,
+	outgoing: Outgoing,
+}
+
+// A subscription to new heads.
+struct Subscriber {
+	id: u64,
+	connection: u64,
+	outgoing: Outgoing,
+}
+
+// The state shared by every connection to the server.
+struct Shared {
+	node: Mutex<Node>,
+	subscribers: Mutex<Vec<Subscriber>>,
+	// The next id of a connection or of a subscription.
+	next_id: AtomicU64,
+	// The number of connections being served.
+	connections: AtomicUsize,
+}
+
+impl Shared {
+	fn node(&self) -> MutexGuard<'_, Node> {
+		self.node.lock().expect("no thread panics while holding the node")
+	}
+
+	fn subscribers(&self) -> MutexGuard<'_, Vec<Subscriber>> {
+		self.subscribers.lock().expect("no thread panics while holding the subscribers")
+	}
+
+	fn next_id(&self) -> u64 {
+		self.next_id.fetch_add(1, Ordering::Relaxed)
+	}
+}
+
+// A running JSON-RPC server. It serves up to `MAX_CONNECTIONS` connections at the same time, each
+// in its own thread, until the process exits.
+pub struct RpcServer {
+	address: SocketAddr,
+	shared: Arc<Shared>,
+}
+
+impl RpcServer {
+	// Start serving requests on `address` for `node`. Binding to port 0 picks any free port.
+	pub fn start(node: Node, address: impl ToSocketAddrs) -> io::Result<Self> {
+		let listener = TcpListener::bind(address)?;
+		let address = listener.local_addr()?;
+		let shared = Arc::new(Shared {
+			node: Mutex::new(node),
+			subscribers: Mutex::new(Vec::new()),
+			next_id: AtomicU64::new(0),
+			connections: AtomicUsize::new(0),
+		});
+		let accepting = shared.clone();
+		std::thread::spawn(move || {
+			for mut stream in listener.incoming().flatten() {
+				if stream.set_read_timeout(Some(TIMEOUT)).is_err()
+					|| stream.set_write_timeout(Some(TIMEOUT)).is_err()
+				{
+					continue;
+				}
+				if accepting.connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+					accepting.connections.fetch_sub(1, Ordering::Relaxed);
+					let _ = write_response(&mut stream, "503 Service Unavailable", "");
+					continue;
+				}
+				let shared = accepting.clone();
+				std::thread::spawn(move || {
+					let _ = serve_connection(&shared, stream);
+					shared.connections.fetch_sub(1, Ordering::Relaxed);
+				});
+			}
+		});
+		Ok(Self { address, shared })
+	}
+
+	// The address the server listens on.
+	pub fn address(&self) -> SocketAddr {
+		self.address
+	}
+
+	// Produce a block with the node, and notify the subscribers of its header.
+	pub fn produce_block(&self) -> Result<(types::Block, types::BlockReceipt), NodeError> {
+		let (block, receipt) = self.shared.node().produce_block()?;
+		let header = header_json(&block.header);
+		// Subscribers whose connection was closed are dropped.
+		self.shared.subscribers().retain(|subscriber| {
+			let notification = json!({
+				"jsonrpc": "2.0",
+				"method": "chain_newHead",
+				"params": { "subscription": subscriber.id.to_string(), "result": header },
+			});
+			subscriber
+				.outgoing
+				.send((websocket::TEXT, notification.to_string().into_bytes()))
+				.is_ok()
+		});
+		Ok((block, receipt))
+	}
+}
+
+// An HTTP request, with the names of its headers in lowercase.
+struct HttpRequest {
+	method: String,
+	headers: BTreeMap<String, String>,
+	body: Vec<u8>,
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<HttpRequest> {
+	let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+	// The request line and the headers are read up to `MAX_HEAD_LEN` bytes.
+	let mut head = io::Read::take(&mut *reader, MAX_HEAD_LEN);
+	let mut line = String::new();
+	head.read_line(&mut line)?;
+	let method = line.split_whitespace().next().ok_or(invalid("empty request"))?.to_string();
+	let mut headers = BTreeMap::new();
+	loop {
+		line.clear();
+		if head.read_line(&mut line)? == 0 {
+			return Err(invalid("unterminated headers"));
+		}
+		let line = line.trim_end();
+		if line.is_empty() {
+			break;
+		}
+		let (name, value) = line.split_once(':').ok_or(invalid("invalid header"))?;
+		headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+	}
+	let len = match headers.get("content-length") {
+		Some(len) => len.parse().map_err(|_| invalid("invalid content length"))?,
+		None => 0,
+	};
+	if len > MAX_BODY_LEN {
+		return Err(invalid("body too long"));
+	}
+	let mut body = vec![0; len];
+	reader.read_exact(&mut body)?;
+	Ok(HttpRequest { method, headers, body })
+}
+
+fn write_response<W: Write>(writer: &mut W, status: &str, body: &str) -> io::Result<()> {
+	// Frontends served from another origin can call the server.
+	write!(
+		writer,
+		"HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+		 Access-Control-Allow-Origin: *\r\nAccess-Control-Allow-Headers: Content-Type\r\n\
+		 Connection: close\r\n\r\n{}",
+		status,
+		body.len(),
+		body
+	)?;
+	writer.flush()
+}
+
+// Serve a single HTTP request, which may upgrade the connection to a WebSocket.
+fn serve_connection(shared: &Shared, mut stream: TcpStream) -> io::Result<()> {
+	let mut reader = BufReader::new(stream.try_clone()?);
+	let request = read_request(&mut reader)?;
+	let upgrade = request.headers.get("upgrade");
+	if upgrade.is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket")) {
+		if let Some(key) = request.headers.get("sec-websocket-key") {
+			stream.write_all(websocket::handshake_response(key).as_bytes())?;
+			return serve_websocket(shared, reader, stream);
+		}
+	}
+	match request.method.as_str() {
+		"POST" => match handle(shared, &String::from_utf8_lossy(&request.body), None) {
+			Some(response) => write_response(&mut stream, "200 OK", &response),
+			// Notifications have no response.
+			None => write_response(&mut stream, "204 No Content", ""),
+		},
+		// The preflight request of a browser.
+		"OPTIONS" => write_response(&mut stream, "204 No Content", ""),
+		_ => write_response(&mut stream, "405 Method Not Allowed", ""),
+	}
+}
+
+// Serve the requests sent over a WebSocket connection, until it is closed.
+fn serve_websocket(
+	shared: &Shared,
+	reader: BufReader<TcpStream>,
+	mut stream: TcpStream,
+) -> io::Result<()> {
+	// Clients which only wait for notifications may stay silent, so they are pinged instead of
+	// being closed at the first timeout.
+	stream.set_read_timeout(Some(PING_INTERVAL))?;
+	let (outgoing, frames) = mpsc::channel::<(u8, Vec<u8>)>();
+	// The frames are written until every sender is dropped, or the connection fails.
+	let writer = std::thread::spawn(move || {
+		for (opcode, payload) in frames {
+			if websocket::write_frame(&mut stream, opcode, &payload, None).is_err()
+				|| opcode == websocket::CLOSE
+			{
+				break;
+			}
+		}
+	});
+
+	let connection = Connection { id: shared.next_id(), outgoing };
+	let mut reader = MessageReader::from_client(reader);
+	let mut pinged = false;
+	loop {
+		let message = match reader.read_message() {
+			Ok(message) => message,
+			// A silent client is pinged once, and the connection is closed if it stays silent.
+			Err(e) if websocket::is_timeout(&e) && !pinged => {
+				pinged = true;
+				let _ = connection.outgoing.send((websocket::PING, Vec::new()));
+				continue;
+			},
+			Err(_) => break,
+		};
+		pinged = false;
+		match message {
+			Message::Text(text) => {
+				if let Some(response) = handle(shared, &text, Some(&connection)) {
+					let _ = connection.outgoing.send((websocket::TEXT, response.into_bytes()));
+				}
+			},
+			Message::Ping(payload) => {
+				let _ = connection.outgoing.send((websocket::PONG, payload));
+			},
+			Message::Pong => {},
+			Message::Close => break,
+		}
+	}
+
+	// The subscriptions of the connection end with it.
+	shared.subscribers().retain(|subscriber| subscriber.connection != connection.id);
+	let _ = connection.outgoing.send((websocket::CLOSE, Vec::new()));
+	drop(connection);
+	let _ = writer.join();
+	Ok(())
+}
+
+// Handle a request, or a batch of requests, and return the response if there is one.
+fn handle(shared: &Shared, text: &str, connection: Option<&Connection>) -> Option<String> {
+	let response = match serde_json::from_str::<Value>(text) {
+		Ok(Value::Array(requests)) if !requests.is_empty() => {
+			let responses = requests
+				.into_iter()
+				.filter_map(|request| handle_request(shared, request, connection))
+				.collect::<Vec<_>>();
+			match responses.is_empty() {
+				true => return None,
+				false => Value::Array(responses),
+			}
+		},
+		Ok(Value::Array(_)) => {
+			error_response(Value::Null, RpcError::new(INVALID_REQUEST, "empty batch"))
+		},
+		Ok(request) => handle_request(shared, request, connection)?,
+		Err(e) => error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string())),
+	};
+	Some(response.to_string())
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+	json!({
+		"jsonrpc": "2.0",
+		"id": id,
+		"error": { "code": error.code, "message": error.message },
+	})
+}
+
+// Handle a single request. Requests without an id are notifications, which have no response.
+fn handle_request(
+	shared: &Shared,
+	request: Value,
+	connection: Option<&Connection>,
+) -> Option<Value> {
+	let id = request.get("id").cloned();
+	let method = request.get("method").and_then(Value::as_str);
+	let result = match (request.get("jsonrpc").and_then(Value::as_str), method) {
+		(Some("2.0"), Some(method)) => match request.get("params") {
+			None => call(shared, method, &[], connection),
+			Some(Value::Array(params)) => call(shared, method, params, connection),
+			Some(_) => Err(RpcError::new(INVALID_PARAMS, "params are given by position")),
+		},
+		_ => Err(RpcError::new(INVALID_REQUEST, "not a JSON-RPC 2.0 request")),
+	};
+	let id = match id {
+		Some(id) => id,
+		// A request which is not even valid is answered, with a null id.
+		None if method.is_none() => Value::Null,
+		None => return None,
+	};
+	Some(match result {
+		Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+		Err(error) => error_response(id, error),
+	})
+}
+
+// Call `method` with `params`, on behalf of `connection` if it is made over a WebSocket.
+fn call(
+	shared: &Shared,
+	method: &str,
+	params: &[Value],
+	connection: Option<&Connection>,
+) -> Result<Value, RpcError> {
+	match method {
+		"chain_getHeader" => {
+			let hash = optional_hash_param(params, 0)?;
+			Ok(block_json(&shared.node(), hash, |block| header_json(&block.header)))
+		},
+		"chain_getBlock" => {
+			let hash = optional_hash_param(params, 0)?;
+			Ok(block_json(&shared.node(), hash, |block| {
+				let extrinsics = block.extrinsics.iter().map(|e| codec::to_hex(&e.encode()));
+				json!({
+					"header": header_json(&block.header),
+					"extrinsics": extrinsics.collect::<Vec<_>>(),
+				})
+			}))
+		},
+		"state_getStorage" => {
+			let key = hex_param(params, 0)?;
+			let pairs = shared.node().runtime().storage_pairs();
+			Ok(match pairs.binary_search_by(|(k, _)| k.cmp(&key)) {
+				Ok(i) => json!(codec::to_hex(&pairs[i].1)),
+				Err(_) => Value::Null,
+			})
+		},
+		"state_getReadProof" => {
+			let key = hex_param(params, 0)?;
+			let node = shared.node();
+			let runtime = node.runtime();
+			Ok(json!({
+				"at": codec::to_hex(&runtime.system.parent_hash()),
+				"proof": codec::to_hex(&runtime.prove_storage(&key).encode()),
+			}))
+		},
+		"balances_free" => {
+			let who = account_param(params, 0)?;
+			Ok(json!(shared.node().runtime().balances.balance(&who).to_string()))
+		},
+		"poe_getClaim" => {
+			let content = string_param(params, 0)?.to_string();
+			Ok(match shared.node().runtime().proof_of_existence.get_claim(&content) {
+				Some((owner, block_number)) => {
+					json!({ "owner": owner, "blockNumber": block_number })
+				},
+				None => Value::Null,
+			})
+		},
+		"system_accountNonce" => {
+			let who = account_param(params, 0)?;
+			Ok(json!(shared.node().next_nonce(&who)))
+		},
+		"author_submitExtrinsic" => {
+			let bytes = hex_param(params, 0)?;
+			let extrinsic = types::Extrinsic::decode_all(&bytes)
+				.map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid extrinsic: {}", e)))?;
+			let hash = shared
+				.node()
+				.submit(extrinsic)
+				.map_err(|e| RpcError::new(INVALID_EXTRINSIC, e.to_string()))?;
+			Ok(json!(codec::to_hex(&hash)))
+		},
+		"chain_subscribeNewHeads" => {
+			let connection = connection.ok_or(RpcError::new(
+				SUBSCRIPTIONS_UNSUPPORTED,
+				"subscriptions need a WebSocket connection",
+			))?;
+			let id = shared.next_id();
+			shared.subscribers().push(Subscriber {
+				id,
+				connection: connection.id,
+				outgoing: connection.outgoing.clone(),
+			});
+			Ok(json!(id.to_string()))
+		},
+		"chain_unsubscribeNewHeads" => {
+			let id = string_param(params, 0)?;
+			let mut subscribers = shared.subscribers();
+			let count = subscribers.len();
+			subscribers.retain(|subscriber| {
+				subscriber.id.to_string() != id
+					|| Some(subscriber.connection) != connection.map(|c| c.id)
+			});
+			Ok(json!(subscribers.len() < count))
+		},
+		_ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+	}
+}
+
+// Render the block with `hash`, or the last block, or null if there is no such block.
+fn block_json(node: &Node, hash: Option<Hash>, render: impl Fn(&types::Block) -> Value) -> Value {
+	let block = match hash {
+		Some(hash) => node.blocks().iter().find(|block| block.header.hash() == hash),
+		None => node.blocks().last(),
+	};
+	block.map_or(Value::Null, render)
+}
+
+fn header_json(header: &types::Header) -> Value {
+	json!({
+		"hash": codec::to_hex(&header.hash()),
+		"parentHash": codec::to_hex(&header.parent_hash),
+		"number": header.block_number,
+		"stateRoot": codec::to_hex(&header.state_root),
+		"extrinsicsRoot": codec::to_hex(&header.extrinsics_root),
+	})
+}
+
+fn string_param(params: &[Value], index: usize) -> Result<&str, RpcError> {
+	params
+		.get(index)
+		.and_then(Value::as_str)
+		.ok_or(RpcError::new(INVALID_PARAMS, format!("param {} must be a string", index)))
+}
+
+fn hex_param(params: &[Value], index: usize) -> Result<Vec<u8>, RpcError> {
+	codec::from_hex(string_param(params, index)?)
+		.map_err(|e| RpcError::new(INVALID_PARAMS, format!("param {}: {}", index, e)))
+}
+
+fn account_param(params: &[Value], index: usize) -> Result<AccountId, RpcError> {
+	string_param(params, index)?
+		.parse()
+		.map_err(|e| RpcError::new(INVALID_PARAMS, format!("param {}: {}", index, e)))
+}
+
+// A hash which may be omitted, or given as null.
+fn optional_hash_param(params: &[Value], index: usize) -> Result<Option<Hash>, RpcError> {
+	match params.get(index) {
+		None | Some(Value::Null) => Ok(None),
+		Some(_) => hex_param(params, index)?.try_into().map(Some).map_err(|_| {
+			RpcError::new(INVALID_PARAMS, format!("param {} must be a hash of 32 bytes", index))
+		}),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::RpcServer;
+	use crate::{
+		balances,
+		codec::{self, Decode, Encode},
+		crypto::Pair,
+		merkle::{self, StorageProof},
+		node::Node,
+		storage, support, RuntimeCall,
+	};
+	use serde_json::{json, Value};
+	use std::{
+		io::{BufRead, BufReader, Read, Write},
+		net::TcpStream,
+		path::PathBuf,
+	};
+
+	// Start a server bound to localhost, for a new chain in a temporary directory.
+	fn start(name: &str) -> (RpcServer, PathBuf) {
+		let dir = std::env::temp_dir().join(format!(
+			"rust-state-machine-{}-rpc-{}",
+			std::process::id(),
+			name
+		));
+		let _ = std::fs::remove_dir_all(&dir);
+		let node = Node::init(&dir, &crate::dev_genesis()).unwrap();
+		(RpcServer::start(node, "127.0.0.1:0").unwrap(), dir)
+	}
+
+	// Send `body` in an HTTP POST request, and return the status line and the body of the response.
+	fn post(server: &RpcServer, body: &str) -> (String, String) {
+		let mut stream = TcpStream::connect(server.address()).unwrap();
+		write!(
+			stream,
+			"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+			 Content-Length: {}\r\n\r\n{}",
+			body.len(),
+			body
+		)
+		.unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).unwrap();
+		let (head, body) = response.split_once("\r\n\r\n").unwrap();
+		(head.lines().next().unwrap().to_string(), body.to_string())
+	}
+
+	// Call `method` over HTTP, and return the response.
+	fn request(server: &RpcServer, method: &str, params: Value) -> Value {
+		let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+		serde_json::from_str(&post(server, &request.to_string()).1).unwrap()
+	}
+
+	// A transfer from alice to bob, signed for the chain of the development genesis.
+	fn transfer(nonce: u32, amount: u128) -> String {
+		let to = Pair::from_seed("bob").account_id();
+		let call = RuntimeCall::balances(balances::Call::transfer { to, amount });
+		let context = crate::dev_genesis().build().system.signing_context();
+		codec::to_hex(
+			&support::Extrinsic::new_signed(&Pair::from_seed("alice"), nonce, call, &context)
+				.encode(),
+		)
+	}
+
+	#[test]
+	fn http_requests() {
+		let (server, dir) = start("http");
+		let alice = format!("{:?}", Pair::from_seed("alice").account_id());
+		let bob = format!("{:?}", Pair::from_seed("bob").account_id());
+
+		assert_eq!(request(&server, "balances_free", json!([alice]))["result"], "1000000");
+		assert_eq!(request(&server, "chain_getHeader", json!([]))["result"], Value::Null);
+		let submitted = request(&server, "author_submitExtrinsic", json!([transfer(0, 300)]));
+		assert_eq!(submitted["result"].as_str().unwrap().len(), 66);
+		// The nonce follows the extrinsics waiting in the pool.
+		assert_eq!(request(&server, "system_accountNonce", json!([alice]))["result"], 1);
+		let rejected = request(&server, "author_submitExtrinsic", json!([transfer(0, 300)]));
+		assert_eq!(rejected["error"]["code"], 1010);
+
+		let (block, _) = server.produce_block().unwrap();
+		assert_eq!(request(&server, "balances_free", json!([bob]))["result"], "300");
+		let header = request(&server, "chain_getHeader", json!([]))["result"].clone();
+		assert_eq!(header["number"], 1);
+		assert_eq!(header["hash"], codec::to_hex(&block.header.hash()));
+		let by_hash = request(&server, "chain_getBlock", json!([header["hash"]]))["result"].clone();
+		assert_eq!(by_hash["header"], header);
+		assert_eq!(by_hash["extrinsics"], json!([transfer(0, 300)]));
+		let unknown = request(&server, "chain_getBlock", json!([codec::to_hex(&[0; 32])]));
+		assert_eq!(unknown["result"], Value::Null);
+
+		let key =
+			storage::storage_map_key("balances", "balances", &Pair::from_seed("bob").account_id());
+		let stored = request(&server, "state_getStorage", json!([codec::to_hex(&key)]));
+		assert_eq!(stored["result"], codec::to_hex(&300u128.encode()));
+		let missing = request(&server, "state_getStorage", json!(["0x00"]));
+		assert_eq!(missing["result"], Value::Null);
+		let read_proof = request(&server, "state_getReadProof", json!([codec::to_hex(&key)]));
+		assert_eq!(read_proof["result"]["at"], header["hash"]);
+		let proof = codec::from_hex(read_proof["result"]["proof"].as_str().unwrap()).unwrap();
+		let proof = StorageProof::decode_all(&proof).unwrap();
+		let value = merkle::verify_storage_proof(&block.header, &key, &proof);
+		assert_eq!(value, Ok(Some(300u128.encode())));
+		assert_eq!(request(&server, "poe_getClaim", json!(["hello"]))["result"], Value::Null);
+
+		// Errors are reported with the codes of JSON-RPC 2.0.
+		assert_eq!(request(&server, "unknown", json!([]))["error"]["code"], -32601);
+		assert_eq!(request(&server, "balances_free", json!(["alice"]))["error"]["code"], -32602);
+		assert_eq!(request(&server, "chain_subscribeNewHeads", json!([]))["error"]["code"], -32000);
+		let (status, body) = post(&server, "{");
+		assert_eq!(status, "HTTP/1.1 200 OK");
+		assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["error"]["code"], -32700);
+
+		// Batches are answered in one response, without the notifications.
+		let batch = json!([
+			{ "jsonrpc": "2.0", "id": 1, "method": "system_accountNonce", "params": [alice] },
+			{ "jsonrpc": "2.0", "method": "system_accountNonce", "params": [alice] },
+			{ "jsonrpc": "2.0", "id": 2, "method": "poe_getClaim" },
+		]);
+		let responses: Value = serde_json::from_str(&post(&server, &batch.to_string()).1).unwrap();
+		assert_eq!(responses[0], json!({ "jsonrpc": "2.0", "id": 1, "result": 1 }));
+		assert_eq!(responses[1]["error"]["code"], -32602);
+		assert_eq!(responses.as_array().unwrap().len(), 2);
+		let notification = json!({ "jsonrpc": "2.0", "method": "system_accountNonce" });
+		assert_eq!(post(&server, &notification.to_string()).0, "HTTP/1.1 204 No Content");
+
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn websocket_subscriptions() {
+		use crate::websocket::{self, Message, MessageReader};
+
+		let (server, dir) = start("websocket");
+		let mut stream = TcpStream::connect(server.address()).unwrap();
+		write!(
+			stream,
+			"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+			 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+		)
+		.unwrap();
+		// The handshake is answered before any message.
+		let mut reader = BufReader::new(stream.try_clone().unwrap());
+		let mut head = String::new();
+		while !head.ends_with("\r\n\r\n") {
+			reader.read_line(&mut head).unwrap();
+		}
+		assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+		assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+		let mut reader = MessageReader::from_server(reader);
+		let mut send = |request: Value| {
+			let payload = request.to_string();
+			websocket::write_frame(&mut stream, websocket::TEXT, payload.as_bytes(), Some([7; 4]))
+				.unwrap();
+		};
+
+		send(json!({ "jsonrpc": "2.0", "id": 1, "method": "chain_subscribeNewHeads" }));
+		let mut receive = || match reader.read_message().unwrap() {
+			Message::Text(text) => serde_json::from_str::<Value>(&text).unwrap(),
+			message => panic!("unexpected message {:?}", message),
+		};
+		let subscription = receive()["result"].clone();
+
+		server.produce_block().unwrap();
+		server.produce_block().unwrap();
+		for number in [1, 2] {
+			let notification = receive();
+			assert_eq!(notification["method"], "chain_newHead");
+			assert_eq!(notification["params"]["subscription"], subscription);
+			assert_eq!(notification["params"]["result"]["number"], number);
+		}
+
+		// Requests are answered on the same connection, and the subscription can be cancelled.
+		let method = "chain_unsubscribeNewHeads";
+		send(json!({ "jsonrpc": "2.0", "id": 2, "method": method, "params": [subscription] }));
+		assert_eq!(receive(), json!({ "jsonrpc": "2.0", "id": 2, "result": true }));
+		server.produce_block().unwrap();
+		send(json!({ "jsonrpc": "2.0", "id": 3, "method": "chain_getHeader" }));
+		assert_eq!(receive()["result"]["number"], 3);
+
+		// The connection is closed when the client sends an invalid frame, here an unmasked one.
+		websocket::write_frame(&mut stream, websocket::TEXT, b"{}", None).unwrap();
+		assert_eq!(reader.read_message().unwrap(), Message::Close);
+
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn connections_are_limited() {
+		let (server, dir) = start("connections");
+		let alice = format!("{:?}", Pair::from_seed("alice").account_id());
+
+		// Connections which send nothing are served until they time out.
+		let idle = (0..super::MAX_CONNECTIONS)
+			.map(|_| TcpStream::connect(server.address()).unwrap())
+			.collect::<Vec<_>>();
+		let mut stream = TcpStream::connect(server.address()).unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+		// Once they are closed, the server accepts connections again.
+		drop(idle);
+		let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+		while post(&server, "{}").0 != "HTTP/1.1 200 OK" {
+			assert!(std::time::Instant::now() < deadline, "the server still refuses connections");
+			std::thread::sleep(std::time::Duration::from_millis(10));
+		}
+		assert_eq!(request(&server, "system_accountNonce", json!([alice]))["result"], 0);
+
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
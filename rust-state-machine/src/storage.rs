@@ -0,0 +1,821 @@
+use crate::codec::{Decode, Encode};
+use core::marker::PhantomData;
+use std::{borrow::Borrow, collections::BTreeMap};
+
+// A trait for anything holding storage which can be modified inside of a transaction.
+//
+// Transactions can be nested: every `start_transaction` must be matched by either a
+// `commit_transaction`, which keeps the changes made since the transaction started, or a
+// `rollback_transaction`, which reverts them.
+pub trait Transactional {
+	// Open a new transaction layer on top of the current one.
+	fn start_transaction(&mut self);
+	// Keep all the changes made in the current transaction layer, and close it.
+	fn commit_transaction(&mut self);
+	// Revert all the changes made in the current transaction layer, and close it.
+	fn rollback_transaction(&mut self);
+
+	// Execute `f` inside of a new transaction.
+	// The changes made by `f` are committed if it returns `Ok`, and reverted if it returns `Err`.
+	fn with_transaction<R, E>(&mut self, f: impl FnOnce(&mut Self) -> Result<R, E>) -> Result<R, E>
+	where
+		Self: Sized,
+	{
+		self.start_transaction();
+		let result = f(self);
+		match result {
+			Ok(_) => self.commit_transaction(),
+			Err(_) => self.rollback_transaction(),
+		}
+		result
+	}
+}
+
+// The encoded `(key, value)` pairs which make up the state of the runtime.
+pub type StoragePairs = Vec<(Vec<u8>, Vec<u8>)>;
+
+// The version of the layout of the storage of a pallet.
+//
+// A pallet increments it whenever it changes how its storage items are encoded, and provides a
+// migration from the previous layout. A pallet which never changed its layout is at version 0.
+pub type StorageVersion = u16;
+
+// A trait for pallets, whose storage is part of the state of the runtime.
+pub trait PalletStorage {
+	// The version of the layout of the storage of this pallet.
+	const STORAGE_VERSION: StorageVersion;
+
+	// Append the `(key, value)` pairs of every storage item of this pallet to `pairs`.
+	// The keys are prefixed with `pallet`, the name of this pallet in the runtime.
+	fn storage_pairs(&self, pallet: &str, pairs: &mut StoragePairs);
+
+	// Restore every storage item of this pallet from the `(key, value)` pairs of a state, ignoring
+	// the pairs which belong to other pallets. It is the reverse of `storage_pairs`.
+	//
+	// Fails if the storage of this pallet in the state is not at `STORAGE_VERSION`.
+	fn load_storage_pairs(
+		&mut self,
+		pallet: &str,
+		pairs: &[(Vec<u8>, Vec<u8>)],
+	) -> Result<(), crate::codec::Error>;
+}
+
+// The prefix of the keys of the storage item named `item` in the pallet named `pallet`.
+//
+// The names are hashed, so every prefix has the same length and no prefix can be the beginning of
+// another one.
+pub fn storage_prefix(pallet: &str, item: &str) -> Vec<u8> {
+	let mut prefix = crate::support::hash(pallet.as_bytes())[..16].to_vec();
+	prefix.extend_from_slice(&crate::support::hash(item.as_bytes())[..16]);
+	prefix
+}
+
+// The storage key of the value at `key`, in the storage map named `item` of the pallet named
+// `pallet`. Only the tests need to know where a value is stored.
+#[cfg(test)]
+pub fn storage_map_key<K: Encode>(pallet: &str, item: &str, key: &K) -> Vec<u8> {
+	let mut storage_key = storage_prefix(pallet, item);
+	key.encode_to(&mut storage_key);
+	storage_key
+}
+
+// The key of the storage version of the pallet named `pallet`.
+pub fn storage_version_key(pallet: &str) -> Vec<u8> {
+	storage_prefix(pallet, ":storage_version:")
+}
+
+// The storage version of the pallet named `pallet` in the state made of `pairs`. It is 0 if the
+// state does not record any version for the pallet.
+pub fn storage_version(
+	pallet: &str,
+	pairs: &[(Vec<u8>, Vec<u8>)],
+) -> Result<StorageVersion, crate::codec::Error> {
+	let key = storage_version_key(pallet);
+	match pairs.iter().find(|(k, _)| *k == key) {
+		Some((_, value)) => StorageVersion::decode_all(value),
+		None => Ok(0),
+	}
+}
+
+// Record `version` as the storage version of the pallet named `pallet` in `pairs`. Version 0 is
+// the default, so it is not recorded.
+pub fn set_storage_version(pallet: &str, pairs: &mut StoragePairs, version: StorageVersion) {
+	let key = storage_version_key(pallet);
+	pairs.retain(|(k, _)| *k != key);
+	if version != 0 {
+		pairs.push((key, version.encode()));
+	}
+}
+
+// A trait for types providing a value of type `V`.
+//
+// It gives the default value of a storage item: the value of a `StorageValue` which was never set,
+// or of a key which is not in a `StorageMap`.
+pub trait Get<V> {
+	fn get() -> V;
+}
+
+// The default value of storage items which do not declare one: the `Default` of their type.
+pub struct DefaultValue;
+
+impl<V: Default> Get<V> for DefaultValue {
+	fn get() -> V {
+		V::default()
+	}
+}
+
+// A single value kept in the storage of a pallet.
+//
+// It starts with the default value given by `D`.
+pub struct StorageValue<V, D = DefaultValue> {
+	value: V,
+	// For every open transaction, the value before it was first modified in that transaction.
+	journal: Vec<Option<V>>,
+	default: PhantomData<D>,
+}
+
+impl<V, D> StorageValue<V, D> {
+	// Create a new storage value, initialized to its default value.
+	pub fn new() -> Self
+	where
+		D: Get<V>,
+	{
+		Self { value: D::get(), journal: Vec::new(), default: PhantomData }
+	}
+
+	// Get the current value.
+	pub fn get(&self) -> &V {
+		&self.value
+	}
+
+	// Set a new value.
+	pub fn set(&mut self, value: V) {
+		let old = core::mem::replace(&mut self.value, value);
+		if let Some(layer) = self.journal.last_mut() {
+			layer.get_or_insert(old);
+		}
+	}
+
+	// Modify the current value in place with `f`, and return what `f` returns.
+	//
+	// The value is only copied the first time it is modified in a transaction, so the transaction
+	// can be reverted.
+	pub fn mutate<R>(&mut self, f: impl FnOnce(&mut V) -> R) -> R
+	where
+		V: Clone,
+	{
+		if let Some(layer @ None) = self.journal.last_mut() {
+			*layer = Some(self.value.clone());
+		}
+		f(&mut self.value)
+	}
+
+	// Reset the value to its default value, and return the value it had.
+	#[allow(dead_code)]
+	pub fn take(&mut self) -> V
+	where
+		V: Clone,
+		D: Get<V>,
+	{
+		let old = self.value.clone();
+		self.set(D::get());
+		old
+	}
+}
+
+impl<V, D: Get<V>> Default for StorageValue<V, D> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<V: Clone, D> Clone for StorageValue<V, D> {
+	fn clone(&self) -> Self {
+		Self { value: self.value.clone(), journal: self.journal.clone(), default: PhantomData }
+	}
+}
+
+impl<V: PartialEq, D> PartialEq for StorageValue<V, D> {
+	fn eq(&self, other: &Self) -> bool {
+		self.value == other.value && self.journal == other.journal
+	}
+}
+
+impl<V: Eq, D> Eq for StorageValue<V, D> {}
+
+impl<V, D> Transactional for StorageValue<V, D> {
+	fn start_transaction(&mut self) {
+		self.journal.push(None);
+	}
+
+	fn commit_transaction(&mut self) {
+		let layer = self.journal.pop().expect("no open transaction to commit");
+		// The parent transaction should still be able to revert to its own original value.
+		if let (Some(old), Some(parent)) = (layer, self.journal.last_mut()) {
+			parent.get_or_insert(old);
+		}
+	}
+
+	fn rollback_transaction(&mut self) {
+		let layer = self.journal.pop().expect("no open transaction to rollback");
+		if let Some(old) = layer {
+			self.value = old;
+		}
+	}
+}
+
+impl<V: core::fmt::Debug, D> core::fmt::Debug for StorageValue<V, D> {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		self.value.fmt(f)
+	}
+}
+
+impl<V: Encode, D> StorageValue<V, D> {
+	// Append the `(key, value)` pair of this value to `pairs`, where the key is `prefix`.
+	pub fn storage_pairs(&self, prefix: &[u8], pairs: &mut StoragePairs) {
+		pairs.push((prefix.to_vec(), self.value.encode()));
+	}
+
+	// Restore this value from the pair of `pairs` whose key is `prefix`, if there is one.
+	pub fn load_storage_pairs(
+		&mut self,
+		prefix: &[u8],
+		pairs: &[(Vec<u8>, Vec<u8>)],
+	) -> Result<(), crate::codec::Error>
+	where
+		V: Decode,
+	{
+		if let Some((_, value)) = pairs.iter().find(|(key, _)| key == prefix) {
+			self.value = V::decode_all(value)?;
+		}
+		Ok(())
+	}
+}
+
+// A map of values kept in the storage of a pallet.
+//
+// Keys which are not in the map have the default value given by `D`.
+pub struct StorageMap<K, V, D = DefaultValue> {
+	map: BTreeMap<K, V>,
+	// For every open transaction, the value of each key before it was first modified in that
+	// transaction. `None` means the key did not exist.
+	journal: Vec<BTreeMap<K, Option<V>>>,
+	default: PhantomData<D>,
+}
+
+impl<K: Ord + Clone, V, D> StorageMap<K, V, D> {
+	// Create a new empty storage map.
+	pub fn new() -> Self {
+		Self { map: BTreeMap::new(), journal: Vec::new(), default: PhantomData }
+	}
+
+	// Get the value stored at `key`, if any.
+	pub fn get<Q>(&self, key: &Q) -> Option<&V>
+	where
+		K: Borrow<Q>,
+		Q: Ord + ?Sized,
+	{
+		self.map.get(key)
+	}
+
+	// Get the value stored at `key`, or the default value if there is none.
+	pub fn get_or_default<Q>(&self, key: &Q) -> V
+	where
+		K: Borrow<Q>,
+		Q: Ord + ?Sized,
+		V: Clone,
+		D: Get<V>,
+	{
+		self.map.get(key).cloned().unwrap_or_else(D::get)
+	}
+
+	// Check if there is a value stored at `key`.
+	pub fn contains_key<Q>(&self, key: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Ord + ?Sized,
+	{
+		self.map.contains_key(key)
+	}
+
+	// Store `value` at `key`, replacing any existing value.
+	pub fn insert(&mut self, key: K, value: V) {
+		let old = self.map.insert(key.clone(), value);
+		self.record(key, old);
+	}
+
+	// Remove the value stored at `key`, if any.
+	pub fn remove(&mut self, key: &K) {
+		let old = self.map.remove(key);
+		self.record(key.clone(), old);
+	}
+
+	// Remove the value stored at `key`, and return it.
+	#[allow(dead_code)]
+	pub fn take(&mut self, key: &K) -> Option<V>
+	where
+		V: Clone,
+	{
+		let old = self.map.remove(key);
+		self.record(key.clone(), old.clone());
+		old
+	}
+
+	// Modify the value stored at `key` in place with `f`, starting from the default value if
+	// there is none, and return what `f` returns. The modified value is stored at `key`.
+	pub fn mutate<R>(&mut self, key: K, f: impl FnOnce(&mut V) -> R) -> R
+	where
+		V: Clone,
+		D: Get<V>,
+	{
+		let mut value = self.get_or_default(&key);
+		let result = f(&mut value);
+		self.insert(key, value);
+		result
+	}
+
+	// Record the value `key` had before being modified, if this is the first modification of the
+	// key in the current transaction.
+	fn record(&mut self, key: K, old: Option<V>) {
+		if let Some(layer) = self.journal.last_mut() {
+			layer.entry(key).or_insert(old);
+		}
+	}
+}
+
+impl<K: Ord + Clone, V, D> Default for StorageMap<K, V, D> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<K: Clone, V: Clone, D> Clone for StorageMap<K, V, D> {
+	fn clone(&self) -> Self {
+		Self { map: self.map.clone(), journal: self.journal.clone(), default: PhantomData }
+	}
+}
+
+impl<K: PartialEq, V: PartialEq, D> PartialEq for StorageMap<K, V, D> {
+	fn eq(&self, other: &Self) -> bool {
+		self.map == other.map && self.journal == other.journal
+	}
+}
+
+impl<K: Eq, V: Eq, D> Eq for StorageMap<K, V, D> {}
+
+impl<K: Ord, V, D> Transactional for StorageMap<K, V, D> {
+	fn start_transaction(&mut self) {
+		self.journal.push(BTreeMap::new());
+	}
+
+	fn commit_transaction(&mut self) {
+		let layer = self.journal.pop().expect("no open transaction to commit");
+		// The parent transaction should still be able to revert to its own original values.
+		if let Some(parent) = self.journal.last_mut() {
+			for (key, old) in layer {
+				parent.entry(key).or_insert(old);
+			}
+		}
+	}
+
+	fn rollback_transaction(&mut self) {
+		let layer = self.journal.pop().expect("no open transaction to rollback");
+		for (key, old) in layer {
+			match old {
+				Some(value) => self.map.insert(key, value),
+				None => self.map.remove(&key),
+			};
+		}
+	}
+}
+
+impl<K: core::fmt::Debug, V: core::fmt::Debug, D> core::fmt::Debug for StorageMap<K, V, D> {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		self.map.fmt(f)
+	}
+}
+
+impl<K: Encode, V: Encode, D> StorageMap<K, V, D> {
+	// Append the `(key, value)` pair of every item in this map to `pairs`, where each key is
+	// `prefix` followed by the encoded key of the item.
+	pub fn storage_pairs(&self, prefix: &[u8], pairs: &mut StoragePairs) {
+		for (key, value) in &self.map {
+			let mut storage_key = prefix.to_vec();
+			key.encode_to(&mut storage_key);
+			pairs.push((storage_key, value.encode()));
+		}
+	}
+
+	// Restore the items of this map from the pairs of `pairs` whose key starts with `prefix`.
+	pub fn load_storage_pairs(
+		&mut self,
+		prefix: &[u8],
+		pairs: &[(Vec<u8>, Vec<u8>)],
+	) -> Result<(), crate::codec::Error>
+	where
+		K: Decode + Ord,
+		V: Decode,
+	{
+		for (storage_key, value) in pairs {
+			if let Some(key) = storage_key.strip_prefix(prefix) {
+				self.map.insert(K::decode_all(key)?, V::decode_all(value)?);
+			}
+		}
+		Ok(())
+	}
+
+	// Re-encode the values of the items of this map in `pairs`, whose keys start with `prefix`,
+	// from their `Old` encoding into `V` with `f`. This migrates a map whose value type changed.
+	pub fn translate<Old: Decode>(
+		prefix: &[u8],
+		pairs: &mut StoragePairs,
+		mut f: impl FnMut(Old) -> V,
+	) -> Result<(), crate::codec::Error> {
+		for (storage_key, value) in pairs.iter_mut() {
+			if storage_key.starts_with(prefix) {
+				*value = f(Old::decode_all(value)?).encode();
+			}
+		}
+		Ok(())
+	}
+}
+
+// A map of values kept in the storage of a pallet, indexed by two keys.
+//
+// It is a `StorageMap` whose key is the pair of both keys. Since a pair is encoded as its first
+// key followed by its second key, the items sharing the same first key are next to each other in
+// the state.
+//
+// Pallets declare double maps with `#[macros::storage]`, but none of ours needs one yet.
+#[allow(dead_code)]
+pub struct StorageDoubleMap<K1, K2, V, D = DefaultValue> {
+	map: StorageMap<(K1, K2), V, D>,
+}
+
+#[allow(dead_code)]
+impl<K1: Ord + Clone, K2: Ord + Clone, V, D> StorageDoubleMap<K1, K2, V, D> {
+	// Create a new empty storage double map.
+	pub fn new() -> Self {
+		Self { map: StorageMap::new() }
+	}
+
+	// Get the value stored at `(key1, key2)`, if any.
+	pub fn get(&self, key1: &K1, key2: &K2) -> Option<&V> {
+		self.map.get(&(key1.clone(), key2.clone()))
+	}
+
+	// Get the value stored at `(key1, key2)`, or the default value if there is none.
+	pub fn get_or_default(&self, key1: &K1, key2: &K2) -> V
+	where
+		V: Clone,
+		D: Get<V>,
+	{
+		self.map.get_or_default(&(key1.clone(), key2.clone()))
+	}
+
+	// Check if there is a value stored at `(key1, key2)`.
+	pub fn contains_key(&self, key1: &K1, key2: &K2) -> bool {
+		self.map.contains_key(&(key1.clone(), key2.clone()))
+	}
+
+	// Store `value` at `(key1, key2)`, replacing any existing value.
+	pub fn insert(&mut self, key1: K1, key2: K2, value: V) {
+		self.map.insert((key1, key2), value);
+	}
+
+	// Remove the value stored at `(key1, key2)`, if any.
+	pub fn remove(&mut self, key1: &K1, key2: &K2) {
+		self.map.remove(&(key1.clone(), key2.clone()));
+	}
+
+	// Remove the value stored at `(key1, key2)`, and return it.
+	pub fn take(&mut self, key1: &K1, key2: &K2) -> Option<V>
+	where
+		V: Clone,
+	{
+		self.map.take(&(key1.clone(), key2.clone()))
+	}
+
+	// Modify the value stored at `(key1, key2)` in place with `f`, starting from the default value
+	// if there is none, and return what `f` returns.
+	pub fn mutate<R>(&mut self, key1: K1, key2: K2, f: impl FnOnce(&mut V) -> R) -> R
+	where
+		V: Clone,
+		D: Get<V>,
+	{
+		self.map.mutate((key1, key2), f)
+	}
+}
+
+impl<K1: Ord + Clone, K2: Ord + Clone, V, D> Default for StorageDoubleMap<K1, K2, V, D> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<K1: Clone, K2: Clone, V: Clone, D> Clone for StorageDoubleMap<K1, K2, V, D> {
+	fn clone(&self) -> Self {
+		Self { map: self.map.clone() }
+	}
+}
+
+impl<K1: PartialEq, K2: PartialEq, V: PartialEq, D> PartialEq for StorageDoubleMap<K1, K2, V, D> {
+	fn eq(&self, other: &Self) -> bool {
+		self.map == other.map
+	}
+}
+
+impl<K1: Eq, K2: Eq, V: Eq, D> Eq for StorageDoubleMap<K1, K2, V, D> {}
+
+impl<K1: Ord, K2: Ord, V, D> Transactional for StorageDoubleMap<K1, K2, V, D> {
+	fn start_transaction(&mut self) {
+		self.map.start_transaction();
+	}
+
+	fn commit_transaction(&mut self) {
+		self.map.commit_transaction();
+	}
+
+	fn rollback_transaction(&mut self) {
+		self.map.rollback_transaction();
+	}
+}
+
+impl<K1: core::fmt::Debug, K2: core::fmt::Debug, V: core::fmt::Debug, D> core::fmt::Debug
+	for StorageDoubleMap<K1, K2, V, D>
+{
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		self.map.fmt(f)
+	}
+}
+
+#[allow(dead_code)]
+impl<K1: Encode, K2: Encode, V: Encode, D> StorageDoubleMap<K1, K2, V, D> {
+	// Append the `(key, value)` pair of every item in this map to `pairs`, where each key is
+	// `prefix` followed by the encoded keys of the item.
+	pub fn storage_pairs(&self, prefix: &[u8], pairs: &mut StoragePairs) {
+		self.map.storage_pairs(prefix, pairs);
+	}
+
+	// Restore the items of this map from the pairs of `pairs` whose key starts with `prefix`.
+	pub fn load_storage_pairs(
+		&mut self,
+		prefix: &[u8],
+		pairs: &[(Vec<u8>, Vec<u8>)],
+	) -> Result<(), crate::codec::Error>
+	where
+		K1: Decode + Ord,
+		K2: Decode + Ord,
+		V: Decode,
+	{
+		self.map.load_storage_pairs(prefix, pairs)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{
+		storage_map_key, storage_prefix, PalletStorage, StorageDoubleMap, StorageMap, StorageValue,
+		Transactional,
+	};
+	use crate::{
+		codec::{Decode, Encode},
+		support::EventQueue,
+	};
+
+	#[test]
+	fn storage_value_transactions() {
+		let mut value = StorageValue::<u32>::new();
+		value.set(1);
+
+		value.start_transaction();
+		value.set(2);
+		value.set(3);
+		value.rollback_transaction();
+		assert_eq!(*value.get(), 1);
+
+		value.start_transaction();
+		value.set(2);
+		value.commit_transaction();
+		assert_eq!(*value.get(), 2);
+	}
+
+	#[test]
+	fn storage_map_transactions() {
+		let mut map = StorageMap::<&str, u32>::new();
+		map.insert("alice", 100);
+
+		// Rolling back restores modified keys and removes inserted keys.
+		map.start_transaction();
+		map.insert("alice", 50);
+		map.insert("bob", 50);
+		map.rollback_transaction();
+		assert_eq!(map.get("alice"), Some(&100));
+		assert_eq!(map.get("bob"), None);
+
+		// Removed keys are restored too.
+		map.start_transaction();
+		map.remove(&"alice");
+		assert_eq!(map.get("alice"), None);
+		map.rollback_transaction();
+		assert_eq!(map.get("alice"), Some(&100));
+
+		map.start_transaction();
+		map.insert("bob", 10);
+		map.commit_transaction();
+		assert_eq!(map.get("bob"), Some(&10));
+	}
+
+	#[test]
+	fn storage_pairs_are_prefixed() {
+		let mut map = StorageMap::<String, u32>::new();
+		map.insert("alice".to_string(), 1);
+		let mut value = StorageValue::<u32>::new();
+		value.set(2);
+
+		let mut pairs = Vec::new();
+		map.storage_pairs(&storage_prefix("pallet", "map"), &mut pairs);
+		value.storage_pairs(&storage_prefix("pallet", "value"), &mut pairs);
+
+		assert_eq!(
+			pairs,
+			vec![
+				(storage_map_key("pallet", "map", &"alice".to_string()), 1u32.encode()),
+				(storage_prefix("pallet", "value"), 2u32.encode()),
+			]
+		);
+		assert_eq!(storage_prefix("pallet", "map").len(), 32);
+		assert_ne!(storage_prefix("pallet", "map"), storage_prefix("other", "map"));
+		assert_ne!(storage_prefix("pallet", "map"), storage_prefix("pallet", "value"));
+	}
+
+	#[test]
+	fn default_values() {
+		let mut value = StorageValue::<u32>::new();
+		assert_eq!(*value.get(), 0);
+		// Mutating returns the result of the closure.
+		let doubled = value.mutate(|v| {
+			*v += 5;
+			*v * 2
+		});
+		assert_eq!(doubled, 10);
+		assert_eq!(value.take(), 5);
+		assert_eq!(*value.get(), 0);
+
+		let mut map = StorageMap::<&str, u32>::new();
+		assert_eq!(map.get_or_default("alice"), 0);
+		map.mutate("alice", |v| *v += 5);
+		assert_eq!(map.get("alice"), Some(&5));
+		assert_eq!(map.take(&"alice"), Some(5));
+		assert_eq!(map.take(&"alice"), None);
+
+		// Taking a value is reverted along with the transaction.
+		map.insert("bob", 1);
+		map.start_transaction();
+		assert_eq!(map.take(&"bob"), Some(1));
+		map.rollback_transaction();
+		assert_eq!(map.get("bob"), Some(&1));
+	}
+
+	#[test]
+	fn storage_double_map() {
+		let mut map = StorageDoubleMap::<&str, u8, u32>::new();
+		map.insert("alice", 1, 10);
+		map.insert("alice", 2, 20);
+		map.mutate("bob", 1, |v| *v += 30);
+
+		assert_eq!(map.get(&"alice", &1), Some(&10));
+		assert_eq!(map.get_or_default(&"alice", &3), 0);
+		assert!(map.contains_key(&"bob", &1));
+		assert!(!map.contains_key(&"bob", &2));
+
+		map.start_transaction();
+		map.remove(&"alice", &1);
+		assert_eq!(map.take(&"alice", &2), Some(20));
+		map.rollback_transaction();
+		assert_eq!(map.get(&"alice", &1), Some(&10));
+		assert_eq!(map.get(&"alice", &2), Some(&20));
+
+		// Each key is the prefix, followed by both keys of the item.
+		let mut pairs = Vec::new();
+		map.storage_pairs(b"prefix", &mut pairs);
+		let key = |k1: &str, k2: u8| [b"prefix".to_vec(), k1.encode(), k2.encode()].concat();
+		assert_eq!(
+			pairs,
+			vec![
+				(key("alice", 1), 10u32.encode()),
+				(key("alice", 2), 20u32.encode()),
+				(key("bob", 1), 30u32.encode()),
+			]
+		);
+	}
+
+	trait Config {
+		type Balance: Copy + Encode + Decode;
+		const EXISTENTIAL_DEPOSIT: Self::Balance;
+	}
+
+	struct TestConfig;
+	impl Config for TestConfig {
+		type Balance = u64;
+		const EXISTENTIAL_DEPOSIT: u64 = 7;
+	}
+
+	// A pallet declaring every kind of storage item.
+	#[macros::storage]
+	struct Pallet<T: Config> {
+		#[getter(minimum)]
+		#[default(T::EXISTENTIAL_DEPOSIT)]
+		minimum: StorageValue<T::Balance>,
+		#[getter(balance)]
+		balances: StorageMap<String, u64>,
+		#[getter(allowance)]
+		allowances: StorageDoubleMap<String, String, u64>,
+		#[transactional]
+		events: EventQueue<&'static str>,
+		// Not a storage item, so it is not part of the state.
+		calls: u32,
+	}
+
+	#[test]
+	fn storage_macro() {
+		let mut pallet = Pallet::<TestConfig> {
+			minimum: StorageValue::new(),
+			balances: StorageMap::new(),
+			allowances: StorageDoubleMap::new(),
+			events: EventQueue::new(),
+			calls: 0,
+		};
+		let alice = || "alice".to_string();
+		let bob = || "bob".to_string();
+
+		// Getters return the declared default value, or the default of the type.
+		assert_eq!(pallet.minimum(), 7);
+		assert_eq!(pallet.balance(&alice()), 0);
+		assert_eq!(pallet.allowance(&alice(), &bob()), 0);
+
+		// Transactions span every storage item, and the transactional fields.
+		pallet.start_transaction();
+		pallet.minimum.set(10);
+		pallet.balances.insert(alice(), 100);
+		pallet.allowances.insert(alice(), bob(), 50);
+		pallet.events.deposit("changed");
+		pallet.calls += 1;
+		pallet.rollback_transaction();
+		assert_eq!(pallet.minimum(), 7);
+		assert_eq!(pallet.balance(&alice()), 0);
+		assert_eq!(pallet.allowance(&alice(), &bob()), 0);
+		assert!(pallet.events.take().is_empty());
+		assert_eq!(pallet.calls, 1);
+
+		// The keys of every storage item are prefixed by the pallet and the name of the item.
+		pallet.balances.insert(alice(), 100);
+		pallet.allowances.insert(alice(), bob(), 50);
+		let mut pairs = Vec::new();
+		pallet.storage_pairs("test", &mut pairs);
+		let double_map_key = [storage_prefix("test", "allowances"), alice().encode()].concat();
+		assert_eq!(
+			pairs,
+			vec![
+				(storage_prefix("test", "minimum"), 7u64.encode()),
+				(storage_map_key("test", "balances", &alice()), 100u64.encode()),
+				([double_map_key, bob().encode()].concat(), 50u64.encode()),
+			]
+		);
+	}
+
+	#[test]
+	fn nested_transactions() {
+		let mut map = StorageMap::<&str, u32>::new();
+
+		// The inner transaction is reverted, but the outer one is kept.
+		let result: Result<(), ()> = map.with_transaction(|map| {
+			map.insert("alice", 1);
+			let inner: Result<(), ()> = map.with_transaction(|map| {
+				map.insert("alice", 2);
+				map.insert("bob", 2);
+				Err(())
+			});
+			assert_eq!(inner, Err(()));
+			assert_eq!(map.get("alice"), Some(&1));
+			assert_eq!(map.get("bob"), None);
+			Ok(())
+		});
+		assert_eq!(result, Ok(()));
+		assert_eq!(map.get("alice"), Some(&1));
+
+		// Changes committed by an inner transaction are still reverted with the outer one.
+		let result: Result<(), ()> = map.with_transaction(|map| {
+			map.with_transaction(|map| {
+				map.insert("alice", 3);
+				map.insert("charlie", 3);
+				Ok::<_, ()>(())
+			})?;
+			assert_eq!(map.get("alice"), Some(&3));
+			Err(())
+		});
+		assert_eq!(result, Err(()));
+		assert_eq!(map.get("alice"), Some(&1));
+		assert_eq!(map.get("charlie"), None);
+	}
+}
@@ -1,4 +1,19 @@
+use crate::{
+	codec::Encode,
+	crypto::{Pair, PublicKey, Signature},
+};
+
+// The type of hashes used for blocks, extrinsics and the state of the runtime.
+pub type Hash = [u8; 32];
+
+// Hash some bytes into a `Hash`, using SHA-256.
+pub fn hash(data: &[u8]) -> Hash {
+	use sha2::Digest;
+	sha2::Sha256::digest(data).into()
+}
+
 // The most primitive representation of a Blockchain block.
+#[derive(Debug, Clone, PartialEq, macros::Encode, macros::Decode)]
 pub struct Block<Header, Extrinsic> {
 	// Contains metadata about the block.
 	pub header: Header,
@@ -6,37 +21,350 @@ pub struct Block<Header, Extrinsic> {
 	pub extrinsics: Vec<Extrinsic>,
 }
 
-// Below is an extremely simplified header containing only the current block number.
-// On a real blockchain, you would expect to also find:
-// - parent's block hash
-// - state root
-// - extrinsic root
-// - etc.
+// A simplified header, which commits to the chain it builds on, the extrinsics it contains and the
+// state of the runtime after executing them.
+// On a real blockchain, you would expect to also find things like a digest of consensus data.
+#[derive(Debug, Clone, PartialEq, macros::Encode, macros::Decode)]
 pub struct Header<BlockNumber> {
+	// The hash of the header of the previous block.
+	pub parent_hash: Hash,
 	pub block_number: BlockNumber,
+	// The root of the state of the runtime, after executing the extrinsics of this block.
+	pub state_root: Hash,
+	// The root of the extrinsics included in this block.
+	pub extrinsics_root: Hash,
+}
+
+impl<BlockNumber: Encode> Header<BlockNumber> {
+	// The hash of this header, which identifies the block.
+	pub fn hash(&self) -> Hash {
+		hash(&self.encode())
+	}
 }
 
 // It's literally an external message from outside of the blockchain.
-// It's a simplified version and tells s who is making the call,
-// and which call they are making.
-pub struct Extrinsic<Caller, Call> {
-	pub caller: Caller,
+// It tells us who is making the call, and which call they are making. The `signer` proves that
+// they are making this call by signing it along with their `nonce`, and the `SigningContext` of
+// the chain it is meant for.
+#[derive(Debug, Clone, PartialEq, macros::Encode, macros::Decode)]
+pub struct Extrinsic<Nonce, Call> {
+	pub signer: PublicKey,
+	pub signature: Signature,
+	pub nonce: Nonce,
 	pub call: Call,
 }
 
+impl<Nonce: Encode, Call: Encode> Extrinsic<Nonce, Call> {
+	// Create an extrinsic making `call`, signed by `pair` for the chain described by `context`.
+	pub fn new_signed(pair: &Pair, nonce: Nonce, call: Call, context: &SigningContext) -> Self {
+		let signature = pair.sign(&Self::signing_payload(&nonce, &call, context));
+		Self { signer: pair.public(), signature, nonce, call }
+	}
+
+	// Check that the signature of this extrinsic was made by its signer, over its nonce and call,
+	// for the chain described by `context`.
+	pub fn verify_signature(&self, context: &SigningContext) -> bool {
+		self.signer
+			.verify(&Self::signing_payload(&self.nonce, &self.call, context), &self.signature)
+	}
+
+	// The message signed by the signer of an extrinsic.
+	fn signing_payload(nonce: &Nonce, call: &Call, context: &SigningContext) -> Vec<u8> {
+		((nonce, call), context).encode()
+	}
+}
+
+// The chain an extrinsic is signed for. It is part of the signed message, so an extrinsic cannot
+// be replayed on a chain started from another genesis, or running another runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, macros::Encode)]
+pub struct SigningContext {
+	// The hash of the initial state of the chain.
+	pub genesis_hash: Hash,
+	pub version: RuntimeVersion,
+}
+
+// Compute the root of a list of extrinsics, which is stored in the header of the block including
+// them.
+pub fn extrinsics_root<Extrinsic: Encode>(extrinsics: &[Extrinsic]) -> Hash {
+	hash(&extrinsics.encode())
+}
+
+// The reasons why a block can be rejected by the runtime.
+//
+// When a block is rejected, none of its extrinsics are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+	// The block number is not the one following the last executed block.
+	WrongBlockNumber,
+	// The parent hash is not the hash of the last executed block.
+	UnknownParent,
+	// The extrinsics root does not match the extrinsics of the block.
+	ExtrinsicsRootMismatch,
+	// The state root does not match the state after executing the block.
+	StateRootMismatch,
+	// The signature of an extrinsic was not made by its signer.
+	BadSignature,
+	// The nonce of an extrinsic was already used by its signer.
+	StaleNonce,
+	// The nonce of an extrinsic is ahead of the next nonce of its signer.
+	FutureNonce,
+	// The extrinsics of the block weigh more than the maximum block weight.
+	ExhaustsResources,
+	// The signer of an extrinsic cannot pay its fee.
+	CannotPayFees,
+}
+
+impl core::fmt::Display for BlockError {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			BlockError::WrongBlockNumber => {
+				write!(f, "block number does not match what is expected")
+			},
+			BlockError::UnknownParent => {
+				write!(f, "parent hash does not match the last executed block")
+			},
+			BlockError::ExtrinsicsRootMismatch => {
+				write!(f, "extrinsics root does not match the extrinsics of the block")
+			},
+			BlockError::StateRootMismatch => {
+				write!(f, "state root does not match the state after execution")
+			},
+			BlockError::BadSignature => write!(f, "extrinsic has an invalid signature"),
+			BlockError::StaleNonce => write!(f, "extrinsic nonce has already been used"),
+			BlockError::FutureNonce => write!(f, "extrinsic nonce is ahead of the account nonce"),
+			BlockError::ExhaustsResources => write!(f, "block exceeds the maximum block weight"),
+			BlockError::CannotPayFees => write!(f, "extrinsic signer cannot pay the fee"),
+		}
+	}
+}
+
+// The phase of the execution of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+	// The `on_initialize` hooks of the pallets are running, before any extrinsic is applied.
+	Initialization,
+	// The extrinsic at this index in the block is being applied.
+	ApplyExtrinsic(u32),
+	// The `on_finalize` hooks of the pallets are running, after every extrinsic was applied.
+	Finalization,
+}
+
+// An event emitted while executing a block, along with the phase of the block which emitted it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventRecord<Event> {
+	pub phase: Phase,
+	pub event: Event,
+}
+
+// The events deposited by a pallet, waiting to be collected by the runtime.
+//
+// Events deposited inside of a transaction are discarded if the transaction is reverted, since
+// whatever they describe did not happen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventQueue<Event> {
+	events: Vec<Event>,
+	// For every open transaction, the number of events deposited before it started.
+	journal: Vec<usize>,
+}
+
+impl<Event> EventQueue<Event> {
+	// Create a new empty queue of events.
+	pub fn new() -> Self {
+		Self { events: Vec::new(), journal: Vec::new() }
+	}
+
+	// Add an event to the queue.
+	pub fn deposit(&mut self, event: Event) {
+		self.events.push(event);
+	}
+
+	// Remove all the events from the queue, and return them in the order they were deposited.
+	pub fn take(&mut self) -> Vec<Event> {
+		core::mem::take(&mut self.events)
+	}
+}
+
+impl<Event> Default for EventQueue<Event> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<Event> crate::storage::Transactional for EventQueue<Event> {
+	fn start_transaction(&mut self) {
+		self.journal.push(self.events.len());
+	}
+
+	fn commit_transaction(&mut self) {
+		self.journal.pop().expect("no open transaction to commit");
+	}
+
+	fn rollback_transaction(&mut self) {
+		let len = self.journal.pop().expect("no open transaction to rollback");
+		self.events.truncate(len);
+	}
+}
+
+// The cost of executing a call, in units of computation time.
+//
+// Every call declares its weight with the `#[weight(expr)]` attribute, and a block cannot contain
+// calls weighing more than the maximum block weight of the runtime.
+pub type Weight = u64;
+
+// Logic which a pallet runs on every block, outside of any extrinsic.
+//
+// The runtime calls the hooks of every pallet, including system, in the order the pallets are
+// declared. Each hook returns the weight it consumed, which is counted against the block even if it
+// exceeds the maximum block weight, since hooks cannot be skipped.
+pub trait Hooks<BlockNumber> {
+	// Called at the start of block `n`, before any extrinsic is applied.
+	fn on_initialize(&mut self, _n: BlockNumber) -> Weight {
+		0
+	}
+
+	// Called at the end of block `n`, after every extrinsic was applied.
+	fn on_finalize(&mut self, _n: BlockNumber) -> Weight {
+		0
+	}
+}
+
+// The version of the runtime.
+//
+// A state is only ever used by runtimes with the same `spec_name`. The `spec_version` is
+// incremented whenever the logic of the runtime changes, for example along with the storage
+// version of a pallet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, macros::Encode)]
+pub struct RuntimeVersion {
+	pub spec_name: &'static str,
+	pub spec_version: u32,
+}
+
+// Migrations of the storage of a pallet, from the layout written by an older version of the
+// runtime to its current layout.
+//
+// When the runtime restores a state written by an older runtime, the migrations of every pallet are
+// run on the `(key, value)` pairs of the state, in the order the pallets are declared, before the
+// pallets are loaded from them. A migration should check the storage version of its pallet in the
+// state, and record the version it migrated to, so it never runs twice.
+pub trait OnRuntimeUpgrade {
+	// Migrate the storage of the pallet named `pallet` in `pairs`.
+	fn on_runtime_upgrade(
+		_pallet: &str,
+		_pairs: &mut crate::storage::StoragePairs,
+	) -> Result<(), crate::codec::Error> {
+		Ok(())
+	}
+}
+
 // The Result type for our runtime. When the dispatch is completed successfully,
-// we return `Ok(())`, otherwise return a static error message.
-pub type DispatchResult = Result<(), &'static str>;
+// we return `Ok(())`, otherwise we return the error `E`.
+//
+// At the pallet level `E` is the pallet's own `Error` enum, while at the runtime level it is a
+// `DispatchError` wrapping the error of whichever pallet failed.
+pub type DispatchResult<E> = Result<(), E>;
+
+// Information about a call, known only once it has been dispatched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PostDispatchInfo {
+	// The weight actually consumed by the call, when it is lower than its declared weight. `None`
+	// means that all of the declared weight was consumed.
+	pub actual_weight: Option<Weight>,
+}
+
+// Calls returning `DispatchResult` consume all of their declared weight.
+impl From<()> for PostDispatchInfo {
+	fn from(_: ()) -> Self {
+		Self::default()
+	}
+}
+
+// The Result type for calls which can report the weight they actually consumed. The unused weight
+// is refunded to the caller once the call has been dispatched.
+pub type DispatchResultWithPostInfo<E> = Result<PostDispatchInfo, E>;
+
+// The outcome of an extrinsic included in an executed block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtrinsicReceipt<Event, Error> {
+	// `Ok` if the call of the extrinsic succeeded, or the error it failed with. A failed call is
+	// still included in the block, and its signer still pays the fee.
+	pub result: DispatchResult<DispatchError<Error>>,
+	// The events emitted by the extrinsic.
+	pub events: Vec<Event>,
+	// The weight consumed by the call, once its unused weight is refunded.
+	pub weight: Weight,
+}
+
+// The outcome of an executed block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockReceipt<Event, Error> {
+	// The hash of the header of the block.
+	pub block_hash: Hash,
+	// The receipt of every extrinsic, in the order of the block.
+	pub extrinsics: Vec<ExtrinsicReceipt<Event, Error>>,
+}
+
+// A trait implemented by the `Error` enum of every pallet.
+// It allows the runtime to identify which error was returned, without knowing the pallet.
+pub trait PalletError: core::fmt::Debug + core::fmt::Display {
+	// The index of this error within the pallet's `Error` enum.
+	fn error_index(&self) -> u8;
+}
+
+// The error returned by the runtime when dispatching a call fails.
+//
+// It contains the index of the pallet which returned the error within the runtime, the index of the
+// error within that pallet's `Error` enum, and the typed error itself. Usually `E` is the
+// `RuntimeError` enum generated by `#[macros::runtime]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DispatchError<E> {
+	pub pallet_index: u8,
+	pub error_index: u8,
+	pub error: E,
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for DispatchError<E> {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "{} (pallet {}, error {})", self.error, self.pallet_index, self.error_index)
+	}
+}
 
 // A trait which allows us to dispatch an incoming extrinsic
 // to the appropriate state transition function (STF) call.
 pub trait Dispatch {
-	// The type used to identify the caller of the function.
-	type Caller;
-	// The STF call the caller is trying to access.
+	// The type used to identify the origin of the call, such as a signed account or the root.
+	type Origin;
+	// The STF call the origin is trying to access.
 	type Call;
+	// The error returned when the call fails.
+	type Error;
 
-	// A function which takes a `caller` and the `call` they want to make,
+	// A function which takes an `origin` and the `call` it wants to make,
 	// and returns a `Result` based on the outcome of that function call.
-	fn dispatch(&mut self, caller: Self::Caller, call: Self::Call) -> DispatchResult;
+	fn dispatch(
+		&mut self,
+		origin: Self::Origin,
+		call: Self::Call,
+	) -> DispatchResultWithPostInfo<Self::Error>;
+}
+
+// A trait implemented by the runtime to charge a fee for every extrinsic.
+//
+// The fee is withdrawn from the signer before its call is dispatched, based on the length of the
+// extrinsic and the declared weight of its call. Once dispatched, the part of the fee paid for
+// unused weight is refunded.
+pub trait ChargeTransaction {
+	// The type used to identify the signer of an extrinsic.
+	type AccountId;
+	// The type of the fees.
+	type Balance;
+
+	// Compute the fee of an extrinsic which is `len` bytes long, and whose call has `weight`.
+	fn compute_fee(len: usize, weight: Weight) -> Self::Balance;
+	// Check if `who` can pay `fee`, without withdrawing it.
+	fn can_pay_fee(&self, who: &Self::AccountId, fee: Self::Balance) -> bool;
+	// Withdraw `fee` from the account of `who`. Returns `false` if `who` cannot pay it.
+	fn withdraw_fee(&mut self, who: &Self::AccountId, fee: Self::Balance) -> bool;
+	// Settle the fee of a dispatched extrinsic: `charged` was withdrawn from `who`, but only
+	// `actual` is due. The difference is refunded to `who`.
+	fn settle_fee(&mut self, who: &Self::AccountId, charged: Self::Balance, actual: Self::Balance);
 }
@@ -1,50 +1,242 @@
+use crate::{
+	codec::{Decode, Encode},
+	storage::{StorageMap, StorageValue},
+	support::{EventRecord, Hash, Hooks, Phase, RuntimeVersion, SigningContext, Weight},
+};
 use core::ops::AddAssign;
 use num::traits::{One, Zero};
-use std::collections::BTreeMap;
 
 pub trait Config {
-	type AccountId: Ord + Clone;
-	type BlockNumber: Zero + One + AddAssign + Copy;
-	type Nonce: Zero + One + Copy;
+	// The identifier of an account. In our runtime, it is derived from the public key which signs
+	// the extrinsics of the account.
+	type AccountId: Ord + Clone + Encode + Decode;
+	type BlockNumber: Zero + One + AddAssign + Copy + Default + Encode + Decode;
+	type Nonce: Zero + One + Copy + Default + Encode + Decode;
+	// The origin of the calls of the runtime. It can always be converted from and into a
+	// `RawOrigin`.
+	type RuntimeOrigin: From<RawOrigin<Self::AccountId>> + Into<RawOrigin<Self::AccountId>>;
+	// The aggregated event type of the runtime, which wraps the events of every pallet.
+	type RuntimeEvent: Clone;
+	// The maximum total weight of the calls in a single block.
+	const MAX_BLOCK_WEIGHT: Weight;
+	// The version of the runtime.
+	const VERSION: RuntimeVersion;
 }
 
-#[derive(Debug)]
+// The origin of a call: who, if anyone, is making it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RawOrigin<AccountId> {
+	// The call is made by the root of the chain, which is allowed to make privileged calls.
+	Root,
+	// The call is made by an account, which signed the extrinsic containing it.
+	Signed(AccountId),
+	// The call is not made by anyone in particular.
+	None,
+}
+
+// The error returned when a call is made from an origin it does not accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadOrigin;
+
+// Ensure that `origin` is a signed account, and return that account.
+pub fn ensure_signed<AccountId>(
+	origin: impl Into<RawOrigin<AccountId>>,
+) -> Result<AccountId, BadOrigin> {
+	match origin.into() {
+		RawOrigin::Signed(who) => Ok(who),
+		_ => Err(BadOrigin),
+	}
+}
+
+// Ensure that `origin` is the root of the chain.
+pub fn ensure_root<AccountId>(origin: impl Into<RawOrigin<AccountId>>) -> Result<(), BadOrigin> {
+	match origin.into() {
+		RawOrigin::Root => Ok(()),
+		_ => Err(BadOrigin),
+	}
+}
+
+// The initial state of the system pallet.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(
+	bound(
+		serialize = "T::BlockNumber: serde::Serialize",
+		deserialize = "T::BlockNumber: serde::Deserialize<'de>"
+	),
+	default,
+	deny_unknown_fields
+)]
+pub struct GenesisConfig<T: Config> {
+	// The number of the block the chain starts from. The first executed block is the next one.
+	pub block_number: T::BlockNumber,
+}
+
+impl<T: Config> Default for GenesisConfig<T> {
+	fn default() -> Self {
+		Self { block_number: T::BlockNumber::zero() }
+	}
+}
+
+impl<T: Config> GenesisConfig<T> {
+	// Write this initial state into `pallet`.
+	pub fn build(&self, pallet: &mut Pallet<T>) {
+		pallet.block_number.set(self.block_number);
+		pallet.set_last_runtime_upgrade(T::VERSION);
+	}
+}
+
+#[macros::storage]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Pallet<T: Config> {
-	block_number: T::BlockNumber,
-	nonce: BTreeMap<T::AccountId, T::Nonce>,
+	// The number of the current block.
+	#[getter(block_number)]
+	block_number: StorageValue<T::BlockNumber>,
+	// The nonce of every account, which is the number of extrinsics it has made so far.
+	#[getter(nonce)]
+	nonce: StorageMap<T::AccountId, T::Nonce>,
+	// The name and the version of the last runtime which used the state. They are empty for a state
+	// which predates runtime versions.
+	#[getter(last_runtime_upgrade)]
+	last_runtime_upgrade: StorageValue<(String, u32)>,
+	// The hash of the initial state of the chain, which identifies the chain. It is zero for a
+	// runtime which was not built from a genesis config.
+	genesis_hash: StorageValue<Hash>,
+	// The hash of the last executed block. It is not part of the state, since it is only known
+	// once the state root of that block has been computed.
+	parent_hash: Hash,
+	// The events emitted in the current block. They are cleared at the start of every block.
+	//
+	// The events and the weight of the current block are not part of the state, but they follow its
+	// transactions, so a rejected block leaves those of the previous block in place.
+	#[transactional]
+	events: StorageValue<Vec<EventRecord<T::RuntimeEvent>>>,
+	// The weight consumed so far by the current block. It is reset at the start of every block.
+	#[transactional]
+	block_weight: StorageValue<Weight>,
 }
 
 impl<T: Config> Pallet<T> {
 	// Create a new instance of the System Pallet.
 	pub fn new() -> Self {
-		Self { block_number: T::BlockNumber::zero(), nonce: BTreeMap::new() }
-	}
-
-	// Get the current block number.
-	pub fn block_number(&self) -> T::BlockNumber {
-		self.block_number
+		Self {
+			block_number: StorageValue::new(),
+			nonce: StorageMap::new(),
+			last_runtime_upgrade: StorageValue::new(),
+			genesis_hash: StorageValue::new(),
+			parent_hash: Hash::default(),
+			events: StorageValue::new(),
+			block_weight: StorageValue::new(),
+		}
 	}
 
 	// Increment the block number.
 	pub fn inc_block_number(&mut self) {
-		self.block_number += T::BlockNumber::one();
+		self.block_number.mutate(|block_number| *block_number += T::BlockNumber::one());
+	}
+
+	// Get the hash of the last executed block, which is the parent of the next block.
+	pub fn parent_hash(&self) -> Hash {
+		self.parent_hash
+	}
+
+	// Set the hash of the last executed block.
+	pub fn set_parent_hash(&mut self, hash: Hash) {
+		self.parent_hash = hash;
+	}
+
+	// Record that the state is now used by the runtime at `version`.
+	pub fn set_last_runtime_upgrade(&mut self, version: RuntimeVersion) {
+		self.last_runtime_upgrade
+			.set((version.spec_name.to_string(), version.spec_version));
+	}
+
+	// Record the hash of the initial state of the chain.
+	pub fn set_genesis_hash(&mut self, hash: Hash) {
+		self.genesis_hash.set(hash);
+	}
+
+	// The chain the extrinsics applied by this runtime must be signed for.
+	pub fn signing_context(&self) -> SigningContext {
+		SigningContext { genesis_hash: *self.genesis_hash.get(), version: T::VERSION }
 	}
 
 	// Increment the nonce of an account.
 	pub fn inc_nonce(&mut self, who: &T::AccountId) {
-		let nonce: T::Nonce = *self.nonce.get(who).unwrap_or(&T::Nonce::zero());
-		let new_nonce = nonce + T::Nonce::one();
-		self.nonce.insert(who.clone(), new_nonce);
+		self.nonce.mutate(who.clone(), |nonce| *nonce = *nonce + T::Nonce::one());
+	}
+
+	// Record an event emitted during `phase` of the current block.
+	pub fn deposit_event(&mut self, phase: Phase, event: T::RuntimeEvent) {
+		self.events.mutate(|events| events.push(EventRecord { phase, event }));
+	}
+
+	// Get all the events emitted so far in the current block.
+	pub fn events(&self) -> &[EventRecord<T::RuntimeEvent>] {
+		self.events.get()
+	}
+
+	// Clear the events emitted in the previous block.
+	pub fn reset_events(&mut self) {
+		self.events.set(Vec::new());
+	}
+
+	// Get the weight consumed so far by the current block.
+	pub fn block_weight(&self) -> Weight {
+		*self.block_weight.get()
+	}
+
+	// Add `weight` to the weight consumed by the current block.
+	//
+	// Returns `false`, without consuming anything, if the block would weigh more than
+	// `T::MAX_BLOCK_WEIGHT`.
+	pub fn consume_weight(&mut self, weight: Weight) -> bool {
+		match self.block_weight().checked_add(weight) {
+			Some(total) if total <= T::MAX_BLOCK_WEIGHT => {
+				self.block_weight.set(total);
+				true
+			},
+			_ => false,
+		}
+	}
+
+	// Add `weight` to the weight consumed by the current block, even if the block would weigh
+	// more than `T::MAX_BLOCK_WEIGHT`.
+	//
+	// This is used for work which must happen in every block, like the hooks of the pallets.
+	pub fn register_extra_weight(&mut self, weight: Weight) {
+		self.block_weight.set(self.block_weight().saturating_add(weight));
+	}
+
+	// Clear the weight consumed by the previous block.
+	pub fn reset_block_weight(&mut self) {
+		self.block_weight.set(0);
+	}
+}
+
+// The system pallet starts every block by clearing what it kept about the previous one. It is the
+// first pallet of the runtime, so this happens before the hooks of any other pallet.
+impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {
+	fn on_initialize(&mut self, _n: T::BlockNumber) -> Weight {
+		self.reset_events();
+		self.reset_block_weight();
+		0
 	}
 }
 
 #[cfg(test)]
 mod test {
+	use super::{ensure_root, ensure_signed, BadOrigin, RawOrigin};
+	use crate::support::{EventRecord, Hooks, Phase, RuntimeVersion};
+
 	struct TestConfig;
 	impl super::Config for TestConfig {
 		type AccountId = String;
 		type BlockNumber = u32;
 		type Nonce = u32;
+		type RuntimeOrigin = RawOrigin<String>;
+		type RuntimeEvent = &'static str;
+		const MAX_BLOCK_WEIGHT: u64 = 100;
+		const VERSION: RuntimeVersion = RuntimeVersion { spec_name: "test", spec_version: 1 };
 	}
 
 	#[test]
@@ -54,7 +246,68 @@ mod test {
 		system.inc_nonce(&"alice".to_string());
 
 		assert_eq!(system.block_number(), 1);
-		assert_eq!(system.nonce.get(&"alice".to_string()), Some(&1));
-		assert_eq!(system.nonce.get(&"bob".to_string()), None);
+		assert_eq!(system.nonce(&"alice".to_string()), 1);
+		assert_eq!(system.nonce(&"bob".to_string()), 0);
+	}
+
+	#[test]
+	fn deposit_events() {
+		let mut system = super::Pallet::<TestConfig>::new();
+		system.deposit_event(Phase::Initialization, "first");
+		system.deposit_event(Phase::ApplyExtrinsic(2), "second");
+
+		assert_eq!(
+			system.events(),
+			&[
+				EventRecord { phase: Phase::Initialization, event: "first" },
+				EventRecord { phase: Phase::ApplyExtrinsic(2), event: "second" },
+			]
+		);
+
+		system.reset_events();
+		assert!(system.events().is_empty());
+	}
+
+	#[test]
+	fn consume_block_weight() {
+		let mut system = super::Pallet::<TestConfig>::new();
+		assert!(system.consume_weight(60));
+		assert!(system.consume_weight(40));
+		// The block is full, so nothing more can be consumed.
+		assert!(!system.consume_weight(1));
+		assert!(!system.consume_weight(u64::MAX));
+		assert_eq!(system.block_weight(), 100);
+
+		system.reset_block_weight();
+		assert_eq!(system.block_weight(), 0);
+
+		// Extra weight is always registered, but leaves no room for calls.
+		system.register_extra_weight(150);
+		assert_eq!(system.block_weight(), 150);
+		assert!(!system.consume_weight(0));
+	}
+
+	#[test]
+	fn on_initialize_clears_the_previous_block() {
+		let mut system = super::Pallet::<TestConfig>::new();
+		system.deposit_event(Phase::Finalization, "last");
+		system.register_extra_weight(10);
+
+		assert_eq!(system.on_initialize(1), 0);
+		assert!(system.events().is_empty());
+		assert_eq!(system.block_weight(), 0);
+	}
+
+	#[test]
+	fn ensure_origins() {
+		let alice = || RawOrigin::Signed("alice".to_string());
+
+		assert_eq!(ensure_signed(alice()), Ok("alice".to_string()));
+		assert_eq!(ensure_signed(RawOrigin::<String>::Root), Err(BadOrigin));
+		assert_eq!(ensure_signed(RawOrigin::<String>::None), Err(BadOrigin));
+
+		assert_eq!(ensure_root(RawOrigin::<String>::Root), Ok(()));
+		assert_eq!(ensure_root(alice()), Err(BadOrigin));
+		assert_eq!(ensure_root(RawOrigin::<String>::None), Err(BadOrigin));
 	}
 }
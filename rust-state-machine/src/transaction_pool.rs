@@ -0,0 +1,526 @@
+use crate::{
+	codec::Encode,
+	support::{hash, BlockError, Hash},
+};
+use core::ops::Add;
+use num::traits::One;
+use std::collections::{BTreeMap, BTreeSet};
+
+// The transaction pool holds the extrinsics submitted to the node, until they are included in a
+// block.
+//
+// Every extrinsic is validated against the current state when it is submitted. The extrinsics of
+// each signer are kept by nonce: those following the next nonce of the signer without any gap are
+// ready to be included in the next block, while the others wait in the future queue until the
+// missing nonces are submitted. Ready extrinsics are included by priority, which is their fee,
+// but always in the nonce order of their signer.
+
+// What the runtime knows about a valid extrinsic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidTransaction<AccountId, Nonce, Priority> {
+	// The account which signed the extrinsic.
+	pub signer: AccountId,
+	// The nonce of the extrinsic.
+	pub nonce: Nonce,
+	// The next nonce of the signer in the current state.
+	pub account_nonce: Nonce,
+	// Extrinsics with a higher priority are included first.
+	pub priority: Priority,
+}
+
+// What the runtime `V` knows about a valid extrinsic.
+pub type ValidTransactionOf<V> = ValidTransaction<
+	<V as TransactionValidator>::AccountId,
+	<V as TransactionValidator>::Nonce,
+	<V as TransactionValidator>::Priority,
+>;
+
+// A trait implemented by the runtime to check extrinsics before they enter the pool.
+pub trait TransactionValidator {
+	type Extrinsic: Encode;
+	type AccountId: Ord + Clone;
+	type Nonce: Ord + Copy + One + Add<Output = Self::Nonce>;
+	type Priority: Ord + Copy;
+
+	// Check that `extrinsic` can be included in a block on top of the current state, once the
+	// extrinsics of its signer with a lower nonce are included.
+	fn validate_transaction(
+		&self,
+		extrinsic: &Self::Extrinsic,
+	) -> Result<ValidTransactionOf<Self>, BlockError>;
+}
+
+// The reasons why an extrinsic is not added to the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolError {
+	// The extrinsic cannot be included on top of the current state.
+	Invalid(BlockError),
+	// The extrinsic is already in the pool.
+	AlreadyImported,
+	// An extrinsic with the same signer and nonce, and at least the same priority, is in the pool.
+	TooLowPriority,
+	// The pool is full of extrinsics which are ready sooner or have a higher priority.
+	PoolFull,
+}
+
+impl core::fmt::Display for PoolError {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			PoolError::Invalid(e) => write!(f, "invalid extrinsic: {}", e),
+			PoolError::AlreadyImported => write!(f, "extrinsic is already in the pool"),
+			PoolError::TooLowPriority => {
+				write!(f, "extrinsic does not have the priority to replace another one")
+			},
+			PoolError::PoolFull => write!(f, "transaction pool is full"),
+		}
+	}
+}
+
+// The maximum size of the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolLimits {
+	// The maximum number of extrinsics in the pool.
+	pub max_count: usize,
+	// The maximum total length of the encoded extrinsics in the pool.
+	pub max_bytes: usize,
+}
+
+impl Default for PoolLimits {
+	fn default() -> Self {
+		Self { max_count: 1_000, max_bytes: 1_000_000 }
+	}
+}
+
+// The number of extrinsics in each queue of the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStatus {
+	pub ready: usize,
+	pub future: usize,
+	// The total length of the encoded extrinsics in the pool.
+	pub bytes: usize,
+}
+
+// An extrinsic waiting in the pool.
+struct PooledTransaction<V: TransactionValidator> {
+	extrinsic: V::Extrinsic,
+	hash: Hash,
+	len: usize,
+	priority: V::Priority,
+}
+
+// An extrinsic evicted from the pool, along with its signer, the next nonce of its signer and its
+// nonce.
+type EvictedTransaction<V> = (
+	<V as TransactionValidator>::AccountId,
+	<V as TransactionValidator>::Nonce,
+	<V as TransactionValidator>::Nonce,
+	PooledTransaction<V>,
+);
+
+// The extrinsics of a single signer.
+struct Account<V: TransactionValidator> {
+	// The next nonce of the signer in the current state.
+	next_nonce: V::Nonce,
+	transactions: BTreeMap<V::Nonce, PooledTransaction<V>>,
+}
+
+impl<V: TransactionValidator> Account<V> {
+	// The extrinsics of the signer which are ready, in the order they must be included.
+	fn ready(&self) -> impl Iterator<Item = &PooledTransaction<V>> {
+		let mut expected = self.next_nonce;
+		self.transactions
+			.range(self.next_nonce..)
+			.map_while(move |(nonce, transaction)| {
+				let is_next = *nonce == expected;
+				expected = expected + V::Nonce::one();
+				is_next.then_some(transaction)
+			})
+	}
+}
+
+// A pool of extrinsics waiting to be included in a block.
+pub struct TransactionPool<V: TransactionValidator> {
+	limits: PoolLimits,
+	accounts: BTreeMap<V::AccountId, Account<V>>,
+	// The hash of every extrinsic in the pool.
+	hashes: BTreeSet<Hash>,
+	bytes: usize,
+}
+
+impl<V: TransactionValidator> TransactionPool<V> {
+	// Create a new empty pool, which never exceeds `limits`.
+	pub fn new(limits: PoolLimits) -> Self {
+		Self { limits, accounts: BTreeMap::new(), hashes: BTreeSet::new(), bytes: 0 }
+	}
+
+	// Validate `extrinsic` against the current state of `runtime`, and add it to the pool.
+	// Returns the hash of the extrinsic.
+	//
+	// An extrinsic replaces the one with the same signer and nonce if it has a higher priority.
+	// When the pool is full, the last extrinsics of the signers are evicted, starting with future
+	// extrinsics and then with the lowest priority. If the extrinsic itself is evicted, the pool is
+	// left as it was.
+	pub fn submit(&mut self, runtime: &V, extrinsic: V::Extrinsic) -> Result<Hash, PoolError> {
+		let valid = runtime.validate_transaction(&extrinsic).map_err(PoolError::Invalid)?;
+		let encoded = extrinsic.encode();
+		let (len, hash) = (encoded.len(), hash(&encoded));
+		if self.contains(&hash) {
+			return Err(PoolError::AlreadyImported);
+		}
+		let existing = self
+			.accounts
+			.get(&valid.signer)
+			.and_then(|account| account.transactions.get(&valid.nonce));
+		if existing.is_some_and(|existing| existing.priority >= valid.priority) {
+			return Err(PoolError::TooLowPriority);
+		}
+
+		let transaction = PooledTransaction { extrinsic, hash, len, priority: valid.priority };
+		let (signer, next_nonce, nonce) = (valid.signer, valid.account_nonce, valid.nonce);
+		let replaced = self.insert(signer.clone(), next_nonce, nonce, transaction);
+		let evicted = self.enforce_limits();
+		if !self.contains(&hash) {
+			for (who, next_nonce, nonce, transaction) in evicted {
+				if transaction.hash != hash {
+					self.insert(who, next_nonce, nonce, transaction);
+				}
+			}
+			if let Some(replaced) = replaced {
+				self.insert(signer, next_nonce, nonce, replaced);
+			}
+			return Err(PoolError::PoolFull);
+		}
+		Ok(hash)
+	}
+
+	// Check if the extrinsic with `hash` is in the pool.
+	pub fn contains(&self, hash: &Hash) -> bool {
+		self.hashes.contains(hash)
+	}
+
+	// The ready extrinsics, in the order they should be included in a block: by priority, but
+	// always after the extrinsics of their signer with a lower nonce.
+	pub fn ready(&self) -> Vec<&V::Extrinsic> {
+		let mut queues = self
+			.accounts
+			.values()
+			.map(|account| account.ready().peekable())
+			.collect::<Vec<_>>();
+		let mut ready = Vec::new();
+		loop {
+			// Ties are broken in favor of the first signer, so the order is deterministic.
+			let best = queues
+				.iter_mut()
+				.enumerate()
+				.filter_map(|(i, queue)| Some((queue.peek()?.priority, core::cmp::Reverse(i))))
+				.max();
+			let Some((_, core::cmp::Reverse(i))) = best else { break };
+			let transaction = queues[i].next().expect("the queue has a next extrinsic");
+			ready.push(&transaction.extrinsic);
+		}
+		ready
+	}
+
+	// Every extrinsic in the pool, ready or not, ordered by signer and nonce.
+	pub fn extrinsics(&self) -> Vec<&V::Extrinsic> {
+		self.accounts
+			.values()
+			.flat_map(|account| account.transactions.values())
+			.map(|transaction| &transaction.extrinsic)
+			.collect()
+	}
+
+	// The nonce following the ready extrinsics of `who`, which is the nonce its next extrinsic must
+	// use. Returns `None` if `who` has no extrinsic in the pool.
+	pub fn next_nonce(&self, who: &V::AccountId) -> Option<V::Nonce> {
+		let account = self.accounts.get(who)?;
+		Some(account.ready().fold(account.next_nonce, |nonce, _| nonce + V::Nonce::one()))
+	}
+
+	// The number of extrinsics in each queue.
+	pub fn status(&self) -> PoolStatus {
+		let ready = self.accounts.values().map(|account| account.ready().count()).sum();
+		PoolStatus { ready, future: self.hashes.len() - ready, bytes: self.bytes }
+	}
+
+	// Update the pool after a block was imported, given the new state of `runtime`.
+	//
+	// Every extrinsic is validated again, so the extrinsics included in the block, whose nonce is
+	// now used, are removed, along with those which became invalid. Future extrinsics whose
+	// missing nonces were included become ready.
+	pub fn on_block_imported(&mut self, runtime: &V) {
+		let accounts = core::mem::take(&mut self.accounts);
+		self.hashes.clear();
+		self.bytes = 0;
+		for account in accounts.into_values() {
+			for transaction in account.transactions.into_values() {
+				let _ = self.submit(runtime, transaction.extrinsic);
+			}
+		}
+	}
+
+	// Add `transaction` with `nonce` to the extrinsics of `who`, whose next nonce in the current
+	// state is `next_nonce`. Returns the extrinsic it replaces, if any.
+	fn insert(
+		&mut self,
+		who: V::AccountId,
+		next_nonce: V::Nonce,
+		nonce: V::Nonce,
+		transaction: PooledTransaction<V>,
+	) -> Option<PooledTransaction<V>> {
+		let account = self
+			.accounts
+			.entry(who)
+			.or_insert_with(|| Account { next_nonce, transactions: BTreeMap::new() });
+		account.next_nonce = next_nonce;
+		self.hashes.insert(transaction.hash);
+		self.bytes += transaction.len;
+		let replaced = account.transactions.insert(nonce, transaction)?;
+		self.hashes.remove(&replaced.hash);
+		self.bytes -= replaced.len;
+		Some(replaced)
+	}
+
+	// Evict extrinsics until the pool is within its limits, and return them.
+	//
+	// Only the last extrinsic of a signer is evicted, so no gap is left in its nonces.
+	fn enforce_limits(&mut self) -> Vec<EvictedTransaction<V>> {
+		let mut evicted = Vec::new();
+		while self.hashes.len() > self.limits.max_count || self.bytes > self.limits.max_bytes {
+			let worst = self
+				.accounts
+				.iter()
+				.filter_map(|(who, account)| {
+					let (nonce, last) = account.transactions.last_key_value()?;
+					let is_ready = account.ready().count() == account.transactions.len();
+					Some(((is_ready, last.priority), who.clone(), *nonce))
+				})
+				.min_by(|a, b| a.0.cmp(&b.0));
+			let Some((_, who, nonce)) = worst else { break };
+
+			let account = self.accounts.get_mut(&who).expect("the account has extrinsics");
+			let next_nonce = account.next_nonce;
+			let transaction =
+				account.transactions.remove(&nonce).expect("the extrinsic is in the pool");
+			if account.transactions.is_empty() {
+				self.accounts.remove(&who);
+			}
+			self.hashes.remove(&transaction.hash);
+			self.bytes -= transaction.len;
+			evicted.push((who, next_nonce, nonce, transaction));
+		}
+		evicted
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{PoolError, PoolLimits, PoolStatus, TransactionPool, TransactionValidator};
+	use crate::{
+		balances, codec::Encode, crypto::Pair, proof_of_existence, support::BlockError, types,
+		Runtime, RuntimeCall,
+	};
+
+	// Sign `call` for the chain of the runtimes built by `runtime`.
+	fn sign(signer: &Pair, nonce: u32, call: RuntimeCall) -> types::Extrinsic {
+		types::Extrinsic::new_signed(signer, nonce, call, &Runtime::new().system.signing_context())
+	}
+
+	fn transfer(signer: &Pair, nonce: u32, amount: u128) -> types::Extrinsic {
+		let to = Pair::from_seed("charlie").account_id();
+		let call = RuntimeCall::balances(balances::Call::transfer { to, amount });
+		sign(signer, nonce, call)
+	}
+
+	// A claim on `len` bytes, whose fee grows with `len`.
+	fn claim(signer: &Pair, nonce: u32, len: usize) -> types::Extrinsic {
+		let call = RuntimeCall::proof_of_existence(proof_of_existence::Call::create_claim {
+			claim: "a".repeat(len),
+		});
+		sign(signer, nonce, call)
+	}
+
+	fn runtime(funded: &[&Pair]) -> Runtime {
+		let mut runtime = Runtime::new();
+		for pair in funded {
+			runtime.balances.set_balance(&pair.account_id(), 1_000_000);
+		}
+		runtime
+	}
+
+	fn status(ready: usize, future: usize, pool: &TransactionPool<Runtime>) -> PoolStatus {
+		PoolStatus { ready, future, bytes: pool.status().bytes }
+	}
+
+	#[test]
+	fn ready_and_future_queues() {
+		let alice = Pair::from_seed("alice");
+		let runtime = runtime(&[&alice]);
+		let mut pool = TransactionPool::new(PoolLimits::default());
+
+		// An extrinsic whose nonce is ahead of the signer waits in the future queue.
+		pool.submit(&runtime, transfer(&alice, 2, 1)).unwrap();
+		pool.submit(&runtime, transfer(&alice, 1, 1)).unwrap();
+		assert_eq!(pool.status(), status(0, 2, &pool));
+		assert!(pool.ready().is_empty());
+		assert_eq!(pool.next_nonce(&alice.account_id()), Some(0));
+		assert_eq!(pool.next_nonce(&Pair::from_seed("bob").account_id()), None);
+
+		// Until the missing nonce is submitted.
+		pool.submit(&runtime, transfer(&alice, 0, 1)).unwrap();
+		assert_eq!(pool.status(), status(3, 0, &pool));
+		let ready = pool.ready().into_iter().map(|e| e.nonce).collect::<Vec<_>>();
+		assert_eq!(ready, vec![0, 1, 2]);
+		assert_eq!(pool.next_nonce(&alice.account_id()), Some(3));
+		let all = pool.extrinsics().into_iter().map(|e| e.nonce).collect::<Vec<_>>();
+		assert_eq!(all, vec![0, 1, 2]);
+	}
+
+	#[test]
+	fn ready_extrinsics_are_ordered_by_priority() {
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		let runtime = runtime(&[&alice, &bob]);
+		let mut pool = TransactionPool::new(PoolLimits::default());
+
+		// Longer claims pay a higher fee, so they have a higher priority.
+		let priority = |e| runtime.validate_transaction(&e).unwrap().priority;
+		assert!(priority(claim(&alice, 0, 20)) > priority(claim(&alice, 0, 10)));
+
+		pool.submit(&runtime, claim(&alice, 0, 1)).unwrap();
+		pool.submit(&runtime, claim(&alice, 1, 30)).unwrap();
+		pool.submit(&runtime, claim(&bob, 0, 10)).unwrap();
+		pool.submit(&runtime, claim(&bob, 1, 20)).unwrap();
+
+		// The claim of alice with the highest priority must wait for her first claim.
+		let ready = pool.ready().into_iter().map(|e| (e.signer, e.nonce)).collect::<Vec<_>>();
+		assert_eq!(
+			ready,
+			vec![(bob.public(), 0), (bob.public(), 1), (alice.public(), 0), (alice.public(), 1)]
+		);
+	}
+
+	#[test]
+	fn invalid_extrinsics_are_rejected() {
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		let mut runtime = runtime(&[&alice]);
+		runtime.system.inc_nonce(&alice.account_id());
+		let mut pool = TransactionPool::new(PoolLimits::default());
+
+		let mut forged = transfer(&bob, 1, 1);
+		forged.signer = alice.public();
+		assert_eq!(
+			pool.submit(&runtime, forged),
+			Err(PoolError::Invalid(BlockError::BadSignature))
+		);
+		assert_eq!(
+			pool.submit(&runtime, transfer(&alice, 0, 1)),
+			Err(PoolError::Invalid(BlockError::StaleNonce))
+		);
+		assert_eq!(
+			pool.submit(&runtime, transfer(&bob, 0, 1)),
+			Err(PoolError::Invalid(BlockError::CannotPayFees))
+		);
+
+		// The same extrinsic cannot be submitted twice, and it can only be replaced by an
+		// extrinsic with a higher priority.
+		let hash = pool.submit(&runtime, claim(&alice, 1, 10)).unwrap();
+		assert!(pool.contains(&hash));
+		assert_eq!(pool.submit(&runtime, claim(&alice, 1, 10)), Err(PoolError::AlreadyImported));
+		assert_eq!(pool.submit(&runtime, claim(&alice, 1, 5)), Err(PoolError::TooLowPriority));
+		let replacement = pool.submit(&runtime, claim(&alice, 1, 20)).unwrap();
+		assert!(!pool.contains(&hash));
+		assert!(pool.contains(&replacement));
+		assert_eq!(pool.status(), status(1, 0, &pool));
+	}
+
+	#[test]
+	fn pool_limits_evict_the_worst_extrinsics() {
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		let runtime = runtime(&[&alice, &bob]);
+		let limits = PoolLimits { max_count: 3, ..Default::default() };
+		let mut pool = TransactionPool::new(limits);
+
+		let future = pool.submit(&runtime, claim(&bob, 5, 30)).unwrap();
+		let alice_first = pool.submit(&runtime, claim(&alice, 0, 1)).unwrap();
+		pool.submit(&runtime, claim(&bob, 0, 10)).unwrap();
+
+		// Future extrinsics are evicted first, even with a higher priority.
+		let alice_second = pool.submit(&runtime, claim(&alice, 1, 20)).unwrap();
+		assert!(!pool.contains(&future));
+		assert_eq!(pool.status(), status(3, 0, &pool));
+
+		// Then the last ready extrinsic of a signer with the lowest priority. The first extrinsic
+		// of alice has a lower priority, but evicting it would leave a gap in her nonces.
+		let bob_second = pool.submit(&runtime, claim(&bob, 1, 25)).unwrap();
+		assert!(pool.contains(&alice_first));
+		assert!(!pool.contains(&alice_second));
+		assert!(pool.contains(&bob_second));
+
+		// An extrinsic which would be evicted right away is rejected.
+		assert_eq!(pool.submit(&runtime, claim(&alice, 1, 2)), Err(PoolError::PoolFull));
+
+		// The total length of the extrinsics is limited too.
+		let limits = PoolLimits { max_bytes: pool.status().bytes, ..Default::default() };
+		let mut pool = TransactionPool::new(limits);
+		pool.submit(&runtime, claim(&alice, 0, 1)).unwrap();
+		pool.submit(&runtime, claim(&bob, 0, 10)).unwrap();
+		pool.submit(&runtime, claim(&bob, 1, 25)).unwrap();
+		assert_eq!(pool.submit(&runtime, claim(&alice, 1, 1)), Err(PoolError::PoolFull));
+	}
+
+	#[test]
+	fn rejected_replacements_leave_the_pool_unchanged() {
+		let alice = Pair::from_seed("alice");
+		let bob = Pair::from_seed("bob");
+		let charlie = Pair::from_seed("charlie");
+		let runtime = runtime(&[&alice, &bob, &charlie]);
+		let (alice_claim, bob_claim, future) =
+			(claim(&alice, 0, 10), claim(&bob, 0, 1_000), claim(&charlie, 1, 1));
+		let max_bytes = [&alice_claim, &bob_claim, &future].map(|e| e.encode().len()).iter().sum();
+		let mut pool = TransactionPool::new(PoolLimits { max_bytes, ..Default::default() });
+		let hashes = [alice_claim, bob_claim, future].map(|e| pool.submit(&runtime, e).unwrap());
+		let status = pool.status();
+
+		// The longer claim of alice has a higher priority than her first one, but not than the
+		// claim of bob. So the future extrinsic of charlie is evicted, and then the replacement,
+		// which does not fit: the replaced claim and the future extrinsic are kept.
+		let replacement = claim(&alice, 0, 500);
+		assert_eq!(pool.submit(&runtime, replacement), Err(PoolError::PoolFull));
+		assert!(hashes.iter().all(|hash| pool.contains(hash)));
+		assert_eq!(pool.status(), status);
+		assert_eq!(pool.next_nonce(&charlie.account_id()), Some(0));
+	}
+
+	#[test]
+	fn imported_blocks_evict_included_extrinsics() {
+		let alice = Pair::from_seed("alice");
+		let runtime = runtime(&[&alice]);
+		let mut pool = TransactionPool::new(PoolLimits::default());
+		pool.submit(&runtime, transfer(&alice, 0, 1)).unwrap();
+		pool.submit(&runtime, transfer(&alice, 1, 1)).unwrap();
+		pool.submit(&runtime, transfer(&alice, 3, 1)).unwrap();
+		let expensive = pool.submit(&runtime, claim(&alice, 4, 100)).unwrap();
+
+		// Importing a block including the ready extrinsics leaves only the future ones.
+		let mut runtime = runtime;
+		let extrinsics = pool.ready().into_iter().cloned().collect();
+		let block = crate::build_block(&runtime, extrinsics);
+		runtime.execute_block(block).unwrap();
+		pool.on_block_imported(&runtime);
+		assert_eq!(pool.status(), status(0, 2, &pool));
+
+		// Once the missing nonce is included, the next extrinsic becomes ready. The last one is
+		// evicted, since alice can still pay the fee of a transfer but not of a long claim.
+		let balance = runtime.balances.balance(&alice.account_id());
+		let fee = runtime.validate_transaction(&transfer(&alice, 2, 0)).unwrap().priority;
+		let block = crate::build_block(&runtime, vec![transfer(&alice, 2, balance - fee - 15_000)]);
+		runtime.execute_block(block).unwrap();
+		pool.on_block_imported(&runtime);
+		assert!(!pool.contains(&expensive));
+		assert_eq!(pool.status(), status(1, 0, &pool));
+		assert_eq!(pool.ready()[0].nonce, 3);
+	}
+}
@@ -0,0 +1,342 @@
+use base64::Engine;
+use sha1::Digest;
+use std::io::{self, Read, Write};
+
+// The parts of the WebSocket protocol (RFC 6455) needed by the RPC server: the opening handshake,
+// and reading and writing messages in frames. Extensions and subprotocols are not supported.
+
+pub const TEXT: u8 = 0x1;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xa;
+const CONTINUATION: u8 = 0x0;
+const BINARY: u8 = 0x2;
+
+// The longest message accepted, so a peer cannot make us allocate an unbounded amount of memory.
+pub const MAX_MESSAGE_LEN: usize = 1 << 20;
+// The longest payload of a control frame, as defined by the protocol.
+const MAX_CONTROL_LEN: usize = 125;
+
+// The GUID appended to the key of the client, as defined by the protocol.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// A message received from the peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+	// A text message, or a binary message which is valid UTF-8.
+	Text(String),
+	// A ping, which must be answered with a pong carrying the same data.
+	Ping(Vec<u8>),
+	// The answer to a ping.
+	Pong,
+	// The peer closes the connection.
+	Close,
+}
+
+// The response of the server to the opening handshake of a client, which sent `key` in its
+// `Sec-WebSocket-Key` header.
+pub fn handshake_response(key: &str) -> String {
+	format!(
+		"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+		 Sec-WebSocket-Accept: {}\r\n\r\n",
+		accept_key(key)
+	)
+}
+
+// The value of the `Sec-WebSocket-Accept` header which proves to the client that the server
+// understood its handshake.
+pub fn accept_key(key: &str) -> String {
+	let hash = sha1::Sha1::digest(format!("{}{}", key.trim(), GUID).as_bytes());
+	base64::engine::general_purpose::STANDARD.encode(hash)
+}
+
+// Write a single frame with `opcode` and `payload`. Clients must mask their frames, servers must
+// not.
+pub fn write_frame<W: Write>(
+	writer: &mut W,
+	opcode: u8,
+	payload: &[u8],
+	mask: Option<[u8; 4]>,
+) -> io::Result<()> {
+	let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+	let mut frame = vec![0x80 | opcode];
+	match payload.len() {
+		len @ 0..=125 => frame.push(mask_bit | len as u8),
+		len @ 126..=0xffff => {
+			frame.push(mask_bit | 126);
+			frame.extend_from_slice(&(len as u16).to_be_bytes());
+		},
+		len => {
+			frame.push(mask_bit | 127);
+			frame.extend_from_slice(&(len as u64).to_be_bytes());
+		},
+	}
+	match mask {
+		Some(mask) => {
+			frame.extend_from_slice(&mask);
+			frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+		},
+		None => frame.extend_from_slice(payload),
+	}
+	writer.write_all(&frame)?;
+	writer.flush()
+}
+
+// Reads the messages sent by the peer, joining the frames of fragmented messages.
+pub struct MessageReader<R> {
+	reader: R,
+	// Whether the frames of the peer must be masked, which is the case of the frames sent by
+	// clients. Servers must not mask their frames.
+	masked: bool,
+	// The frames received so far of a fragmented message. Control frames can arrive in between.
+	partial: Option<Vec<u8>>,
+}
+
+impl<R: Read> MessageReader<R> {
+	// Read the messages sent by a client to the server.
+	pub fn from_client(reader: R) -> Self {
+		Self { reader, masked: true, partial: None }
+	}
+
+	// Read the messages sent by a server to the client.
+	#[cfg(test)]
+	pub fn from_server(reader: R) -> Self {
+		Self { reader, masked: false, partial: None }
+	}
+
+	// Read the next message.
+	//
+	// A read which times out before the first byte of a frame fails with an error for which
+	// `is_timeout` holds, and can be retried. Any other error fails the connection.
+	pub fn read_message(&mut self) -> io::Result<Message> {
+		loop {
+			let (fin, opcode, payload) = read_frame(&mut self.reader, self.masked)?;
+			match (opcode, self.partial.as_mut()) {
+				(TEXT | BINARY, None) => self.partial = Some(payload),
+				(CONTINUATION, Some(partial)) => {
+					if partial.len() + payload.len() > MAX_MESSAGE_LEN {
+						return Err(invalid_data("message too long"));
+					}
+					partial.extend_from_slice(&payload);
+				},
+				(TEXT | BINARY, Some(_)) => return Err(invalid_data("message is not finished")),
+				(CONTINUATION, None) => return Err(invalid_data("no message to continue")),
+				(CLOSE, _) => return Ok(Message::Close),
+				(PING, _) => return Ok(Message::Ping(payload)),
+				(PONG, _) => return Ok(Message::Pong),
+				_ => return Err(invalid_data("unknown opcode")),
+			}
+			if fin {
+				let message = self.partial.take().expect("a data frame was just read");
+				return String::from_utf8(message)
+					.map(Message::Text)
+					.map_err(|_| invalid_data("message is not valid UTF-8"));
+			}
+		}
+	}
+}
+
+// Whether `error` is a read which timed out, which is reported with a different kind depending on
+// the platform.
+pub fn is_timeout(error: &io::Error) -> bool {
+	matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+// Read a single frame, whose payload must be masked if `masked` holds, and return whether it is the
+// last frame of its message, its opcode and its unmasked payload.
+//
+// A timeout in the middle of a frame is an error, since the frame cannot be read again.
+fn read_frame<R: Read>(reader: &mut R, masked: bool) -> io::Result<(bool, u8, Vec<u8>)> {
+	let mut first = [0u8; 1];
+	reader.read_exact(&mut first)?;
+	read_frame_rest(reader, first[0], masked).map_err(|e| match is_timeout(&e) {
+		true => invalid_data("frame timed out"),
+		false => e,
+	})
+}
+
+// Read the rest of a frame whose first byte is `first`.
+fn read_frame_rest<R: Read>(
+	reader: &mut R,
+	first: u8,
+	masked: bool,
+) -> io::Result<(bool, u8, Vec<u8>)> {
+	let mut second = [0u8; 1];
+	reader.read_exact(&mut second)?;
+	let fin = first & 0x80 != 0;
+	let opcode = first & 0x0f;
+	// The reserved bits are only used by extensions.
+	if first & 0x70 != 0 {
+		return Err(invalid_data("reserved bits are set"));
+	}
+	if (second[0] & 0x80 != 0) != masked {
+		return Err(invalid_data(match masked {
+			true => "frames of clients must be masked",
+			false => "frames of servers must not be masked",
+		}));
+	}
+	let len = match second[0] & 0x7f {
+		126 => {
+			let mut len = [0u8; 2];
+			reader.read_exact(&mut len)?;
+			u16::from_be_bytes(len) as u64
+		},
+		127 => {
+			let mut len = [0u8; 8];
+			reader.read_exact(&mut len)?;
+			u64::from_be_bytes(len)
+		},
+		len => len as u64,
+	};
+	// Control frames cannot be fragmented, and have short payloads.
+	if opcode & 0x8 != 0 && (!fin || len > MAX_CONTROL_LEN as u64) {
+		return Err(invalid_data("invalid control frame"));
+	}
+	if len > MAX_MESSAGE_LEN as u64 {
+		return Err(invalid_data("message too long"));
+	}
+	let mut mask = [0u8; 4];
+	if masked {
+		reader.read_exact(&mut mask)?;
+	}
+	let mut payload = vec![0u8; len as usize];
+	reader.read_exact(&mut payload)?;
+	if masked {
+		payload.iter_mut().enumerate().for_each(|(i, byte)| *byte ^= mask[i % 4]);
+	}
+	Ok((fin, opcode, payload))
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+	use super::{
+		accept_key, is_timeout, write_frame, Message, MessageReader, CLOSE, CONTINUATION,
+		MAX_MESSAGE_LEN, PING, TEXT,
+	};
+	use std::{
+		collections::VecDeque,
+		io::{self, Read},
+	};
+
+	// A frame sent by a client, which is the last of its message if `fin` holds.
+	fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		write_frame(&mut bytes, opcode, payload, Some([1, 2, 3, 4])).unwrap();
+		if !fin {
+			bytes[0] &= 0x7f;
+		}
+		bytes
+	}
+
+	// Whether the server rejects the first message of `bytes` as invalid.
+	fn rejected(bytes: &[u8]) -> bool {
+		let result = MessageReader::from_client(bytes).read_message();
+		result.is_err_and(|e| e.kind() == io::ErrorKind::InvalidData)
+	}
+
+	#[test]
+	fn handshake_follows_the_rfc() {
+		// The example of the RFC.
+		assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+	}
+
+	#[test]
+	fn messages_round_trip_through_frames() {
+		for len in [0, 125, 126, 70_000] {
+			let text = "a".repeat(len);
+			let mut bytes = Vec::new();
+			write_frame(&mut bytes, TEXT, text.as_bytes(), None).unwrap();
+			let message = MessageReader::from_server(bytes.as_slice()).read_message().unwrap();
+			assert_eq!(message, Message::Text(text.clone()));
+			let bytes = client_frame(true, TEXT, text.as_bytes());
+			let message = MessageReader::from_client(bytes.as_slice()).read_message().unwrap();
+			assert_eq!(message, Message::Text(text));
+		}
+
+		// A fragmented message is joined, even with a ping in between.
+		let mut bytes = client_frame(false, TEXT, b"he");
+		bytes.extend(client_frame(true, PING, b"ping"));
+		bytes.extend(client_frame(true, CONTINUATION, b"llo"));
+		let mut reader = MessageReader::from_client(bytes.as_slice());
+		assert_eq!(reader.read_message().unwrap(), Message::Ping(b"ping".to_vec()));
+		assert_eq!(reader.read_message().unwrap(), Message::Text("hello".to_string()));
+	}
+
+	#[test]
+	fn invalid_frames_are_rejected() {
+		// Frames of clients must be masked, and frames of servers must not.
+		let mut unmasked = Vec::new();
+		write_frame(&mut unmasked, TEXT, b"hello", None).unwrap();
+		assert!(rejected(&unmasked));
+		let masked = client_frame(true, TEXT, b"hello");
+		let result = MessageReader::from_server(masked.as_slice()).read_message();
+		assert!(result.is_err_and(|e| e.kind() == io::ErrorKind::InvalidData));
+
+		// No extension is negotiated, so the reserved bits must be clear.
+		let mut reserved = client_frame(true, TEXT, b"hello");
+		reserved[0] |= 0x40;
+		assert!(rejected(&reserved));
+
+		// Control frames cannot be fragmented, nor carry more than 125 bytes.
+		assert!(rejected(&client_frame(false, PING, b"ping")));
+		assert!(rejected(&client_frame(true, CLOSE, &[0; 126])));
+		assert!(!rejected(&client_frame(true, PING, &[0; 125])));
+
+		// Fragments must continue a message, and a message must end before the next one starts.
+		assert!(rejected(&client_frame(true, CONTINUATION, b"hello")));
+		let mut bytes = client_frame(false, TEXT, b"he");
+		bytes.extend(client_frame(true, TEXT, b"llo"));
+		assert!(rejected(&bytes));
+
+		// Messages which are too long are rejected before they are read, even when fragmented.
+		let mut bytes = vec![0x80 | TEXT, 0x80 | 127];
+		bytes.extend_from_slice(&u64::MAX.to_be_bytes());
+		assert!(rejected(&bytes));
+		let half = vec![b'a'; MAX_MESSAGE_LEN / 2 + 1];
+		let mut bytes = client_frame(false, TEXT, &half);
+		bytes.extend(client_frame(true, CONTINUATION, &half));
+		assert!(rejected(&bytes));
+	}
+
+	// A reader which returns its chunks of bytes in order, and times out on each empty chunk.
+	struct Stalling(VecDeque<Vec<u8>>);
+
+	impl Read for Stalling {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			let Some(chunk) = self.0.front_mut() else { return Ok(0) };
+			if chunk.is_empty() {
+				self.0.pop_front();
+				return Err(io::ErrorKind::WouldBlock.into());
+			}
+			let len = buf.len().min(chunk.len());
+			buf[..len].copy_from_slice(&chunk[..len]);
+			chunk.drain(..len);
+			if chunk.is_empty() {
+				self.0.pop_front();
+			}
+			Ok(len)
+		}
+	}
+
+	#[test]
+	fn reads_are_only_retried_between_frames() {
+		// A timeout between the fragments of a message can be retried.
+		let mut frame = client_frame(true, CONTINUATION, b"llo");
+		let rest = frame.split_off(3);
+		let chunks = [client_frame(false, TEXT, b"he"), vec![], frame, rest];
+		let mut reader = MessageReader::from_client(Stalling(chunks.into()));
+		assert!(reader.read_message().is_err_and(|e| is_timeout(&e)));
+		assert_eq!(reader.read_message().unwrap(), Message::Text("hello".to_string()));
+
+		// But not in the middle of a frame.
+		let mut frame = client_frame(true, TEXT, b"hello");
+		let rest = frame.split_off(3);
+		let mut reader = MessageReader::from_client(Stalling([frame, vec![], rest].into()));
+		let result = reader.read_message();
+		assert!(result.is_err_and(|e| e.kind() == io::ErrorKind::InvalidData));
+	}
+}